@@ -0,0 +1,91 @@
+use crate::hex;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use crate::tls::Stream;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const NONCE_LEN: usize = 32;
+// A plain sys-botbase never sends a challenge, a client that never answers one is given up on
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+static SECRET: OnceLock<String> = OnceLock::new();
+
+pub fn configure(secret: String) {
+    if SECRET.set(secret).is_err() {
+        panic!("Authentication secret is already configured!");
+    }
+}
+
+// Read through the connection's own buffer, so what the peer sends right after stays in it
+fn read_line(stream: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    stream.read_line(&mut line)?;
+    Ok(line.trim().to_string())
+}
+
+fn denied(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, reason.to_string())
+}
+
+// Client side: answers the challenge of a relay when a secret is configured
+pub fn authenticate(stream: &mut BufReader<Stream>) -> io::Result<()> {
+    let Some(secret) = SECRET.get() else {
+        return Ok(());
+    };
+
+    stream.get_ref().tcp().set_read_timeout(Some(AUTH_TIMEOUT))?;
+    let answered = answer_challenge(stream, secret);
+    stream.get_ref().tcp().set_read_timeout(None)?;
+    answered
+}
+
+fn answer_challenge(stream: &mut BufReader<Stream>, secret: &str) -> io::Result<()> {
+    let challenge = read_line(stream).map_err(|err| match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            io::Error::new(io::ErrorKind::TimedOut, "peer did not answer the auth challenge, is it a relay with a secret?")
+        }
+        _ => err,
+    })?;
+    let nonce = challenge.strip_prefix("challenge ")
+        .and_then(hex::decode)
+        .ok_or_else(|| denied("expected an authentication challenge"))?;
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, &nonce);
+    stream.get_mut().write_all(format!("auth {}\n", hex::encode(tag.as_ref())).as_bytes())?;
+
+    match read_line(stream)?.as_str() {
+        "ok" => Ok(()),
+        _ => Err(denied("authentication rejected")),
+    }
+}
+
+// Server side: challenges a freshly accepted client and checks its answer
+pub fn verify_client(stream: &mut BufReader<TcpStream>, secret: &str) -> io::Result<()> {
+    stream.get_ref().set_read_timeout(Some(AUTH_TIMEOUT))?;
+    let verified = check_answer(stream, secret);
+    stream.get_ref().set_read_timeout(None)?;
+    verified
+}
+
+fn check_answer(stream: &mut BufReader<TcpStream>, secret: &str) -> io::Result<()> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).map_err(|_| io::Error::other("unable to generate a nonce"))?;
+    stream.get_mut().write_all(format!("challenge {}\n", hex::encode(&nonce)).as_bytes())?;
+
+    let answer = read_line(stream)?;
+    let tag = answer.strip_prefix("auth ")
+        .and_then(hex::decode)
+        .ok_or_else(|| denied("expected an authentication answer"))?;
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    if hmac::verify(&key, &nonce, &tag).is_err() {
+        let _ = stream.get_mut().write_all(b"denied\n");
+        return Err(denied("invalid authentication answer"));
+    }
+
+    stream.get_mut().write_all(b"ok\n")
+}
@@ -0,0 +1,57 @@
+use crate::command::Command;
+use crate::router::Router;
+use crate::shutdown;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_COUNT: usize = 100;
+pub const DEFAULT_INTERVAL: u64 = 10;
+
+// getVersion changes nothing on the console and every botbase answers it
+const ECHO: &str = "getVersion";
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    sorted[(sorted.len() * percent / 100).min(sorted.len() - 1)]
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
+
+// Times round trips to the first target, jitter being the average change between two consecutive ones
+pub fn run(router: &mut Router, count: usize, interval: Duration) {
+    println!("Sending {} {} requests to {}...", count, ECHO, router.describe());
+    let echo = Command::OTHER(ECHO.to_string());
+    let mut samples = Vec::with_capacity(count);
+    let mut failures = 0;
+    for _ in 0..count {
+        if shutdown::requested() {
+            break;
+        }
+        let start = Instant::now();
+        match router.request(&echo) {
+            Ok(_) => samples.push(start.elapsed()),
+            Err(err) if samples.is_empty() && failures == 0 => {
+                println!("The link doesn't answer requests ({}), nothing to measure", err);
+                return;
+            }
+            Err(_) => failures += 1,
+        }
+        shutdown::sleep(interval);
+    }
+    if samples.is_empty() {
+        println!("No answer received");
+        return;
+    }
+
+    let changes: Vec<Duration> = samples.windows(2).map(|pair| pair[0].abs_diff(pair[1])).collect();
+    let jitter = match changes.len() {
+        0 => Duration::ZERO,
+        length => changes.iter().sum::<Duration>() / length as u32,
+    };
+    let average = samples.iter().sum::<Duration>() / samples.len() as u32;
+    let mut sorted = samples;
+    sorted.sort();
+    println!("{} answers, {} failures", sorted.len(), failures);
+    println!("min {}, avg {}, p99 {}, max {}", millis(sorted[0]), millis(average), millis(percentile(&sorted, 99)), millis(sorted[sorted.len() - 1]));
+    println!("jitter {}", millis(jitter));
+}
@@ -0,0 +1,439 @@
+use crate::command::Command;
+use crate::health::SendOutcome;
+use crate::notify;
+use crate::hex;
+use crate::shutdown;
+use crate::transport::Transport;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const AF_BLUETOOTH: u16 = 31;
+const BTPROTO_L2CAP: i32 = 0;
+const SOCK_SEQPACKET: i32 = 5;
+const CONTROL_PSM: u16 = 17;
+const INTERRUPT_PSM: u16 = 19;
+
+const REPORT_INTERVAL: Duration = Duration::from_millis(15);
+// About 50ms of reports, long enough for the console to see a click
+const CLICK_REPORTS: u32 = 4;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+const INPUT_REPORT: u8 = 0xA1;
+const OUTPUT_REPORT: u8 = 0xA2;
+const REPORT_LENGTH: usize = 50;
+const FULL_BATTERY: u8 = 0x90;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BluetoothAddress([u8; 6]);
+
+impl FromStr for BluetoothAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(&s.replace(':', ""))
+            .filter(|bytes| bytes.len() == 6)
+            .ok_or_else(|| format!("Expected a Bluetooth address like AA:BB:CC:DD:EE:FF, got {}", s))?;
+        let mut address = [0u8; 6];
+        address.copy_from_slice(&bytes);
+        Ok(BluetoothAddress(address))
+    }
+}
+
+impl Display for BluetoothAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<String> = self.0.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{}", parts.join(":"))
+    }
+}
+
+// An empty address means pairing with whichever switch opens the Change Grip/Order menu
+pub fn parse_switch(address: &str) -> Result<Option<BluetoothAddress>, String> {
+    match address {
+        "" => Ok(None),
+        address => address.parse().map(Some),
+    }
+}
+
+// sockaddr_l2 isn't exposed by socket2, the layout is family, PSM, then the address in reverse byte order
+fn l2cap_address(address: BluetoothAddress, psm: u16) -> SockAddr {
+    let mut raw = [0u8; 14];
+    raw[0..2].copy_from_slice(&AF_BLUETOOTH.to_ne_bytes());
+    raw[2..4].copy_from_slice(&psm.to_le_bytes());
+    raw[4..10].iter_mut().zip(address.0.iter().rev()).for_each(|(dst, src)| *dst = *src);
+
+    let (_, address) = unsafe {
+        SockAddr::try_init(|storage, len| {
+            std::ptr::copy_nonoverlapping(raw.as_ptr(), storage.cast::<u8>(), raw.len());
+            *len = raw.len() as _;
+            Ok(())
+        })
+    }
+    .unwrap();
+    address
+}
+
+fn bluetooth_address(address: &SockAddr) -> BluetoothAddress {
+    let raw = unsafe { std::slice::from_raw_parts(address.as_ptr().cast::<u8>(), address.len() as usize) };
+    let mut bytes = [0u8; 6];
+    bytes.iter_mut().zip(raw[4..10].iter().rev()).for_each(|(dst, src)| *dst = *src);
+    BluetoothAddress(bytes)
+}
+
+fn l2cap_socket() -> io::Result<Socket> {
+    Socket::new(Domain::from(AF_BLUETOOTH as i32), Type::from(SOCK_SEQPACKET), Some(Protocol::from(BTPROTO_L2CAP)))
+}
+
+fn listen(psm: u16) -> io::Result<Socket> {
+    let socket = l2cap_socket()?;
+    socket.bind(&l2cap_address(BluetoothAddress([0; 6]), psm))?;
+    socket.listen(1)?;
+    Ok(socket)
+}
+
+fn connect(switch: BluetoothAddress, psm: u16) -> io::Result<Socket> {
+    let socket = l2cap_socket()?;
+    socket.connect(&l2cap_address(switch, psm))?;
+    Ok(socket)
+}
+
+// The console only shows controllers advertising themselves as a gamepad named Pro Controller
+fn prepare_adapter() {
+    for args in [&["hci0", "class", "0x002508"][..], &["hci0", "name", "Pro Controller"], &["hci0", "piscan"]] {
+        let ok = process::Command::new("hciconfig").args(args).status().is_ok_and(|status| status.success());
+        if !ok {
+            warn!("Unable to run hciconfig {}, make sure BlueZ is installed and you are root", args.join(" "));
+        }
+    }
+}
+
+fn button_bit(button: &str) -> Option<(usize, u8)> {
+    Some(match button {
+        "Y" => (0, 0x01),
+        "X" => (0, 0x02),
+        "B" => (0, 0x04),
+        "A" => (0, 0x08),
+        "R" => (0, 0x40),
+        "ZR" => (0, 0x80),
+        "MINUS" => (1, 0x01),
+        "PLUS" => (1, 0x02),
+        "RSTICK" => (1, 0x04),
+        "LSTICK" => (1, 0x08),
+        "HOME" => (1, 0x10),
+        "CAPTURE" => (1, 0x20),
+        "DDOWN" => (2, 0x01),
+        "DUP" => (2, 0x02),
+        "DRIGHT" => (2, 0x04),
+        "DLEFT" => (2, 0x08),
+        "L" => (2, 0x40),
+        "ZL" => (2, 0x80),
+        _ => return None,
+    })
+}
+
+// Sticks are two 12 bit values centered on 2048 packed in 3 bytes
+fn stick_bytes(value: (i32, i32)) -> [u8; 3] {
+    let x = ((value.0 + 0x8000) >> 4).clamp(0, 0xFFF) as u16;
+    let y = ((value.1 + 0x8000) >> 4).clamp(0, 0xFFF) as u16;
+    [(x & 0xFF) as u8, ((x >> 8) | ((y & 0xF) << 4)) as u8, (y >> 4) as u8]
+}
+
+// Factory calibration the console reads over SPI while pairing, unknown ranges read as erased flash
+fn spi_flash(address: u32, size: usize) -> Vec<u8> {
+    const RANGES: [(u32, &str); 5] = [
+        (0x6020, "d3ffd5ff55010040004000401900ddffdcff3b343b343b34"),
+        (0x603D, "baf5626fc877ed955b16d87df2b55f86655e"),
+        (0x6050, "323232ffffff"),
+        (0x6086, "0f30619630f3d41454411554c7799c333663"),
+        (0x6098, "0f30619630f3d41454411554c7799c333663"),
+    ];
+
+    (address..address + size as u32)
+        .map(|byte| {
+            RANGES.iter()
+                .find_map(|(start, data)| {
+                    let data = hex::decode(data)?;
+                    data.get(byte.checked_sub(*start)? as usize).copied()
+                })
+                .unwrap_or(0xFF)
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Inputs {
+    buttons: [u8; 3],
+    left_stick: (i32, i32),
+    right_stick: (i32, i32),
+    // Clicked buttons are let go by the report thread once enough reports carried them
+    clicked: [u8; 3],
+    click_reports: u32,
+}
+
+impl Inputs {
+    fn apply(&mut self, command: &Command) {
+        match command {
+            Command::CLICK(button) => {
+                if let Some((byte, bit)) = button_bit(button) {
+                    self.buttons[byte] |= bit;
+                    self.clicked[byte] |= bit;
+                    self.click_reports = CLICK_REPORTS;
+                }
+            }
+            // A press or release after a click decides the button from then on
+            Command::PRESS(button) => {
+                if let Some((byte, bit)) = button_bit(button) {
+                    self.buttons[byte] |= bit;
+                    self.clicked[byte] &= !bit;
+                }
+            }
+            Command::RELEASE(button) => {
+                if let Some((byte, bit)) = button_bit(button) {
+                    self.buttons[byte] &= !bit;
+                    self.clicked[byte] &= !bit;
+                }
+            }
+            Command::SETSTICK(stick, x, y) => match stick.as_ref() {
+                "LEFT" => self.left_stick = (*x, *y),
+                "RIGHT" => self.right_stick = (*x, *y),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    fn reported(&mut self) {
+        if self.click_reports == 0 {
+            return;
+        }
+        self.click_reports -= 1;
+        if self.click_reports == 0 {
+            self.buttons.iter_mut().zip(self.clicked).for_each(|(byte, bits)| *byte &= !bits);
+            self.clicked = [0; 3];
+        }
+    }
+
+    fn report(&self, id: u8, timer: u8) -> Vec<u8> {
+        let mut report = vec![INPUT_REPORT, id, timer, FULL_BATTERY];
+        report.extend_from_slice(&self.buttons);
+        report.extend_from_slice(&stick_bytes(self.left_stick));
+        report.extend_from_slice(&stick_bytes(self.right_stick));
+        report.push(0x00);
+        report
+    }
+}
+
+struct Session {
+    control: Socket,
+    interrupt: Arc<Socket>,
+    connected: Arc<AtomicBool>,
+}
+
+fn subcommand_reply(inputs: &Inputs, timer: u8, controller: BluetoothAddress, request: &[u8]) -> Vec<u8> {
+    let subcommand = request.get(11).copied().unwrap_or_default();
+    let args = request.get(12..).unwrap_or_default();
+    let (ack, data) = match subcommand {
+        // Device info: firmware 3.8B, Pro Controller, our address, colors from SPI
+        0x02 => {
+            let mut data = vec![0x03, 0x8B, 0x03, 0x02];
+            data.extend_from_slice(&controller.0);
+            data.extend_from_slice(&[0x01, 0x01]);
+            (0x82, data)
+        }
+        0x10 if args.len() >= 5 => {
+            let address = u32::from_le_bytes([args[0], args[1], args[2], args[3]]);
+            let mut data = args[..5].to_vec();
+            data.extend(spi_flash(address, args[4] as usize));
+            (0x90, data)
+        }
+        0x04 => (0x83, Vec::new()),
+        0x21 => (0xA0, vec![0x01, 0x00, 0xFF, 0x00, 0x08, 0x00, 0x1B, 0x01]),
+        _ => (0x80, Vec::new()),
+    };
+
+    let mut reply = inputs.report(0x21, timer);
+    reply.push(ack);
+    reply.push(subcommand);
+    reply.extend(data);
+    reply.resize(REPORT_LENGTH, 0);
+    reply
+}
+
+// Answers the console's subcommands and streams full input reports once it asks for them
+fn run(interrupt: Arc<Socket>, inputs: Arc<Mutex<Inputs>>, connected: Arc<AtomicBool>) {
+    let controller = interrupt.local_addr().map(|address| bluetooth_address(&address)).unwrap_or(BluetoothAddress([0; 6]));
+    let mut streaming = false;
+    let mut timer: u8 = 0;
+    let mut buf = [0u8; 64];
+    let _ = interrupt.set_read_timeout(Some(REPORT_INTERVAL));
+
+    while connected.load(Ordering::Relaxed) {
+        let start = Instant::now();
+        timer = timer.wrapping_add(1);
+
+        let reply = match (&*interrupt).read(&mut buf) {
+            Ok(0) => break,
+            Ok(size) if size > 11 && buf[0] == OUTPUT_REPORT && buf[1] == 0x01 => {
+                let inputs = inputs.lock().unwrap();
+                // Player lights are the last thing set while pairing
+                if buf[11] == 0x30 && !streaming {
+                    info!("Switch accepted the controller");
+                }
+                streaming |= buf[11] == 0x03 || buf[11] == 0x30;
+                Some(subcommand_reply(&inputs, timer, controller, &buf[..size]))
+            }
+            Ok(_) => None,
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => None,
+            Err(_) => break,
+        };
+
+        let report = reply.or_else(|| {
+            streaming.then(|| {
+                let mut report = inputs.lock().unwrap().report(0x30, timer);
+                report.resize(REPORT_LENGTH, 0);
+                report
+            })
+        });
+        if let Some(report) = report {
+            if (&*interrupt).write_all(&report).is_err() {
+                break;
+            }
+            inputs.lock().unwrap().reported();
+        }
+        thread::sleep(REPORT_INTERVAL.saturating_sub(start.elapsed()));
+    }
+    connected.store(false, Ordering::Relaxed);
+}
+
+pub struct Bluetooth {
+    switch: BluetoothAddress,
+    inputs: Arc<Mutex<Inputs>>,
+    session: Option<Session>,
+}
+
+impl Bluetooth {
+    fn start(control: Socket, interrupt: Socket, inputs: &Arc<Mutex<Inputs>>) -> Session {
+        let interrupt = Arc::new(interrupt);
+        let connected = Arc::new(AtomicBool::new(true));
+        let session = Session { control, interrupt: interrupt.clone(), connected: connected.clone() };
+        let inputs = inputs.clone();
+        thread::spawn(move || run(interrupt, inputs, connected));
+        session
+    }
+
+    pub fn pair() -> io::Result<Bluetooth> {
+        prepare_adapter();
+        let control_listener = listen(CONTROL_PSM)?;
+        let interrupt_listener = listen(INTERRUPT_PSM)?;
+        println!("Open Controllers > Change Grip/Order on the switch to pair");
+
+        let (control, address) = control_listener.accept()?;
+        let (interrupt, _) = interrupt_listener.accept()?;
+        let switch = bluetooth_address(&address);
+        println!("Paired with switch {}, use bluetooth:{} to reconnect without pairing again", switch, switch);
+
+        let inputs = Arc::new(Mutex::new(Inputs::default()));
+        let session = Bluetooth::start(control, interrupt, &inputs);
+        Ok(Bluetooth { switch, inputs, session: Some(session) })
+    }
+
+    pub fn reconnect(switch: BluetoothAddress) -> io::Result<Bluetooth> {
+        let inputs = Arc::new(Mutex::new(Inputs::default()));
+        let control = connect(switch, CONTROL_PSM)?;
+        let interrupt = connect(switch, INTERRUPT_PSM)?;
+        let session = Bluetooth::start(control, interrupt, &inputs);
+        Ok(Bluetooth { switch, inputs, session: Some(session) })
+    }
+
+    fn is_connected(&self) -> bool {
+        self.session.as_ref().is_some_and(|session| session.connected.load(Ordering::Relaxed))
+    }
+
+    // Returns true when the link had to be brought back, gives up after a while or when stopping
+    // so the next send tries again rather than hanging here
+    fn ensure_connected(&mut self) -> Result<bool, String> {
+        if self.is_connected() {
+            return Ok(false);
+        }
+
+        if self.session.take().is_some() {
+            warn!("Lost Bluetooth connection to switch {}, reconnecting...", self.switch);
+            notify::send(notify::SWITCH_LOST, &format!("Lost the Bluetooth connection to {}, reconnecting", self.switch));
+        }
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match connect(self.switch, CONTROL_PSM).and_then(|control| Ok((control, connect(self.switch, INTERRUPT_PSM)?))) {
+                Ok((control, interrupt)) => {
+                    self.session = Some(Bluetooth::start(control, interrupt, &self.inputs));
+                    info!("Reconnected to switch!");
+                    notify::send(notify::SWITCH_BACK, &format!("Reconnected to {} over Bluetooth", self.switch));
+                    return Ok(true);
+                }
+                Err(err) if attempts >= MAX_RECONNECT_ATTEMPTS || shutdown::requested() => {
+                    return Err(format!("Unable to reach switch {} ({})", self.switch, err));
+                }
+                Err(err) => {
+                    warn!("Unable to reach switch {} ({}), retrying...", self.switch, err);
+                    shutdown::sleep(RECONNECT_INTERVAL);
+                }
+            }
+        }
+    }
+}
+
+impl Transport for Bluetooth {
+    fn describe(&self) -> String {
+        format!("Bluetooth switch {}", self.switch)
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.ensure_connected().map(|_| ())
+    }
+
+    // The report thread carries the inputs, so a send only fails while the link is down
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        let mut outcome = self.heartbeat();
+        if self.try_send_commands(commands).is_err() {
+            outcome.failures += 1;
+        }
+        outcome
+    }
+
+    fn try_send_commands(&mut self, commands: &[Command]) -> Result<(), String> {
+        if !self.is_connected() {
+            return Err("Bluetooth link is down".to_string());
+        }
+
+        let mut inputs = self.inputs.lock().unwrap();
+        commands.iter().for_each(|command| inputs.apply(command));
+        Ok(())
+    }
+
+    fn heartbeat(&mut self) -> SendOutcome {
+        match self.ensure_connected() {
+            Ok(true) => SendOutcome { failures: 1, reconnects: 1, ..SendOutcome::default() },
+            Ok(false) => SendOutcome::default(),
+            Err(err) => {
+                warn!("{}, trying again on the next send", err);
+                SendOutcome { failures: 1, ..SendOutcome::default() }
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.connected.store(false, Ordering::Relaxed);
+        let _ = self.interrupt.shutdown(std::net::Shutdown::Both);
+        let _ = self.control.shutdown(std::net::Shutdown::Both);
+    }
+}
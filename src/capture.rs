@@ -0,0 +1,321 @@
+use crate::command::Command;
+use crate::config::Source;
+use crate::error::Error;
+use crate::macros;
+use crate::memory::{self, Location, Region};
+use crate::nxtas;
+use crate::router::Router;
+use crate::sequence::{self, Step};
+use crate::shutdown;
+use crate::watch::Condition;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// One JSON object per line, timestamps are microseconds since the capture started
+#[derive(Serialize, Deserialize)]
+pub struct Record {
+    pub micros: u64,
+    pub target: String,
+    pub command: String,
+}
+
+pub struct Capture {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl Capture {
+    pub fn create(path: &Path) -> io::Result<Capture> {
+        let writer = BufWriter::new(File::create(path)?);
+        Ok(Capture { writer, start: Instant::now() })
+    }
+
+    pub fn record(&mut self, target: &str, commands: &[Command]) {
+        let micros = self.start.elapsed().as_micros() as u64;
+        let written = commands.iter().try_for_each(|command| {
+            let record = Record { micros, target: target.to_string(), command: command.to_string() };
+            serde_json::to_writer(&mut self.writer, &record)?;
+            self.writer.write_all(b"\n")
+        });
+
+        // Flushed every batch so the capture survives the process being killed
+        if let Err(err) = written.and_then(|_| self.writer.flush()) {
+            warn!("Unable to write the capture ({})", err);
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<Record>, Error> {
+    let invalid = |reason: String| Error::LOAD { path: path.to_path_buf(), reason };
+    let content = fs::read_to_string(path).map_err(|err| invalid(err.to_string()))?;
+    content.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|err| invalid(format!("invalid record on line {}: {}", index + 1, err)))
+        })
+        .collect()
+}
+
+// What a macro plays: batches sent a delay after the previous one, and branches on game memory
+// which hand-written macros use to react to the game
+#[derive(Clone, Debug)]
+pub enum Instruction {
+    BATCH(Duration, Vec<Command>),
+    // Goes on when the value matches, jumps to the index otherwise
+    BRANCH(Location, usize, Condition, usize),
+    JUMP(usize),
+}
+
+// nx-TAS scripts are plain text files, .macro ones are written by hand, anything else is a capture
+pub fn load_macro(path: &Path) -> Result<Vec<Instruction>, Error> {
+    let records = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("txt") => nxtas::load(path)?,
+        Some("macro") => return macros::load(path),
+        _ => load(path)?,
+    };
+    instructions(&records).map_err(|reason| Error::LOAD { path: path.to_path_buf(), reason })
+}
+
+// Commands broadcast to several targets were captured once per target, the router
+// of the replaying session decides again where each of them goes
+pub fn instructions(records: &[Record]) -> Result<Vec<Instruction>, String> {
+    let mut batches: Vec<(u64, Vec<Command>)> = Vec::new();
+    for record in records {
+        let command: Command = record.command.parse()
+            .map_err(|err| format!("invalid command {}: {}", record.command, err))?;
+        match batches.last_mut() {
+            Some((micros, commands)) if *micros == record.micros => {
+                if !commands.contains(&command) {
+                    commands.push(command);
+                }
+            }
+            _ => batches.push((record.micros, vec![command])),
+        }
+    }
+
+    let mut previous = 0;
+    Ok(batches.into_iter()
+        .map(|(micros, commands)| {
+            let delay = Duration::from_micros(micros.saturating_sub(previous));
+            previous = micros;
+            Instruction::BATCH(delay, commands)
+        })
+        .collect())
+}
+
+// Number of instructions from the start that are a lone click each
+fn click_run(instructions: &[Instruction]) -> usize {
+    instructions.iter()
+        .take_while(|instruction| matches!(instruction, Instruction::BATCH(_, commands) if matches!(commands.as_slice(), [Command::CLICK(_)])))
+        .count()
+}
+
+// The delay of the first click was already waited for, the others become waits of the sequence
+fn click_seq(run: &[Instruction], speed: f64) -> (Command, Duration) {
+    let mut steps = Vec::new();
+    let mut length = Duration::ZERO;
+    for (index, instruction) in run.iter().enumerate() {
+        if let Instruction::BATCH(delay, commands) = instruction {
+            if index > 0 {
+                let delay = delay.div_f64(speed);
+                steps.push(Step::WAIT(delay.as_millis() as u64));
+                length += delay;
+            }
+            if let [Command::CLICK(button)] = commands.as_slice() {
+                steps.push(Step::CLICK(button.clone()));
+            }
+        }
+    }
+    (sequence::click_seq(&steps), length)
+}
+
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 4.0;
+
+// A multiplier of the playback speed like 0.5 or 2, the delays get divided by it
+pub fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s.trim_end_matches(['x', 'X']).parse().map_err(|_| format!("Invalid speed {}", s))?;
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(format!("The speed must be between {}x and {}x", MIN_SPEED, MAX_SPEED));
+    }
+    Ok(speed)
+}
+
+// Sleeping is only precise to a millisecond or so, the end of each wait spins instead
+const SPIN: Duration = Duration::from_millis(2);
+
+fn wait_until(deadline: Instant) {
+    if let Some(wait) = deadline.checked_duration_since(Instant::now() + SPIN) {
+        shutdown::sleep(wait);
+    }
+    while Instant::now() < deadline && !shutdown::requested() {
+        std::hint::spin_loop();
+    }
+}
+
+// A value that can't be read counts as not matching
+fn branch_taken(router: &mut Router, location: &Location, size: usize, condition: &Condition) -> bool {
+    match memory::peek_at(router, Region::HEAP, location, size) {
+        Ok(bytes) => condition.matches(&bytes),
+        Err(err) => {
+            warn!("Unable to read {} for the macro ({})", location, err);
+            false
+        }
+    }
+}
+
+// Sends batches once they are due, so a playback can run between other work. Delays count
+// from when the previous batch was due, so late sends don't add up over a long macro
+pub struct Playback {
+    instructions: Vec<Instruction>,
+    index: usize,
+    clock: Instant,
+    sequences: bool,
+    speed: f64,
+    paused: Option<Instant>,
+}
+
+impl Playback {
+    pub fn new(instructions: &[Instruction], router: &Router, speed: f64) -> Playback {
+        let commands: usize = instructions.iter()
+            .map(|instruction| match instruction {
+                Instruction::BATCH(_, commands) => commands.len(),
+                _ => 0,
+            })
+            .sum();
+        match speed {
+            1.0 => info!("Replaying {} commands", commands),
+            _ => info!("Replaying {} commands at {}x", commands, speed),
+        }
+        Playback { instructions: instructions.to_vec(), index: 0, clock: Instant::now(), sequences: router.runs_sequences(), speed, paused: None }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.index >= self.instructions.len()
+    }
+
+    // Branches and jumps take no time, they are due right away
+    fn next_due(&self) -> Option<Instant> {
+        match self.instructions.get(self.index)? {
+            Instruction::BATCH(delay, _) => Some(self.clock + delay.div_f64(self.speed)),
+            _ => Some(self.clock),
+        }
+    }
+
+    // When poll has something to send next, never while paused
+    pub fn wakeup(&self) -> Option<Instant> {
+        self.next_due().filter(|_| self.paused.is_none())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    pub fn pause(&mut self) {
+        if self.paused.is_none() {
+            self.paused = Some(Instant::now());
+            info!("Replay paused at instruction {}/{}", self.index, self.instructions.len());
+        }
+    }
+
+    // The time spent paused is skipped so the rest keeps its timing
+    pub fn resume(&mut self) {
+        if let Some(paused) = self.paused.take() {
+            self.clock += paused.elapsed();
+            info!("Replay resumed");
+        }
+    }
+
+    // Sends the next batch right away, leaving the playback paused after it
+    pub fn step(&mut self, router: &mut Router) {
+        self.pause();
+        while !self.is_finished() && !self.advance(router, Instant::now()) {}
+        self.paused = Some(Instant::now());
+        info!("Stepped to instruction {}/{}", self.index, self.instructions.len());
+    }
+
+    pub fn poll(&mut self, router: &mut Router) {
+        while let Some(due) = self.next_due().filter(|due| self.paused.is_none() && *due <= Instant::now()) {
+            self.advance(router, due);
+        }
+    }
+
+    // Runs the next instruction as if it was due then, telling whether it sent anything
+    fn advance(&mut self, router: &mut Router, due: Instant) -> bool {
+        match &self.instructions[self.index] {
+            Instruction::BATCH(_, commands) => {
+                self.clock = due;
+                let instructions = &self.instructions[self.index..];
+                let run = if self.sequences && self.paused.is_none() { click_run(instructions) } else { 0 };
+                if run >= 2 {
+                    let (command, length) = click_seq(&instructions[..run], self.speed);
+                    router.send(Source::MACRO, 0, vec![command]);
+                    self.clock += length;
+                    self.index += run;
+                } else {
+                    if !commands.is_empty() {
+                        router.send(Source::MACRO, 0, commands.clone());
+                    }
+                    self.index += 1;
+                }
+                true
+            }
+            Instruction::BRANCH(location, size, condition, otherwise) => {
+                self.index = match branch_taken(router, location, *size, condition) {
+                    true => self.index + 1,
+                    false => *otherwise,
+                };
+                false
+            }
+            Instruction::JUMP(target) => {
+                self.index = *target;
+                false
+            }
+        }
+    }
+}
+
+pub fn play(instructions: &[Instruction], router: &mut Router) {
+    play_at(instructions, router, 1.0);
+}
+
+pub fn play_at(instructions: &[Instruction], router: &mut Router, speed: f64) {
+    router.count_run("replay");
+    let mut playback = Playback::new(instructions, router, speed);
+    while let Some(due) = playback.next_due() {
+        wait_until(due);
+        if shutdown::requested() {
+            info!("Replay stopped");
+            return;
+        }
+        playback.poll(router);
+    }
+    info!("Replay finished");
+}
+
+// Plays the macro repeat times, or forever without a count, the link is kept alive during the delays
+pub fn play_looped(instructions: &[Instruction], router: &mut Router, repeat: Option<u32>, delay: Duration, speed: f64) {
+    let mut iteration = 0;
+    while repeat.is_none_or(|repeat| iteration < repeat) && !shutdown::requested() {
+        if iteration > 0 {
+            let resume = Instant::now() + delay;
+            while let Some(wait) = resume.checked_duration_since(Instant::now()).filter(|_| !shutdown::requested()) {
+                router.heartbeat();
+                thread::sleep(wait.min(Duration::from_millis(100)));
+            }
+        }
+        iteration += 1;
+        match repeat {
+            Some(1) => {}
+            Some(repeat) => info!("Iteration {}/{}", iteration, repeat),
+            None => info!("Iteration {}", iteration),
+        }
+        play_at(instructions, router, speed);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::error::Error;
+use crate::freeze::Freezes;
+use crate::memory::{self, Bases, Location, Region};
+use crate::router::Router;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    FREEZE,
+    ONCE,
+}
+
+// Addresses are heap offsets, pointer chains start from main like everywhere else
+#[derive(Deserialize, Clone, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub location: Location,
+    pub value: String,
+    #[serde(default = "default_mode")]
+    pub mode: Mode,
+}
+
+fn default_mode() -> Mode {
+    Mode::ONCE
+}
+
+#[derive(Deserialize)]
+struct CheatFile {
+    #[serde(default)]
+    cheats: Vec<Cheat>,
+}
+
+pub fn load(path: &Path) -> Result<Vec<Cheat>, Error> {
+    let invalid = |reason: String| Error::LOAD { path: path.to_path_buf(), reason };
+    let content = fs::read_to_string(path).map_err(|err| invalid(err.to_string()))?;
+    let file: CheatFile = toml::from_str(&content).map_err(|err| invalid(err.to_string()))?;
+    for cheat in &file.cheats {
+        memory::parse_data(&cheat.value).map_err(|err| invalid(format!("invalid cheat {}: {}", cheat.name, err)))?;
+    }
+    Ok(file.cheats)
+}
+
+pub fn find<'a>(cheats: &'a [Cheat], name: &str) -> Option<&'a Cheat> {
+    cheats.iter().find(|cheat| cheat.name.eq_ignore_ascii_case(name))
+}
+
+pub fn list(cheats: &[Cheat]) {
+    for cheat in cheats {
+        let mode = match cheat.mode {
+            Mode::FREEZE => "freeze",
+            Mode::ONCE => "once",
+        };
+        println!("{}: {} = {} ({})", cheat.name, cheat.location, cheat.value, mode);
+    }
+}
+
+// sys-botbase only freezes heap offsets, so a pointer chain is followed to where it ends now
+fn heap_offset(router: &mut Router, bases: &mut Bases, location: &Location) -> Result<u64, String> {
+    match location {
+        Location::ADDRESS(offset) => Ok(*offset),
+        Location::POINTER(chain) => {
+            let address = memory::resolve(router, chain)?;
+            match bases.relative(router, address)? {
+                (Region::HEAP, offset) => Ok(offset),
+                _ => Err(format!("{} doesn't end in the heap, it can't be frozen", chain)),
+            }
+        }
+    }
+}
+
+pub fn apply(router: &mut Router, bases: &mut Bases, freezes: &mut Freezes, cheat: &Cheat) -> Result<(), String> {
+    let value = memory::parse_data(&cheat.value)?;
+    match cheat.mode {
+        Mode::ONCE => memory::poke_at(router, Region::HEAP, &cheat.location, &value),
+        Mode::FREEZE => {
+            let offset = heap_offset(router, bases, &cheat.location)?;
+            freezes.freeze_at(router, offset, &value);
+        }
+    }
+    info!("Applied {}", cheat.name);
+    Ok(())
+}
+
+pub fn remove(router: &mut Router, bases: &mut Bases, freezes: &mut Freezes, cheat: &Cheat) -> Result<(), String> {
+    if cheat.mode == Mode::ONCE {
+        return Err(format!("{} is written once, there is nothing to undo", cheat.name));
+    }
+    let offset = heap_offset(router, bases, &cheat.location)?;
+    freezes.unfreeze_at(router, offset);
+    info!("Removed {}", cheat.name);
+    Ok(())
+}
@@ -0,0 +1,498 @@
+use switch_usb_controld::bench;
+use switch_usb_controld::capture;
+use switch_usb_controld::config::{default_watch_interval, ControllerType, TimingSettings};
+use switch_usb_controld::connection::{ConnectionType, UsbDevice};
+use switch_usb_controld::dateskip;
+use switch_usb_controld::dump;
+use switch_usb_controld::library;
+use switch_usb_controld::mash;
+use switch_usb_controld::memory::{self, Location, PointerChain, Region};
+use switch_usb_controld::scan::{self, Filter};
+use switch_usb_controld::transport::Target;
+use switch_usb_controld::watch::Condition;
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(version, about = "Forward a gamepad to a Nintendo Switch running sys-botbase")]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Connection to use, asked interactively when omitted
+    #[arg(long, value_enum)]
+    pub connection: Option<ConnectionType>,
+
+    /// Address (ip:port) of the switch for internet, failover and redundant connections
+    #[arg(long)]
+    pub address: Option<SocketAddr>,
+
+    /// Serial number (or index) of the USB switch to use when several are plugged in
+    #[arg(long)]
+    pub serial: Option<UsbDevice>,
+
+    /// Serial port of the microcontroller for serial connections
+    #[arg(long)]
+    pub port: Option<String>,
+
+    /// Baud rate of the serial port
+    #[arg(long, default_value_t = switch_usb_controld::serial::DEFAULT_BAUD)]
+    pub baud: u32,
+
+    /// Switch to mirror inputs to (usb, usb:<index or serial>, serial:<port>[@baud], hidplus:<ip>[:port], bluetooth[:<switch address>], gadget, dry-run or ip:port), can be repeated
+    #[arg(long = "target")]
+    pub targets: Vec<Target>,
+
+    /// Wrap TCP connections in TLS, verifying the certificate against this server name
+    #[arg(long = "tls", value_name = "SERVER_NAME")]
+    pub tls_server_name: Option<String>,
+
+    /// Shared secret used to authenticate with a relay (or required from relay clients when serving)
+    #[arg(long)]
+    pub secret: Option<String>,
+
+    /// Kind of controller sys-botbase emulates, overrides the one of the config file
+    #[arg(long, value_enum)]
+    pub controller: Option<ControllerType>,
+
+    #[command(flatten)]
+    pub timing: TimingSettings,
+
+    /// Log and validate the packets instead of sending them to a switch
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Record every outgoing command with its timestamp to this JSONL file
+    #[arg(long)]
+    pub capture: Option<PathBuf>,
+
+    /// Log every raw gamepad event and forwarded command with a monotonic timestamp to this file,
+    /// as CSV when it ends in .csv and JSONL otherwise
+    #[arg(long)]
+    pub input_log: Option<PathBuf>,
+
+    /// Rhai script whose on_connect, on_tick, on_title_change and on_button hooks run while forwarding the gamepad
+    #[cfg(feature = "scripting")]
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+
+    /// Show a live dashboard of the inputs sent, the links and what is running while forwarding the gamepad,
+    /// the log goes to its bottom
+    #[cfg(feature = "tui")]
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Show a tray icon with the state of the link and the profile while forwarding the gamepad,
+    /// its menu pauses forwarding, switches to another config file of the same directory and quits
+    #[cfg(feature = "tray")]
+    #[arg(long)]
+    pub tray: bool,
+
+    /// Configuration file with named targets and routing rules
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Send a tiny stick wiggle after this many minutes without input so the console stays awake,
+    /// overrides the one of the config file
+    #[arg(long, value_name = "MINUTES")]
+    pub anti_idle: Option<u64>,
+
+    /// Run the sending thread at real-time priority, pinned to its core, for steadier timing
+    /// during TAS playback (Linux only, needs CAP_SYS_NICE)
+    #[arg(long)]
+    pub realtime: bool,
+
+    /// Log debug events, twice to log trace events too
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Also append the log to this file
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// Serve Prometheus metrics (packets, send latency, reconnects) at http://<address>/metrics
+    /// while the tool runs
+    #[arg(long, value_name = "ADDRESS")]
+    pub metrics: Option<SocketAddr>,
+
+    /// Broadcast the buttons and sticks sent to each switch as JSON over a WebSocket at ws://<address>,
+    /// for stream overlays
+    #[arg(long, value_name = "ADDRESS")]
+    pub overlay: Option<SocketAddr>,
+
+    /// Show a desktop notification when a switch is lost or comes back and when the gamepad is disconnected
+    #[arg(long)]
+    pub notify: bool,
+
+    /// Write a usage report of the session to this JSON file when the tool stops: presses per button,
+    /// stick heatmaps, commands per source and how many times macros ran
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+
+    /// Leave the virtual controller attached when the tool stops
+    #[arg(long)]
+    pub stay_attached: bool,
+
+    /// Hex title ID to wait for before anything is sent, overrides the one of the config file
+    #[arg(long, value_parser = memory::parse_address)]
+    pub wait_for_title: Option<u64>,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Accept inputs from relay clients and forward them to the switch
+    Serve {
+        /// Address to listen on for relay clients
+        #[arg(long, default_value = switch_usb_controld::relay::DEFAULT_LISTEN)]
+        listen: SocketAddr,
+
+        /// Also accept one player over a peer-to-peer WebRTC link, exchanging offer and answer by copy-paste
+        #[cfg(feature = "webrtc")]
+        #[arg(long)]
+        webrtc: bool,
+    },
+    /// Block until a title runs, for scripts started before their game
+    WaitFor {
+        /// Hex title ID
+        #[arg(value_parser = memory::parse_address)]
+        title: u64,
+    },
+    /// Replay a capture, a recorded macro or an nx-TAS script with its original timing
+    Play {
+        /// Capture file to replay, a hand-written macro when it ends in .macro, or an nx-TAS script when it ends in .txt
+        capture: PathBuf,
+
+        /// Number of times to play it
+        #[arg(long = "loop", default_value_t = 1)]
+        repeat: u32,
+
+        /// Play it until the tool is stopped
+        #[arg(long, conflicts_with = "repeat")]
+        loop_forever: bool,
+
+        /// Milliseconds to wait between two plays
+        #[arg(long, default_value_t = 0)]
+        loop_delay: u64,
+
+        /// Speed multiplier from 0.25 to 4, the recorded delays are divided by it
+        #[arg(long, default_value_t = 1.0, value_parser = capture::parse_speed)]
+        speed: f64,
+    },
+    /// Run a built-in automation like egg-hatch, asking for its settings, or pick one from a list
+    Template {
+        /// One of egg-hatch, mash, soft-reset and walk
+        name: Option<String>,
+
+        /// Write the automation to this .macro file to tweak it instead of running it
+        #[arg(long)]
+        save: Option<PathBuf>,
+    },
+    /// Move the date forward one day at a time through System Settings, e.g. for den farming
+    DateSkip {
+        /// Days to skip, asked for when omitted
+        count: Option<u32>,
+
+        /// Firmware of the switch, which decides where the menus are and how long they take, asked for when omitted
+        #[arg(long, value_enum)]
+        firmware: Option<dateskip::Profile>,
+    },
+    /// Run a Rhai script with press, release, click, stick, wait, send, query, peek, peek_int, poke, pixel,
+    /// pixel_matches, wait_until_pixel, find_template, wait_until_template and screenshot
+    #[cfg(feature = "scripting")]
+    Script {
+        file: PathBuf,
+    },
+    /// Click a button over and over until stopped
+    Mash {
+        #[arg(default_value = "A")]
+        button: String,
+
+        /// Clicks per second
+        #[arg(long, default_value_t = mash::DEFAULT_RATE)]
+        rate: f64,
+
+        /// Stop after this many clicks
+        #[arg(long)]
+        count: Option<u64>,
+    },
+    /// Convert a capture or recorded macro to an nx-TAS script, without connecting to a switch
+    Export {
+        /// Capture file to convert
+        capture: PathBuf,
+
+        /// nx-TAS script to write
+        script: PathBuf,
+    },
+    /// Manage a directory of macros, or share some as a single archive file, without connecting to a switch
+    Macro {
+        /// Directory holding the macros
+        #[arg(long, default_value = library::DEFAULT_DIR)]
+        dir: PathBuf,
+
+        #[command(subcommand)]
+        action: MacroAction,
+    },
+    /// Send one sys-botbase command (e.g. getTitleID or "peek 0x1000 8") and print its response
+    Query {
+        /// Command to send
+        command: String,
+    },
+    /// Read memory of the running game
+    Peek {
+        /// Hex address relative to the region, or a pointer chain like [[main+A]+B]+C
+        location: Location,
+
+        /// Number of bytes to read
+        size: usize,
+
+        /// What the address is relative to
+        #[arg(long, value_enum, default_value = "heap")]
+        region: Region,
+    },
+    /// Save a memory range of the running game to a file, reading it chunk by chunk
+    Dump {
+        /// What the offset is relative to
+        #[arg(value_enum)]
+        region: Region,
+
+        /// Hex offset in the region
+        #[arg(value_parser = memory::parse_address)]
+        offset: u64,
+
+        /// Number of bytes to dump, decimal or 0x prefixed hex
+        #[arg(value_parser = dump::parse_size)]
+        size: usize,
+
+        /// File to write, an unfinished one is resumed
+        file: PathBuf,
+
+        /// Bytes read by each peek
+        #[arg(long, default_value_t = dump::DEFAULT_CHUNK, value_parser = dump::parse_size)]
+        chunk: usize,
+    },
+    /// Write memory of the running game
+    Poke {
+        /// Hex address relative to the region, or a pointer chain like [[main+A]+B]+C
+        location: Location,
+
+        /// Hex bytes to write, in memory order
+        data: String,
+
+        /// What the address is relative to
+        #[arg(long, value_enum, default_value = "heap")]
+        region: Region,
+    },
+    /// Print the absolute address a pointer chain like [[main+A]+B]+C ends at
+    Pointer {
+        chain: PointerChain,
+    },
+    /// Look for the address of a value by scanning memory then narrowing the results down
+    Scan {
+        #[command(subcommand)]
+        step: ScanStep,
+    },
+    /// Apply cheats of a cheat file by name, or list them when no name is given
+    Cheat {
+        /// TOML file with [[cheats]] entries of a name, location, hex value and mode (freeze or once)
+        file: PathBuf,
+
+        names: Vec<String>,
+
+        /// Stop the named freeze cheats instead
+        #[arg(long)]
+        off: bool,
+    },
+    /// Keep rewriting a value, given by its name in the config or as a heap address and hex value
+    Freeze {
+        target: String,
+        value: Option<String>,
+    },
+    /// Stop freezing a value, given by its name in the config or its heap address
+    Unfreeze {
+        #[arg(required_unless_present = "all")]
+        target: Option<String>,
+
+        /// Stop every freeze on the switch
+        #[arg(long)]
+        all: bool,
+    },
+    /// List the freezes of the config and count the ones active on the switch
+    Freezes,
+    /// Log changes of the watches of the config, or of a single location, as they get polled
+    Watch {
+        /// Hex heap address, or a pointer chain like [[main+A]+B]+C
+        #[arg(requires = "size")]
+        location: Option<Location>,
+
+        /// Number of bytes to read
+        size: Option<usize>,
+
+        /// Milliseconds between two reads
+        #[arg(long, default_value_t = default_watch_interval())]
+        interval: u64,
+
+        /// Condition on the value read as a little endian integer, like "== 0" or ">= 0x10"
+        #[arg(long)]
+        when: Option<Condition>,
+
+        /// Capture to play each time the condition starts matching
+        #[arg(long, requires = "when")]
+        play: Option<PathBuf>,
+    },
+    /// Print the running title, its build, the system language, the sys-botbase version and the memory bases
+    Info,
+    /// Time echo requests to the switch and print their latency and jitter, to compare links and settings
+    Bench {
+        /// Number of requests
+        #[arg(long, default_value_t = bench::DEFAULT_COUNT)]
+        count: usize,
+
+        /// Milliseconds between two requests
+        #[arg(long, default_value_t = bench::DEFAULT_INTERVAL)]
+        interval: u64,
+    },
+    /// Save what the switch displays to a timestamped JPEG file
+    Screenshot {
+        /// Directory to save the screenshot in
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Pick a switch, edit the button layout, watch what is sent and run macros from a window
+    #[cfg(feature = "gui")]
+    Gui,
+    /// Show what the switch displays in a window, refreshed with pixelPeek
+    #[cfg(feature = "preview")]
+    Preview {
+        /// Keep forwarding the gamepad while the window is open
+        #[arg(long)]
+        forward: bool,
+    },
+    /// Turn the switch display off, inputs keep working
+    ScreenOff,
+    /// Turn the switch display back on
+    ScreenOn,
+    /// Remove the virtual controller from the switch
+    Detach,
+    /// Attach the virtual controller again without pressing anything
+    Attach,
+    /// Type text into the on-screen keyboard of the switch
+    Type {
+        /// Text to type
+        #[arg(required_unless_present = "clipboard")]
+        text: Option<String>,
+
+        /// Type the content of this computer's clipboard instead
+        #[arg(long)]
+        clipboard: bool,
+    },
+    /// Type raw sys-botbase commands and print their decoded responses
+    Repl {
+        /// Keep forwarding the gamepad while the prompt is open
+        #[arg(long)]
+        forward: bool,
+    },
+    /// Forward this machine's gamepad to a relay server
+    Join {
+        /// Host (and optional port) of the relay server
+        #[cfg_attr(feature = "webrtc", arg(required_unless_present = "webrtc"))]
+        #[cfg_attr(not(feature = "webrtc"), arg(required = true))]
+        host: Option<String>,
+
+        /// Reach the relay over a peer-to-peer WebRTC link instead of a direct TCP connection
+        #[cfg(feature = "webrtc")]
+        #[arg(long)]
+        webrtc: bool,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum MacroAction {
+    /// Print the macros with their kind and description
+    List,
+    /// Print the description, length and, for hand-written macros, the content of a macro
+    Show {
+        /// File name, the extension can be left out
+        name: String,
+    },
+    /// Set the description of a macro
+    Describe {
+        name: String,
+        description: String,
+    },
+    /// Delete a macro file along with its description
+    Delete {
+        name: String,
+    },
+    /// Rename a macro, keeping its extension when the new name has none
+    Rename {
+        name: String,
+        new_name: String,
+    },
+    /// Bundle macros with their descriptions into an archive, every macro when none is named
+    Export {
+        archive: PathBuf,
+        names: Vec<String>,
+    },
+    /// Add the macros of an archive to the directory
+    Import {
+        archive: PathBuf,
+
+        /// Replace the macros that already exist
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ScanStep {
+    /// Read a whole range and keep the addresses holding the value, replacing the previous scan
+    New {
+        /// What the offset is relative to
+        #[arg(value_enum)]
+        region: Region,
+
+        /// Hex offset in the region
+        #[arg(value_parser = memory::parse_address)]
+        offset: u64,
+
+        /// Number of bytes to scan, decimal or 0x prefixed hex
+        #[arg(value_parser = dump::parse_size)]
+        size: usize,
+
+        /// Value to look for, decimal or 0x prefixed hex
+        #[arg(value_parser = scan::parse_value)]
+        value: u64,
+
+        /// Bytes of the value, 1, 2, 4 or 8
+        #[arg(long, default_value = "4", value_parser = scan::parse_width)]
+        width: usize,
+
+        /// File keeping the results between steps
+        #[arg(long, default_value = scan::DEFAULT_FILE)]
+        file: PathBuf,
+    },
+    /// Read the remaining addresses again and keep the ones passing the filter
+    Next {
+        #[arg(value_enum)]
+        filter: Filter,
+
+        /// Value for the equal filter, decimal or 0x prefixed hex
+        #[arg(value_parser = scan::parse_value)]
+        value: Option<u64>,
+
+        /// File keeping the results between steps
+        #[arg(long, default_value = scan::DEFAULT_FILE)]
+        file: PathBuf,
+    },
+    /// Print the remaining addresses and their last values
+    List {
+        /// Addresses printed at most
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+
+        /// File keeping the results between steps
+        #[arg(long, default_value = scan::DEFAULT_FILE)]
+        file: PathBuf,
+    },
+}
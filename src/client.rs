@@ -0,0 +1,214 @@
+//! A gamepad session with one switch, for programs embedding the crate.
+//!
+//! The stick deadzone needs the `gamepad` feature, the example only builds with it.
+//!
+#![cfg_attr(feature = "gamepad", doc = "```no_run")]
+#![cfg_attr(not(feature = "gamepad"), doc = "```ignore")]
+//! use switch_usb_controld::client::SwitchClient;
+//!
+//! let mut client = SwitchClient::builder()
+//!     .tcp("192.168.1.20:6000".parse().unwrap())
+//!     .deadzone(8000)
+//!     .build()
+//!     .expect("Unable to reach the switch");
+//! client.press(switch_usb_controld::controller::Button::A);
+//! client.tick();
+//! client.close();
+//! ```
+
+use crate::command::Command;
+use crate::config::Source;
+use crate::connection::Tcp;
+#[cfg(feature = "usb")]
+use crate::connection::UsbDevice;
+use crate::controller::{Button, ControllerState};
+#[cfg(feature = "gamepad")]
+use crate::controller::{self, BTN_ASSOCIATION};
+use crate::error::Error;
+use crate::router::Router;
+use crate::transport::{Target, Transport};
+#[cfg(feature = "usb")]
+use crate::usb::Usb;
+#[cfg(feature = "gamepad")]
+use gilrs::{Axis, EventType};
+#[cfg(feature = "gamepad")]
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+#[derive(Clone)]
+enum Link {
+    #[cfg(feature = "usb")]
+    USB(UsbDevice),
+    TCP(SocketAddr),
+    TARGET(Target),
+}
+
+/// Configures a [`SwitchClient`], which needs one of `usb`, `tcp` or `target` to know where to connect.
+#[derive(Clone)]
+pub struct SwitchClientBuilder {
+    link: Option<Link>,
+    #[cfg(feature = "gamepad")]
+    deadzone: i32,
+    #[cfg(feature = "gamepad")]
+    mapping: HashMap<gilrs::Button, Button>,
+}
+
+impl SwitchClientBuilder {
+    /// Connects to the first switch plugged over USB.
+    #[cfg(feature = "usb")]
+    pub fn usb(self) -> Self {
+        self.usb_device(UsbDevice::INDEX(0))
+    }
+
+    /// Connects to a given switch plugged over USB, when there are several.
+    #[cfg(feature = "usb")]
+    pub fn usb_device(mut self, device: UsbDevice) -> Self {
+        self.link = Some(Link::USB(device));
+        self
+    }
+
+    /// Connects to sys-botbase over the network.
+    pub fn tcp(mut self, address: SocketAddr) -> Self {
+        self.link = Some(Link::TCP(address));
+        self
+    }
+
+    /// Connects through any backend, written as on the command line, e.g. `serial:/dev/ttyACM0`.
+    pub fn target(mut self, target: Target) -> Self {
+        self.link = Some(Link::TARGET(target));
+        self
+    }
+
+    /// How far from the center, in sys-botbase units, a stick has to move to count.
+    #[cfg(feature = "gamepad")]
+    pub fn deadzone(mut self, deadzone: i32) -> Self {
+        self.deadzone = deadzone;
+        self
+    }
+
+    /// Which switch button each gamepad button presses, replacing the default layout.
+    #[cfg(feature = "gamepad")]
+    pub fn mapping(mut self, mapping: impl IntoIterator<Item = (gilrs::Button, Button)>) -> Self {
+        self.mapping = mapping.into_iter().collect();
+        self
+    }
+
+    /// Opens the link once, [`crate::transport::retry`] keeps trying when given a clone of the builder.
+    pub fn build(self) -> Result<SwitchClient, Error> {
+        let transport: Box<dyn Transport> = match self.link {
+            #[cfg(feature = "usb")]
+            Some(Link::USB(device)) => Box::new(Usb::connect(device)?),
+            Some(Link::TCP(address)) => Box::new(Tcp::connect(address)?),
+            Some(Link::TARGET(target)) => target.connect()?,
+            None => return Err(Error::NOLINK),
+        };
+        Ok(SwitchClient {
+            router: Router::single(transport),
+            state: ControllerState::new(),
+            #[cfg(feature = "gamepad")]
+            deadzone: self.deadzone,
+            #[cfg(feature = "gamepad")]
+            mapping: self.mapping,
+        })
+    }
+}
+
+/// A connected switch and the gamepad driving it, sent one tick at a time.
+pub struct SwitchClient {
+    router: Router,
+    state: ControllerState,
+    #[cfg(feature = "gamepad")]
+    deadzone: i32,
+    #[cfg(feature = "gamepad")]
+    mapping: HashMap<gilrs::Button, Button>,
+}
+
+impl SwitchClient {
+    pub fn builder() -> SwitchClientBuilder {
+        SwitchClientBuilder {
+            link: None,
+            #[cfg(feature = "gamepad")]
+            deadzone: controller::DEADZONE,
+            #[cfg(feature = "gamepad")]
+            mapping: BTN_ASSOCIATION.rev().clone(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        self.router.describe()
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.state.press(button);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.state.release(button);
+    }
+
+    pub fn controller(&mut self) -> &mut ControllerState {
+        &mut self.state
+    }
+
+    /// Replaces the mapping given to the builder, for a layout edited while the session runs.
+    #[cfg(feature = "gamepad")]
+    pub fn remap(&mut self, mapping: impl IntoIterator<Item = (gilrs::Button, Button)>) {
+        self.mapping = mapping.into_iter().collect();
+    }
+
+    /// Applies a gilrs event through the mapping and dead zone, ignoring what the switch doesn't have.
+    #[cfg(feature = "gamepad")]
+    pub fn handle(&mut self, event: &EventType) {
+        match *event {
+            EventType::ButtonPressed(button, _) => {
+                if let Some(button) = self.mapping.get(&button) {
+                    self.state.press(*button);
+                }
+            }
+            EventType::ButtonReleased(button, _) => {
+                if let Some(button) = self.mapping.get(&button) {
+                    self.state.release(*button);
+                }
+            }
+            EventType::AxisChanged(axis, value, _) => {
+                let value = controller::get_axis_values(value, self.deadzone);
+                match axis {
+                    Axis::LeftStickX => self.state.l_stick.0 = value,
+                    Axis::LeftStickY => self.state.l_stick.1 = value,
+                    Axis::RightStickX => self.state.r_stick.0 = value,
+                    Axis::RightStickY => self.state.r_stick.1 = value,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends what changed on the gamepad since the last tick and what the router's sources produced,
+    /// and keeps the link alive.
+    pub fn tick(&mut self) {
+        let commands = self.state.make_packets();
+        if !commands.is_empty() {
+            self.router.send(Source::GAMEPAD, 0, commands);
+        }
+        self.state.next_tick();
+        self.router.poll_sources();
+        self.router.heartbeat();
+    }
+
+    /// Sends commands as they are, outside of the gamepad state.
+    pub fn send(&mut self, commands: Vec<Command>) {
+        self.router.send(Source::MACRO, 0, commands);
+    }
+
+    /// For what the client doesn't wrap, such as playing macros or reading memory.
+    pub fn router(&mut self) -> &mut Router {
+        &mut self.router
+    }
+
+    /// Releases whatever is still held and detaches the virtual controller.
+    pub fn close(mut self) {
+        self.router.release_all();
+        self.router.detach();
+    }
+}
@@ -0,0 +1,152 @@
+use crate::hex;
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+// Names sys-botbase accepts for click, press and release
+pub const BUTTONS: [&str; 19] = [
+    "A", "B", "X", "Y", "RSTICK", "LSTICK", "L", "R", "ZL", "ZR", "PLUS", "MINUS",
+    "DLEFT", "DUP", "DDOWN", "DRIGHT", "HOME", "CAPTURE", "PALMA",
+];
+pub const STICKS: [&str; 2] = ["LEFT", "RIGHT"];
+
+// Touch points are in screen pixels, the origin being the top left corner
+pub const SCREEN_WIDTH: i32 = 1280;
+pub const SCREEN_HEIGHT: i32 = 720;
+
+// Names known in advance are borrowed, so building the usual commands doesn't allocate
+pub type Name = Cow<'static, str>;
+
+// Borrows the known spelling of a name, only unknown ones are copied
+pub fn name(known: &[&'static str], name: &str) -> Name {
+    match known.iter().find(|known| **known == name) {
+        Some(known) => Cow::Borrowed(known),
+        None => Cow::Owned(name.to_string()),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    CLICK(Name),
+    PRESS(Name),
+    RELEASE(Name),
+    SETSTICK(Name, i32, i32),
+    TOUCH(Vec<(i32, i32)>),
+    TOUCHHOLD(i32, i32, u32),
+    TOUCHDRAW(Vec<(i32, i32)>),
+    OTHER(String),
+}
+
+impl Command {
+    // The button or stick the command acts on, used for routing
+    pub fn input(&self) -> Option<&str> {
+        match self {
+            Command::CLICK(input) | Command::PRESS(input) | Command::RELEASE(input) | Command::SETSTICK(input, _, _) => Some(input),
+            _ => None,
+        }
+    }
+
+    pub fn is_input(&self) -> bool {
+        self.input().is_some()
+    }
+
+    pub fn is_touch(&self) -> bool {
+        matches!(self, Command::TOUCH(_) | Command::TOUCHHOLD(..) | Command::TOUCHDRAW(_))
+    }
+
+    // The sys-botbase command name, the first word of the line
+    pub fn name(&self) -> &str {
+        match self {
+            Command::CLICK(_) => "click",
+            Command::PRESS(_) => "press",
+            Command::RELEASE(_) => "release",
+            Command::SETSTICK(..) => "setStick",
+            Command::TOUCH(_) => "touch",
+            Command::TOUCHHOLD(..) => "touchHold",
+            Command::TOUCHDRAW(_) => "touchDraw",
+            Command::OTHER(command) => command.split_whitespace().next().unwrap_or_default(),
+        }
+    }
+}
+
+fn write_points(f: &mut Formatter<'_>, points: &[(i32, i32)]) -> std::fmt::Result {
+    points.iter().try_for_each(|(x, y)| write!(f, " {} {}", x, y))
+}
+
+fn parse_points(values: &[&str]) -> Result<Vec<(i32, i32)>, String> {
+    let values = values.iter()
+        .map(|value| value.parse::<i32>().map_err(|_| format!("Invalid coordinate {}", value)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    values.chunks(2)
+        .map(|point| match point {
+            [x, y] if (0..SCREEN_WIDTH).contains(x) && (0..SCREEN_HEIGHT).contains(y) => Ok((*x, *y)),
+            [x, y] => Err(format!("Touch point ({}, {}) is off the {}x{} screen", x, y, SCREEN_WIDTH, SCREEN_HEIGHT)),
+            _ => Err("Touch points need both coordinates".to_string()),
+        })
+        .collect()
+}
+
+// Straight into the packet, a setStick is built on every stick move
+fn write_hex(f: &mut Formatter<'_>, n: i32) -> std::fmt::Result {
+    if !(-0x8000..=0x7FFF).contains(&n) {
+        panic!("Number out of range for 16-bit signed integer");
+    }
+
+    let sign = if n < 0 { "-" } else { "" };
+    write!(f, " {}0x{:>04X}", sign, n.unsigned_abs())
+}
+
+impl Display for Command {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Command::CLICK(button) => write!(f, "click {}", button),
+            Command::PRESS(button) => write!(f, "press {}", button),
+            Command::RELEASE(button) => write!(f, "release {}", button),
+            Command::SETSTICK(stick, x, y) => {
+                write!(f, "setStick {}", stick)?;
+                write_hex(f, *x)?;
+                write_hex(f, *y)
+            }
+            Command::TOUCH(points) => {
+                write!(f, "touch")?;
+                write_points(f, points)
+            }
+            Command::TOUCHHOLD(x, y, millis) => write!(f, "touchHold {} {} {}", x, y, millis),
+            Command::TOUCHDRAW(points) => {
+                write!(f, "touchDraw")?;
+                write_points(f, points)
+            }
+            Command::OTHER(command) => write!(f, "{}", command),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        Ok(match parts.as_slice() {
+            ["click", button] => Command::CLICK(name(&BUTTONS, button)),
+            ["press", button] => Command::PRESS(name(&BUTTONS, button)),
+            ["release", button] => Command::RELEASE(name(&BUTTONS, button)),
+            ["setStick", stick, x, y] => {
+                let axis = |value: &str| {
+                    hex::parse_signed(value)
+                        .filter(|value| (-0x8000..=0x7FFF).contains(value))
+                        .ok_or_else(|| format!("Invalid stick value {}", value))
+                };
+                Command::SETSTICK(name(&STICKS, stick), axis(x)?, axis(y)?)
+            }
+            ["touch", points @ ..] if !points.is_empty() => Command::TOUCH(parse_points(points)?),
+            ["touchHold", x, y, millis] => {
+                let (x, y) = parse_points(&[x, y])?[0];
+                Command::TOUCHHOLD(x, y, millis.parse().map_err(|_| format!("Invalid duration {}", millis))?)
+            }
+            // A drag needs somewhere to start and somewhere to end
+            ["touchDraw", points @ ..] if points.len() >= 4 => Command::TOUCHDRAW(parse_points(points)?),
+            _ => Command::OTHER(s.trim().to_string()),
+        })
+    }
+}
@@ -0,0 +1,149 @@
+use crate::command::{Command, Name, BUTTONS, STICKS};
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+
+// Asked in text before switching, a patched sys-botbase or a relay answers 1
+pub const NEGOTIATE: &str = "compactCommands";
+
+// Every frame starts with an opcode, inputs then take a button or stick index and
+// setStick two i16 LE, anything else is sent as text behind a u16 LE length
+const TEXT: u8 = 0x00;
+const CLICK: u8 = 0x01;
+const PRESS: u8 = 0x02;
+const RELEASE: u8 = 0x03;
+const SETSTICK: u8 = 0x04;
+
+fn index(names: &[&str], name: &str) -> Option<u8> {
+    names.iter().position(|candidate| *candidate == name).map(|index| index as u8)
+}
+
+// A text longer than its u16 length can say is refused rather than cut, which would desync the stream
+fn encode_text(command: &Command, frame: &mut Vec<u8>) -> Result<(), String> {
+    let start = frame.len();
+    frame.push(TEXT);
+    frame.extend_from_slice(&[0; 2]);
+    write!(frame, "{}", command).unwrap();
+    let Ok(text) = u16::try_from(frame.len() - start - 3) else {
+        let length = frame.len() - start - 3;
+        frame.truncate(start);
+        return Err(format!("A {} byte command is too long for a compact frame, at most {}", length, u16::MAX));
+    };
+    frame[start + 1..start + 3].copy_from_slice(&text.to_le_bytes());
+    Ok(())
+}
+
+// Appends the frame, so a batch is encoded into one buffer. Nothing is appended on error
+pub fn encode(command: &Command, frame: &mut Vec<u8>) -> Result<(), String> {
+    let (opcode, index) = match command {
+        Command::CLICK(button) => (CLICK, index(&BUTTONS, button)),
+        Command::PRESS(button) => (PRESS, index(&BUTTONS, button)),
+        Command::RELEASE(button) => (RELEASE, index(&BUTTONS, button)),
+        Command::SETSTICK(stick, _, _) => (SETSTICK, index(&STICKS, stick)),
+        _ => (TEXT, None),
+    };
+    let Some(index) = index else {
+        return encode_text(command, frame);
+    };
+    frame.extend_from_slice(&[opcode, index]);
+    if let Command::SETSTICK(_, x, y) = command {
+        frame.extend_from_slice(&(*x as i16).to_le_bytes());
+        frame.extend_from_slice(&(*y as i16).to_le_bytes());
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn name(names: &[&'static str], index: u8) -> io::Result<Name> {
+    names.get(index as usize)
+        .map(|name| Cow::Borrowed(*name))
+        .ok_or_else(|| invalid(format!("Unknown input index {}", index)))
+}
+
+pub fn read(reader: &mut impl Read) -> io::Result<Command> {
+    let [opcode] = read_bytes(reader)?;
+    match opcode {
+        CLICK | PRESS | RELEASE => {
+            let [index] = read_bytes(reader)?;
+            let button = name(&BUTTONS, index)?;
+            Ok(match opcode {
+                CLICK => Command::CLICK(button),
+                PRESS => Command::PRESS(button),
+                _ => Command::RELEASE(button),
+            })
+        }
+        SETSTICK => {
+            let [index, x0, x1, y0, y1] = read_bytes(reader)?;
+            let stick = name(&STICKS, index)?;
+            Ok(Command::SETSTICK(stick, i16::from_le_bytes([x0, x1]) as i32, i16::from_le_bytes([y0, y1]) as i32))
+        }
+        TEXT => {
+            let length = u16::from_le_bytes(read_bytes(reader)?);
+            let mut text = vec![0; length as usize];
+            reader.read_exact(&mut text)?;
+            String::from_utf8(text)
+                .map_err(|err| invalid(err.to_string()))?
+                .parse()
+                .map_err(invalid)
+        }
+        _ => Err(invalid(format!("Unknown compact opcode {:#04x}", opcode))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_reads_back_as_encoded() {
+        let commands = [
+            Command::CLICK("A".into()),
+            Command::PRESS("ZR".into()),
+            Command::RELEASE("HOME".into()),
+            Command::SETSTICK("LEFT".into(), -32767, 32767),
+            Command::SETSTICK("RIGHT".into(), 0, -1),
+            Command::OTHER("getVersion".to_string()),
+        ];
+        let mut frames = Vec::new();
+        for command in &commands {
+            encode(command, &mut frames).unwrap();
+        }
+
+        let mut reader = frames.as_slice();
+        for command in &commands {
+            assert_eq!(read(&mut reader).unwrap(), *command);
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn inputs_take_fixed_size_frames() {
+        let mut frame = Vec::new();
+        encode(&Command::CLICK("A".into()), &mut frame).unwrap();
+        assert_eq!(frame.len(), 2);
+        encode(&Command::SETSTICK("LEFT".into(), 1, 2), &mut frame).unwrap();
+        assert_eq!(frame.len(), 2 + 6);
+    }
+
+    #[test]
+    fn an_over_length_text_frame_is_refused() {
+        let mut frame = vec![CLICK, 0];
+        let err = encode(&Command::OTHER("a".repeat(u16::MAX as usize + 1)), &mut frame).unwrap_err();
+        assert!(err.contains("too long"), "{}", err);
+        assert_eq!(frame, [CLICK, 0]);
+    }
+
+    #[test]
+    fn an_unknown_opcode_is_invalid_data() {
+        let err = read(&mut [0x7Fu8, 0].as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,373 @@
+use crate::command::Command;
+use crate::error::Error;
+use crate::memory::Location;
+use crate::transport::Target;
+use crate::watch::Condition;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CONFIG_PATH: &str = "switch-usb-control.toml";
+
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub usb: UsbSettings,
+    #[serde(default)]
+    pub protocol: ProtocolSettings,
+    #[serde(default)]
+    pub tcp: TcpSettings,
+    pub tls: Option<TlsSettings>,
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub targets: BTreeMap<String, Target>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub sinks: Vec<String>,
+    #[serde(default)]
+    pub bindings: Vec<Binding>,
+    pub controller: Option<ControllerType>,
+    pub wait_for_title: Option<String>,
+    pub anti_idle_minutes: Option<u64>,
+    // Overrides the default of the connection, USB ticks faster than the network
+    pub tick_interval_ms: Option<u64>,
+    #[serde(default)]
+    pub timing: TimingSettings,
+    #[serde(default)]
+    pub freezes: BTreeMap<String, FreezeSettings>,
+    #[serde(default)]
+    pub watches: BTreeMap<String, WatchSettings>,
+    #[serde(default)]
+    pub schedule: BTreeMap<String, ScheduleSettings>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct UsbSettings {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub interface: u8,
+    pub endpoint: u8,
+    pub in_endpoint: u8,
+    // Transfers left in flight before sending waits for the oldest one to complete
+    pub max_in_flight: usize,
+}
+
+impl Default for UsbSettings {
+    fn default() -> Self {
+        UsbSettings {
+            vendor_id: 0x057e,
+            product_id: 0x3000,
+            interface: 0,
+            endpoint: 0x01,
+            in_endpoint: 0x81,
+            max_in_flight: 16,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Framing {
+    #[default]
+    AUTO,
+    LINES,
+    PREFIXED,
+}
+
+// Compact binary commands are only used over TCP once the other end agreed to them
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    TEXT,
+    COMPACT,
+}
+
+// How the length header of prefixed framing counts the command, it changed between botbase versions
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeHeader {
+    #[default]
+    PADDED,
+    EXACT,
+    TERMINATED,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct ProtocolSettings {
+    pub framing: Framing,
+    pub size_header: SizeHeader,
+    pub encoding: Encoding,
+}
+
+// Nagle's algorithm would hold a small batch back until the previous one is acknowledged
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct TcpSettings {
+    pub nodelay: bool,
+}
+
+impl Default for TcpSettings {
+    fn default() -> Self {
+        TcpSettings { nodelay: true }
+    }
+}
+
+// sys-botbase defaults are tuned for safety, automation often wants them shorter
+#[derive(Deserialize, Default, Clone, Copy, Debug, clap::Args)]
+pub struct TimingSettings {
+    /// Milliseconds sys-botbase sleeps between two reads of its socket
+    #[arg(long)]
+    pub main_loop_sleep_time: Option<u32>,
+
+    /// Milliseconds sys-botbase holds a button down for a click
+    #[arg(long)]
+    pub button_click_sleep_time: Option<u32>,
+
+    /// Milliseconds sys-botbase waits between two keyboard keys
+    #[arg(long)]
+    pub key_sleep_time: Option<u32>,
+
+    /// Milliseconds between two polls of the virtual controller
+    #[arg(long)]
+    pub poll_rate: Option<u32>,
+}
+
+impl TimingSettings {
+    // Values given on the command line win over the ones of the config file
+    pub fn or(self, other: TimingSettings) -> TimingSettings {
+        TimingSettings {
+            main_loop_sleep_time: self.main_loop_sleep_time.or(other.main_loop_sleep_time),
+            button_click_sleep_time: self.button_click_sleep_time.or(other.button_click_sleep_time),
+            key_sleep_time: self.key_sleep_time.or(other.key_sleep_time),
+            poll_rate: self.poll_rate.or(other.poll_rate),
+        }
+    }
+
+    pub fn commands(&self) -> Vec<Command> {
+        [
+            ("mainLoopSleepTime", self.main_loop_sleep_time),
+            ("buttonClickSleepTime", self.button_click_sleep_time),
+            ("keySleepTime", self.key_sleep_time),
+            ("pollRate", self.poll_rate),
+        ]
+        .into_iter()
+        .filter_map(|(setting, value)| value.map(|value| Command::OTHER(format!("configure {} {}", setting, value))))
+        .collect()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TlsSettings {
+    pub server_name: String,
+    pub ca_file: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    #[default]
+    GAMEPAD,
+    MACRO,
+    PLUGIN,
+}
+
+impl Source {
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::GAMEPAD => "gamepad",
+            Source::MACRO => "macro",
+            Source::PLUGIN => "plugin",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ControllerType {
+    PRO,
+    JOYCONLEFT,
+    JOYCONRIGHT,
+    HANDHELD,
+}
+
+impl ControllerType {
+    // sys-botbase takes the libnx HidDeviceType of the pad it emulates
+    pub fn device_type(self) -> u8 {
+        match self {
+            ControllerType::JOYCONRIGHT => 1,
+            ControllerType::JOYCONLEFT => 2,
+            ControllerType::PRO => 3,
+            ControllerType::HANDHELD => 6,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    PASTE,
+    SCREENSHOT,
+    SCREENOFF,
+    SCREENON,
+    DETACH,
+    ATTACH,
+    FREEZE,
+    RECORD,
+    PLAY,
+    MASH,
+    BIND,
+    PAUSE,
+    STEP,
+    STATS,
+}
+
+// Pressing all the buttons of a binding together on the gamepad performs its action,
+// the freeze action toggles the named freeze, the record one starts or stops recording
+// the gamepad to the macro file (a timestamped one when omitted), the play one starts
+// or stops replaying it, speed times faster and the mash one starts or stops clicking the button rate times a second.
+// The bind one makes the next chord pressed play the file, or the macro recorded last, until the tool stops.
+// The pause one pauses or resumes the macro being replayed and the step one sends its next command.
+// The stats one prints the packets, latencies, reconnects and dropped events of the session so far.
+// Nothing is bound unless the config asks for it, the buttons of a chord still reach the console. For example:
+//
+//   [[bindings]]
+//   buttons = ["LSTICK", "RSTICK"]
+//   action = "paste"
+//
+//   [[bindings]]
+//   buttons = ["PLUS", "MINUS"]
+//   action = "screenshot"
+//
+//   [[bindings]]
+//   buttons = ["ZL", "ZR", "MINUS"]
+//   action = "record"
+//
+//   [[bindings]]
+//   buttons = ["ZL", "ZR", "PLUS"]
+//   action = "bind"
+//
+//   [[bindings]]
+//   buttons = ["ZL", "ZR", "Y"]
+//   action = "pause"
+//
+//   [[bindings]]
+//   buttons = ["ZL", "ZR", "X"]
+//   action = "step"
+#[derive(Deserialize, Clone, Debug)]
+pub struct Binding {
+    pub buttons: Vec<String>,
+    pub action: Action,
+    pub freeze: Option<String>,
+    pub file: Option<PathBuf>,
+    pub button: Option<String>,
+    pub rate: Option<f64>,
+    pub speed: Option<f64>,
+}
+
+// A heap value to freeze, both written in hex
+#[derive(Deserialize, Clone, Debug)]
+pub struct FreezeSettings {
+    pub address: String,
+    pub value: String,
+}
+
+// Memory polled every interval, the macro plays each time the condition starts matching
+#[derive(Deserialize, Clone, Debug)]
+pub struct WatchSettings {
+    pub location: Location,
+    pub size: usize,
+    #[serde(default = "default_watch_interval")]
+    pub interval_ms: u64,
+    pub when: Option<Condition>,
+    pub play: Option<PathBuf>,
+}
+
+pub fn default_watch_interval() -> u64 {
+    500
+}
+
+// A macro played at the times of a cron expression ("0 6 * * *") or every interval ("30m")
+#[derive(Deserialize, Clone, Debug)]
+pub struct ScheduleSettings {
+    #[serde(rename = "macro")]
+    pub file: PathBuf,
+    pub cron: Option<String>,
+    pub every: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Route {
+    pub target: String,
+    #[serde(default)]
+    pub source: Source,
+    pub gamepad: Option<usize>,
+    #[serde(default)]
+    pub inputs: Vec<String>,
+}
+
+impl Route {
+    pub fn matches(&self, source: Source, gamepad: usize, command: &Command) -> bool {
+        let input = command.input().unwrap_or_default();
+
+        self.source == source
+            && self.gamepad.is_none_or(|g| g == gamepad)
+            && (self.inputs.is_empty() || self.inputs.iter().any(|i| i.eq_ignore_ascii_case(input)))
+    }
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Path::new(DEFAULT_CONFIG_PATH),
+            None => return Ok(Config::default()),
+        };
+
+        fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|content| toml::from_str::<Config>(&content).map_err(|err| err.to_string()))
+            .and_then(|config| config.check_routes().map(|_| config))
+            .map_err(|reason| Error::CONFIG { path: path.to_path_buf(), reason })
+    }
+
+    // Caught when loading, the router would only find out once connected to the other targets
+    fn check_routes(&self) -> Result<(), String> {
+        match self.routes.iter().find(|route| !self.targets.contains_key(&route.target)) {
+            Some(route) => Err(format!("a route goes to the unknown target {}", route.target)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn load(name: &str, content: &str) -> Result<Config, Error> {
+        let path: PathBuf = std::env::temp_dir().join(format!("switch-usb-controld-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        let config = Config::load(Some(&path));
+        let _ = fs::remove_file(&path);
+        config
+    }
+
+    #[test]
+    fn routes_to_known_targets_load() {
+        let config = load("known.toml", "[targets]\nleft = \"tcp:127.0.0.1:6000\"\n\n[[routes]]\ntarget = \"left\"\n").unwrap();
+        assert_eq!(config.routes.len(), 1);
+    }
+
+    #[test]
+    fn a_route_to_an_unknown_target_is_refused() {
+        let config = load("unknown.toml", "[targets]\nleft = \"tcp:127.0.0.1:6000\"\n\n[[routes]]\ntarget = \"right\"\n");
+        assert!(matches!(config, Err(Error::CONFIG { reason, .. }) if reason.contains("right")));
+    }
+}
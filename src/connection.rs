@@ -0,0 +1,352 @@
+use crate::auth;
+use crate::command::Command;
+use crate::compact;
+use crate::config::{Encoding, Framing, TcpSettings};
+use crate::error::Error;
+use crate::health::SendOutcome;
+use crate::notify;
+use crate::protocol::{self, Transfers};
+use crate::response::{self, Reply, Response};
+use crate::shutdown;
+use crate::tls::{self, Stream};
+use crate::transport::{Target, Transport};
+use crate::version::Negotiated;
+use socket2::{SockRef, TcpKeepalive};
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(8);
+// About half a minute with the backoff, enough for the switch to come back from sleep or a Wi-Fi hiccup
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(2);
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+pub(crate) const MAX_READ_CHUNK: usize = 0x10000;
+const KEEPALIVE_TIME: Duration = Duration::from_secs(5);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+
+static TCP_SETTINGS: OnceLock<TcpSettings> = OnceLock::new();
+
+pub fn configure_tcp(settings: TcpSettings) {
+    if TCP_SETTINGS.set(settings).is_err() {
+        panic!("TCP settings are already configured!");
+    }
+}
+
+fn tcp_settings() -> TcpSettings {
+    *TCP_SETTINGS.get_or_init(TcpSettings::default)
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum ConnectionType {
+    USB,
+    INTERNET,
+    FAILOVER,
+    REDUNDANT,
+    MIRROR,
+    SERIAL,
+    BLUETOOTH,
+    GADGET,
+}
+
+#[derive(Clone, Debug)]
+pub enum UsbDevice {
+    INDEX(usize),
+    SERIAL(String),
+}
+
+impl FromStr for UsbDevice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            _ if s.is_empty() => UsbDevice::INDEX(0),
+            Ok(index) => UsbDevice::INDEX(index),
+            Err(_) => UsbDevice::SERIAL(s.to_string()),
+        })
+    }
+}
+
+impl Display for UsbDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UsbDevice::INDEX(index) => write!(f, "#{}", index),
+            UsbDevice::SERIAL(serial) => write!(f, "{}", serial),
+        }
+    }
+}
+
+// One buffer for the whole connection, bytes read past a line stay there for the next read
+type Buffered = BufReader<Stream>;
+
+pub struct Tcp {
+    stream: Buffered,
+    address: SocketAddr,
+    negotiated: Negotiated,
+    compact: bool,
+    transfers: Transfers,
+}
+
+impl Tcp {
+    fn negotiate(mut stream: Buffered, address: SocketAddr) -> Tcp {
+        let reply = query(&mut stream, "getVersion", false).ok();
+        let compact = negotiate_compact(&mut stream);
+        Tcp { stream, address, negotiated: Negotiated::new(reply.as_deref()), compact, transfers: Transfers::default() }
+    }
+
+    // A new connection starts over in text, so compact commands are asked for again
+    fn reopen(&mut self) -> io::Result<()> {
+        self.stream = reconnect_with_backoff(self.address)?;
+        self.compact = negotiate_compact(&mut self.stream);
+        Ok(())
+    }
+
+    // Counts the failure and reopens the link, false when the switch stayed out of reach
+    fn recover(&mut self, outcome: &mut SendOutcome) -> bool {
+        outcome.failures += 1;
+        match self.reopen() {
+            Ok(()) => {
+                info!("Reconnected to switch!");
+                notify::send(notify::SWITCH_BACK, &format!("Reconnected to {}", self.address));
+                outcome.reconnects += 1;
+                true
+            }
+            Err(err) => {
+                warn!("Unable to reconnect to switch at {} ({}), giving up on this send", self.address, err);
+                false
+            }
+        }
+    }
+
+    pub fn connect(address: SocketAddr) -> Result<Tcp, Error> {
+        let stream = TcpStream::connect(address)
+            .and_then(open_stream)
+            .map_err(|source| Error::TCP { address, source })?;
+        Ok(Tcp::negotiate(stream, address))
+    }
+
+    pub fn try_connect(address: SocketAddr) -> Option<Tcp> {
+        let socket = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).ok()?;
+        Some(Tcp::negotiate(open_stream(socket).ok()?, address))
+    }
+
+    fn supported(&mut self, commands: &[Command]) -> Vec<Command> {
+        commands.iter().filter(|command| self.negotiated.supports(command)).cloned().collect()
+    }
+}
+
+impl Transport for Tcp {
+    fn describe(&self) -> String {
+        format!("switch at {}", self.address)
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.reopen().map_err(|err| err.to_string())
+    }
+
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        let spacing = self.negotiated.command_spacing();
+        let commands = self.supported(commands);
+        // Older sys-botbase versions drop commands arriving together, they get one write each
+        let batches = match spacing.is_zero() {
+            true => commands.chunks(commands.len().max(1)),
+            false => commands.chunks(1),
+        };
+        for batch in batches {
+            while let Err(err) = write_commands(&mut self.stream, &mut self.transfers, batch, self.compact) {
+                warn!("Lost connection to switch ({}), reconnecting...", err);
+                notify::send(notify::SWITCH_LOST, &format!("Lost connection to {} ({}), reconnecting", self.address, err));
+                // The failures tell the router the link is down, the next send tries again
+                if !self.recover(&mut outcome) || outcome.failures >= MAX_RECONNECT_ATTEMPTS {
+                    return outcome;
+                }
+            }
+            outcome.bytes += self.transfers.bytes().len() as u64;
+            if !spacing.is_zero() {
+                thread::sleep(spacing);
+            }
+        }
+        outcome
+    }
+
+    fn try_send_commands(&mut self, commands: &[Command]) -> Result<(), String> {
+        let spacing = self.negotiated.command_spacing();
+        let commands = self.supported(commands);
+        if spacing.is_zero() {
+            return write_commands(&mut self.stream, &mut self.transfers, &commands, self.compact).map_err(|err| err.to_string());
+        }
+        commands.iter().try_for_each(|command| {
+            write_commands(&mut self.stream, &mut self.transfers, std::slice::from_ref(command), self.compact)?;
+            thread::sleep(spacing);
+            Ok(())
+        })
+        .map_err(|err: io::Error| err.to_string())
+    }
+
+    fn heartbeat(&mut self) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        if let Err(err) = ping(&mut self.stream, self.compact) {
+            warn!("Switch stopped answering ({}), reconnecting...", err);
+            notify::send(notify::SWITCH_LOST, &format!("{} stopped answering ({}), reconnecting", self.address, err));
+            self.recover(&mut outcome);
+        }
+        outcome
+    }
+
+    fn try_heartbeat(&mut self) -> Result<(), String> {
+        ping(&mut self.stream, self.compact).map_err(|err| err.to_string())
+    }
+
+    fn runs_sequences(&self) -> bool {
+        self.negotiated.understands("clickSeq")
+    }
+
+    fn request(&mut self, command: &Command) -> Result<Response, String> {
+        let kind = expected_reply(command)?;
+        if !self.negotiated.supports(command) {
+            return Err("The sys-botbase on the switch is too old".to_string());
+        }
+        write_commands(&mut self.stream, &mut self.transfers, std::slice::from_ref(command), self.compact)
+            .and_then(|_| read_line(&mut self.stream, RESPONSE_TIMEOUT))
+            .map_err(|err| err.to_string())
+            .and_then(|line| Response::from_line(kind, &line))
+    }
+}
+
+pub struct Mirror {
+    transports: Vec<Box<dyn Transport>>,
+}
+
+impl Mirror {
+    pub fn connect(targets: &[Target]) -> Mirror {
+        Mirror { transports: targets.iter().map(Target::connect_retrying).collect() }
+    }
+}
+
+impl Transport for Mirror {
+    fn describe(&self) -> String {
+        self.transports.iter().map(|transport| transport.describe()).collect::<Vec<_>>().join(", ")
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.transports.iter_mut().try_for_each(|transport| transport.reconnect())
+    }
+
+    // One after the other, sends are short writes so the last switch is barely behind the first
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        self.transports.iter_mut()
+            .map(|transport| transport.send_commands(commands))
+            .fold(SendOutcome::default(), SendOutcome::merge)
+    }
+
+    fn runs_sequences(&self) -> bool {
+        self.transports.iter().all(|transport| transport.runs_sequences())
+    }
+
+    fn heartbeat(&mut self) -> SendOutcome {
+        self.transports.iter_mut()
+            .map(|transport| transport.heartbeat())
+            .fold(SendOutcome::default(), SendOutcome::merge)
+    }
+}
+
+fn write_commands(stream: &mut Buffered, transfers: &mut Transfers, commands: &[Command], compact: bool) -> io::Result<()> {
+    let transfers = match compact {
+        true => transfers.encode_compact(commands),
+        false => transfers.encode(Framing::LINES, commands),
+    };
+    // The whole batch in one write, a syscall and a segment per command would space them out
+    stream.get_mut().write_all(transfers.bytes())
+}
+
+fn enable_keepalive(socket: &TcpStream) {
+    let keepalive = TcpKeepalive::new()
+        .with_time(KEEPALIVE_TIME)
+        .with_interval(KEEPALIVE_INTERVAL);
+    if let Err(err) = SockRef::from(socket).set_tcp_keepalive(&keepalive) {
+        warn!("Unable to enable TCP keepalive ({})", err);
+    }
+}
+
+fn open_stream(socket: TcpStream) -> io::Result<Buffered> {
+    enable_keepalive(&socket);
+    if let Err(err) = socket.set_nodelay(tcp_settings().nodelay) {
+        warn!("Unable to set TCP_NODELAY ({})", err);
+    }
+    // pixelPeek answers with hundreds of kilobytes of hex on a single line
+    let mut stream = BufReader::with_capacity(MAX_READ_CHUNK, tls::wrap(socket)?);
+    auth::authenticate(&mut stream)?;
+    Ok(stream)
+}
+
+pub(crate) fn expected_reply(command: &Command) -> Result<Reply, String> {
+    response::reply_kind(command).ok_or_else(|| format!("sys-botbase doesn't answer {}", command))
+}
+
+fn read_line(socket: &mut Buffered, timeout: Duration) -> io::Result<String> {
+    socket.get_ref().tcp().set_read_timeout(Some(timeout))?;
+    let mut line = String::new();
+    let read = socket.read_line(&mut line);
+    socket.get_ref().tcp().set_read_timeout(None)?;
+    match read? {
+        0 => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+        _ => Ok(line.trim().to_string()),
+    }
+}
+
+fn query(socket: &mut Buffered, command: &str, compact: bool) -> io::Result<String> {
+    write_commands(socket, &mut Transfers::default(), &[Command::OTHER(command.to_string())], compact)?;
+    read_line(socket, HEARTBEAT_TIMEOUT)
+}
+
+// A write to a half-open socket still succeeds, only a missing answer reveals it
+fn ping(socket: &mut Buffered, compact: bool) -> io::Result<()> {
+    query(socket, "getVersion", compact).map(|_| ())
+}
+
+// Stock sys-botbase never answers, which costs one timeout on connect when compact is configured
+fn negotiate_compact(socket: &mut Buffered) -> bool {
+    if protocol::encoding() != Encoding::COMPACT {
+        return false;
+    }
+
+    match query(socket, compact::NEGOTIATE, false) {
+        Ok(reply) if reply == "1" => {
+            info!("Switched to compact command encoding");
+            true
+        }
+        _ => {
+            info!("Switch doesn't understand compact commands, falling back to text");
+            false
+        }
+    }
+}
+
+// Gives up after a while or when stopping, so a switch that is gone shows as failed sends rather than a hang
+fn reconnect_with_backoff(address: SocketAddr) -> io::Result<Buffered> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        shutdown::sleep(backoff);
+        if shutdown::requested() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "stopping"));
+        }
+        match TcpStream::connect(address).and_then(open_stream) {
+            Ok(socket) => return Ok(socket),
+            Err(err) if attempts >= MAX_RECONNECT_ATTEMPTS => return Err(err),
+            Err(err) => {
+                warn!("Unable to reach switch at {} ({}), retrying in {:?}", address, err, backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use crate::command::Command;
+#[cfg(feature = "gamepad")]
+use bidirectional_map::Bimap;
+#[cfg(feature = "gamepad")]
+use gilrs::ev;
+#[cfg(feature = "gamepad")]
+use lazy_static::lazy_static;
+#[cfg(feature = "gamepad")]
+use std::collections::HashMap;
+
+#[cfg(feature = "gamepad")]
+lazy_static! {
+    pub static ref BTN_ASSOCIATION: Bimap<Button, gilrs::Button> = Bimap::from_hash_map(HashMap::from([
+        (Button::A, ev::Button::East),
+        (Button::B, ev::Button::South),
+        (Button::Y, ev::Button::West),
+        (Button::X, ev::Button::North),
+        (Button::DPADRIGHT, ev::Button::DPadRight),
+        (Button::DPADDOWN, ev::Button::DPadDown),
+        (Button::DPADLEFT, ev::Button::DPadLeft),
+        (Button::DPADUP, ev::Button::DPadUp),
+        (Button::R1, ev::Button::RightTrigger),
+        (Button::L1, ev::Button::LeftTrigger),
+        (Button::R2, ev::Button::RightTrigger2),
+        (Button::L2, ev::Button::LeftTrigger2),
+        (Button::R3, ev::Button::RightThumb),
+        (Button::L3, ev::Button::LeftThumb),
+        (Button::START, ev::Button::Start),
+        (Button::SELECT, ev::Button::Select),
+        (Button::HOME, ev::Button::Mode),
+        (Button::CAPTURE, ev::Button::Unknown)
+    ]));
+}
+
+#[derive(Eq, PartialEq, Clone, Copy)]
+#[derive(Hash, Debug)]
+pub enum Button {
+    A,
+    B,
+    X,
+    Y,
+    DPADRIGHT,
+    DPADDOWN,
+    DPADLEFT,
+    DPADUP,
+    R1,
+    L1,
+    R2,
+    L2,
+    R3,
+    L3,
+    START,
+    SELECT,
+    CAPTURE,
+    HOME,
+}
+
+const BUTTON_COUNT: usize = 18;
+/// Every button, in the order of the enum. A button's position is its index in the state.
+pub const ALL_BUTTONS: [Button; BUTTON_COUNT] = [
+    Button::A, Button::B, Button::X, Button::Y,
+    Button::DPADRIGHT, Button::DPADDOWN, Button::DPADLEFT, Button::DPADUP,
+    Button::R1, Button::L1, Button::R2, Button::L2, Button::R3, Button::L3,
+    Button::START, Button::SELECT, Button::CAPTURE, Button::HOME,
+];
+
+impl Button {
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+enum Stick {
+    RIGHT,
+    LEFT
+}
+
+/// What a button does during a tick, each state becoming one command.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ButtonState {
+    /// Pressed and released within the tick, sent as `click`.
+    PRESSED,
+    /// Pressed and still down at the end of the tick, sent as `press`.
+    HELD,
+    /// Released after being held, sent as `release`.
+    RELEASED,
+}
+
+/// What a gamepad holds, turned into the sys-botbase commands that changed since the last tick.
+///
+/// Buttons move through these transitions within a tick:
+///
+/// | Before the tick | Events        | Sent       |
+/// |-----------------|---------------|------------|
+/// | up              | press         | `press`    |
+/// | up              | press release | `click`    |
+/// | held            | release       | `release`  |
+/// | held            | release press | nothing    |
+/// | held            | press         | nothing    |
+/// | up              | release       | nothing    |
+///
+/// Sticks are in sys-botbase units (-32767 to 32767) and only sent when they moved.
+/// Call [`ControllerState::make_packets`] once per tick, then [`ControllerState::next_tick`].
+#[derive(Debug, Default)]
+pub struct ControllerState {
+    pub r_stick: (i32, i32),
+    pub l_stick: (i32, i32),
+    // What the console holds as of the last tick, a bit per button, and what changes during this one.
+    // Fixed size so the input loop doesn't allocate or hash for every event
+    held: u32,
+    changes: [Option<ButtonState>; BUTTON_COUNT],
+    old_r_stick: (i32, i32),
+    old_l_stick: (i32, i32),
+}
+
+/// The sys-botbase name of a button, e.g. `ZL` or `DUP`.
+pub const fn get_button_name(button: Button) -> &'static str {
+    match button {
+        Button::A => "A",
+        Button::B => "B",
+        Button::X => "X",
+        Button::Y => "Y",
+        Button::DPADRIGHT => "DRIGHT",
+        Button::DPADDOWN => "DDOWN",
+        Button::DPADLEFT => "DLEFT",
+        Button::DPADUP => "DUP",
+        Button::R1 => "R",
+        Button::L1 => "L",
+        Button::R2 => "ZR",
+        Button::L2 => "ZL",
+        Button::R3 => "RSTICK",
+        Button::L3 => "LSTICK",
+        Button::START => "PLUS",
+        Button::SELECT => "MINUS",
+        Button::CAPTURE => "CAPTURE",
+        Button::HOME => "HOME"
+    }
+}
+
+impl ControllerState {
+    pub fn new() -> ControllerState {
+        ControllerState::default()
+    }
+
+    pub fn press(&mut self, button: Button) {
+        let change = &mut self.changes[button as usize];
+        match change {
+            None if self.held & button.bit() == 0 => *change = Some(ButtonState::HELD),
+            // Down again before the release went out, the console never sees it let go
+            Some(ButtonState::RELEASED) => *change = None,
+            // Down again after a click, it stays held rather than going out as the click alone
+            Some(ButtonState::PRESSED) => *change = Some(ButtonState::HELD),
+            _ => {}
+        }
+    }
+
+    pub fn release(&mut self, button: Button) {
+        let change = &mut self.changes[button as usize];
+        match change {
+            None if self.held & button.bit() != 0 => *change = Some(ButtonState::RELEASED),
+            Some(ButtonState::HELD) => *change = Some(ButtonState::PRESSED),
+            _ => {}
+        }
+    }
+
+    /// What the button sends this tick, if anything.
+    pub fn state(&self, button: Button) -> Option<ButtonState> {
+        self.changes[button as usize]
+    }
+
+    /// Whether the console holds the button once this tick is sent.
+    pub fn is_held(&self, button: Button) -> bool {
+        match self.changes[button as usize] {
+            Some(ButtonState::HELD) => true,
+            Some(_) => false,
+            None => self.held & button.bit() != 0,
+        }
+    }
+
+    /// Whether a button sends something this tick.
+    pub fn buttons_changed(&self) -> bool {
+        self.changes.iter().any(Option::is_some)
+    }
+
+    /// Whether a stick moved since the last tick.
+    pub fn sticks_moved(&self) -> bool {
+        self.old_l_stick != self.l_stick || self.old_r_stick != self.r_stick
+    }
+
+    /// The commands to send for this tick.
+    pub fn make_packets(&self) -> Vec<Command> {
+        let mut packets: Vec<_> = ALL_BUTTONS.iter()
+            .filter_map(|button| self.changes[*button as usize].map(|state| make_packet_for_button_state(*button, state)))
+            .collect();
+
+        if self.old_r_stick != self.r_stick {
+            packets.push(make_packet_for_stick(Stick::RIGHT, self.r_stick))
+        }
+
+        if self.old_l_stick != self.l_stick {
+            packets.push(make_packet_for_stick(Stick::LEFT, self.l_stick))
+        }
+
+        packets
+    }
+
+    /// Applies this tick's changes, so the next one only sends what changed.
+    pub fn next_tick(&mut self) {
+        for button in ALL_BUTTONS {
+            match self.changes[button as usize].take() {
+                Some(ButtonState::HELD) => self.held |= button.bit(),
+                Some(ButtonState::RELEASED) => self.held &= !button.bit(),
+                Some(ButtonState::PRESSED) | None => {}
+            }
+        }
+        self.old_l_stick = self.l_stick;
+        self.old_r_stick = self.r_stick;
+    }
+}
+
+fn make_packet_for_stick(stick: Stick, value: (i32, i32)) -> Command {
+    match stick {
+        Stick::RIGHT => Command::SETSTICK("RIGHT".into(), value.0, value.1),
+        Stick::LEFT => Command::SETSTICK("LEFT".into(), value.0, value.1)
+    }
+}
+
+fn make_packet_for_button_state(button: Button, state: ButtonState) -> Command {
+    match state {
+        ButtonState::PRESSED => {
+            Command::CLICK(get_button_name(button).into())
+        }
+        ButtonState::HELD => {
+            Command::PRESS(get_button_name(button).into())
+        }
+        ButtonState::RELEASED => {
+            Command::RELEASE(get_button_name(button).into())
+        }
+    }
+}
+
+
+/// How far from the center, in sys-botbase units, a stick has to move to count.
+pub const DEADZONE: i32 = 5000;
+
+/// Converts a gilrs axis value to sys-botbase units, with a dead zone around the center.
+pub fn get_axis_values(value: f32, deadzone: i32) -> i32 {
+    let val = (value * 32767.) as i32;
+    if val.abs() < deadzone {
+        0
+    } else {
+        val
+    }
+}
+
+/// Updates the state from a gilrs button event, ignoring buttons the switch doesn't have.
+#[cfg(feature = "gamepad")]
+pub fn process_button_action(controller_state: &mut ControllerState, btn: &gilrs::Button, pressed: bool) {
+    match BTN_ASSOCIATION.get_rev(btn) {
+        Some(button) if pressed => controller_state.press(*button),
+        Some(button) => controller_state.release(*button),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticked(state: &mut ControllerState) -> Vec<Command> {
+        let packets = state.make_packets();
+        state.next_tick();
+        packets
+    }
+
+    #[test]
+    fn press_is_sent_once_while_held() {
+        let mut state = ControllerState::new();
+        state.press(Button::A);
+        assert_eq!(state.state(Button::A), Some(ButtonState::HELD));
+        assert_eq!(ticked(&mut state), vec![Command::PRESS("A".into())]);
+
+        state.press(Button::A);
+        assert!(state.is_held(Button::A));
+        assert_eq!(ticked(&mut state), vec![]);
+    }
+
+    #[test]
+    fn release_of_held_button_is_sent() {
+        let mut state = ControllerState::new();
+        state.press(Button::B);
+        ticked(&mut state);
+
+        state.release(Button::B);
+        assert!(!state.is_held(Button::B));
+        assert_eq!(ticked(&mut state), vec![Command::RELEASE("B".into())]);
+        assert_eq!(ticked(&mut state), vec![]);
+    }
+
+    #[test]
+    fn press_and_release_in_one_tick_is_a_click() {
+        let mut state = ControllerState::new();
+        state.press(Button::X);
+        state.release(Button::X);
+        assert_eq!(state.state(Button::X), Some(ButtonState::PRESSED));
+        assert_eq!(ticked(&mut state), vec![Command::CLICK("X".into())]);
+        assert!(!state.is_held(Button::X));
+        assert_eq!(ticked(&mut state), vec![]);
+    }
+
+    #[test]
+    fn release_then_press_keeps_button_held() {
+        let mut state = ControllerState::new();
+        state.press(Button::Y);
+        ticked(&mut state);
+
+        state.release(Button::Y);
+        state.press(Button::Y);
+        assert_eq!(state.state(Button::Y), None);
+        assert!(state.is_held(Button::Y));
+        assert_eq!(ticked(&mut state), vec![]);
+    }
+
+    #[test]
+    fn press_release_press_in_one_tick_keeps_button_held() {
+        let mut state = ControllerState::new();
+        state.press(Button::A);
+        state.release(Button::A);
+        state.press(Button::A);
+        assert_eq!(state.state(Button::A), Some(ButtonState::HELD));
+        assert_eq!(ticked(&mut state), vec![Command::PRESS("A".into())]);
+        assert!(state.is_held(Button::A));
+
+        state.release(Button::A);
+        assert_eq!(ticked(&mut state), vec![Command::RELEASE("A".into())]);
+        assert!(!state.is_held(Button::A));
+    }
+
+    #[test]
+    fn release_of_unheld_button_is_ignored() {
+        let mut state = ControllerState::new();
+        state.release(Button::HOME);
+        assert_eq!(state.state(Button::HOME), None);
+        assert_eq!(ticked(&mut state), vec![]);
+    }
+
+    #[test]
+    fn buttons_are_listed_in_enum_order() {
+        for (index, button) in ALL_BUTTONS.iter().enumerate() {
+            assert_eq!(*button as usize, index);
+        }
+    }
+
+    #[test]
+    fn sticks_are_sent_only_when_moved() {
+        let mut state = ControllerState::new();
+        assert_eq!(ticked(&mut state), vec![]);
+
+        state.l_stick = (32767, 0);
+        assert_eq!(ticked(&mut state), vec![Command::SETSTICK("LEFT".into(), 32767, 0)]);
+        assert_eq!(ticked(&mut state), vec![]);
+
+        state.l_stick = (0, 0);
+        assert_eq!(ticked(&mut state), vec![Command::SETSTICK("LEFT".into(), 0, 0)]);
+    }
+}
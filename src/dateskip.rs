@@ -0,0 +1,96 @@
+use crate::capture;
+use crate::error::Error;
+use crate::macros;
+use crate::router::Router;
+use crate::shutdown;
+use std::fmt::Write;
+use tracing::info;
+
+// Where System Settings and the date are in the menus, and how long they take to show up,
+// change between firmwares. The game has to be running with the date set manually
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Profile {
+    // 10.x and older
+    V10,
+    // 11.0 to 13.x
+    V11,
+    // 14.0 and later
+    V14,
+}
+
+struct Timing {
+    // Icons between the first one of the HOME bottom row and System Settings
+    settings_icon: u32,
+    // Entries between the top of System Settings and System
+    system_entry: u32,
+    // Entries between the top of System and Date and Time
+    date_entry: u32,
+    press_ms: u32,
+    settings_ms: u32,
+}
+
+impl Profile {
+    fn timing(&self) -> Timing {
+        match self {
+            Profile::V10 => Timing { settings_icon: 4, system_entry: 14, date_entry: 4, press_ms: 100, settings_ms: 1500 },
+            Profile::V11 => Timing { settings_icon: 5, system_entry: 14, date_entry: 4, press_ms: 100, settings_ms: 1200 },
+            Profile::V14 => Timing { settings_icon: 5, system_entry: 14, date_entry: 5, press_ms: 80, settings_ms: 1000 },
+        }
+    }
+}
+
+fn clicks(text: &mut String, button: &str, count: u32, wait_ms: u32) {
+    for _ in 0..count {
+        let _ = writeln!(text, "click {}; wait {}ms", button, wait_ms);
+    }
+}
+
+// HOME, System Settings, System, Date and Time, one more day, then back to the game
+fn one_skip(timing: &Timing) -> String {
+    let press = timing.press_ms;
+    let mut text = String::new();
+    let _ = writeln!(text, "click HOME; wait {}ms", timing.settings_ms);
+    clicks(&mut text, "DDOWN", 1, press);
+    clicks(&mut text, "DRIGHT", timing.settings_icon, press);
+    let _ = writeln!(text, "click A; wait {}ms", timing.settings_ms);
+    clicks(&mut text, "DDOWN", timing.system_entry, press);
+    clicks(&mut text, "DRIGHT", 1, press);
+    clicks(&mut text, "DDOWN", timing.date_entry, press);
+    let _ = writeln!(text, "click A; wait {}ms", timing.settings_ms);
+    clicks(&mut text, "DDOWN", 2, press);
+    let _ = writeln!(text, "click A; wait {}ms", timing.settings_ms);
+    // The cursor starts on the day
+    clicks(&mut text, "DUP", 1, press);
+    clicks(&mut text, "DRIGHT", 5, press);
+    let _ = writeln!(text, "click A; wait {}ms", press);
+    let _ = writeln!(text, "click HOME; wait {}ms", timing.settings_ms);
+    let _ = writeln!(text, "click A; wait {}ms", timing.settings_ms);
+    text
+}
+
+// Both are asked for when not given on the command line
+pub fn prompt_profile() -> Result<Profile, Error> {
+    let options = vec!["10.x and older", "11.0 to 13.x", "14.0 and later"];
+    let ans = inquire::Select::new("Which firmware does the switch run?", options)
+        .raw_prompt()
+        .map_err(|err| Error::PROMPT { what: "firmware".to_string(), reason: err.to_string() })?;
+    Ok([Profile::V10, Profile::V11, Profile::V14][ans.index])
+}
+
+pub fn prompt_count() -> Result<u32, Error> {
+    inquire::CustomType::<u32>::new("How many days should be skipped?")
+        .with_default(1)
+        .with_error_message("Please type a whole number")
+        .prompt()
+        .map_err(|err| Error::PROMPT { what: "count".to_string(), reason: err.to_string() })
+}
+
+pub fn run(router: &mut Router, profile: Profile, count: u32) -> Result<(), Error> {
+    let instructions = macros::parse(&one_skip(&profile.timing()))
+        .map_err(|reason| Error::INVALID { what: "date skip macro".to_string(), reason })?;
+    for skip in (1..=count).take_while(|_| !shutdown::requested()) {
+        info!("Skipping day {}/{}", skip, count);
+        capture::play(&instructions, router);
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+use crate::auth;
+use crate::relay;
+use crate::tls::{self, Stream};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_PORT: u16 = 6000;
+
+// Broadcast to the network, relays answer it from their port with their version, marked as a relay
+pub const PROBE: &[u8] = b"discover\r\n";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(1000);
+const PROBE_WAIT: Duration = Duration::from_millis(500);
+// Stock sys-botbase only listens on TCP, the hosts that didn't answer the probe are swept this many at a time
+const SWEEP_THREADS: usize = 32;
+// Wider networks are swept around this machine's address only
+const MAX_SWEEP: u32 = 1024;
+
+// A scan takes seconds, the retries of a failed connection reuse it
+static FOUND: Mutex<BTreeMap<u16, Vec<DiscoveredConsole>>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone)]
+pub struct DiscoveredConsole {
+    pub address: SocketAddr,
+    pub version: String,
+    pub title_id: String,
+    // A relay server in front of a switch, it doesn't tell the game running
+    pub relay: bool,
+}
+
+impl Display for DiscoveredConsole {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.relay {
+            return write!(f, "{} (relay to a switch on sys-botbase {})", self.address, self.version);
+        }
+        let running = if self.title_id.trim_start_matches('0').is_empty() {
+            "no game running".to_string()
+        } else {
+            format!("running {}", self.title_id)
+        };
+        write!(f, "{} (sys-botbase {}, {})", self.address, self.version, running)
+    }
+}
+
+// Sending to a public address picks the outgoing interface without emitting any packet
+pub fn get_local_ipv4() -> Option<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(8, 8, 8, 8), 80)).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(ip) => Some(ip),
+        IpAddr::V6(_) => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Network {
+    address: Ipv4Addr,
+    netmask: Ipv4Addr,
+}
+
+impl Network {
+    fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) | !u32::from(self.netmask))
+    }
+
+    fn hosts(&self) -> Vec<Ipv4Addr> {
+        let first = (u32::from(self.address) & u32::from(self.netmask)) + 1;
+        let last = u32::from(self.broadcast()).saturating_sub(1);
+        let start = u32::from(self.address).saturating_sub(MAX_SWEEP / 2).clamp(first, last.saturating_sub(MAX_SWEEP - 1).max(first));
+        (start..=last.min(start + MAX_SWEEP - 1)).map(Ipv4Addr::from).collect()
+    }
+}
+
+// Interfaces whose mask can't be read are taken as a /24, the usual home network
+fn local_network() -> Option<Network> {
+    let address = get_local_ipv4()?;
+    Some(Network { address, netmask: netmask(address).unwrap_or(Ipv4Addr::new(255, 255, 255, 0)) })
+}
+
+#[cfg(target_os = "linux")]
+fn netmask(address: Ipv4Addr) -> Option<Ipv4Addr> {
+    let mut interfaces: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut interfaces) } != 0 {
+        return None;
+    }
+    let mut netmask = None;
+    let mut current = interfaces;
+    while let Some(interface) = unsafe { current.as_ref() } {
+        let ipv4 = |sockaddr: *const libc::sockaddr| match unsafe { sockaddr.as_ref() } {
+            Some(sockaddr) if sockaddr.sa_family as i32 == libc::AF_INET => {
+                let sockaddr = unsafe { &*(sockaddr as *const libc::sockaddr as *const libc::sockaddr_in) };
+                Some(Ipv4Addr::from(u32::from_be(sockaddr.sin_addr.s_addr)))
+            }
+            _ => None,
+        };
+        if ipv4(interface.ifa_addr) == Some(address) {
+            netmask = ipv4(interface.ifa_netmask);
+            break;
+        }
+        current = interface.ifa_next;
+    }
+    unsafe { libc::freeifaddrs(interfaces) };
+    netmask
+}
+
+#[cfg(not(target_os = "linux"))]
+fn netmask(_: Ipv4Addr) -> Option<Ipv4Addr> {
+    None
+}
+
+// Answers are "<version> relay", sent from the port the relay listens on
+fn broadcast_probe(network: Network, ports: &[u16]) -> io::Result<Vec<DiscoveredConsole>> {
+    let socket = UdpSocket::bind((network.address, 0))?;
+    socket.set_broadcast(true)?;
+    for port in ports {
+        socket.send_to(PROBE, (network.broadcast(), *port))?;
+    }
+
+    let mut consoles: Vec<DiscoveredConsole> = Vec::new();
+    let mut buf = [0u8; 256];
+    let deadline = Instant::now() + PROBE_WAIT;
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+        socket.set_read_timeout(Some(left))?;
+        let Ok((size, address)) = socket.recv_from(&mut buf) else {
+            break;
+        };
+        let answer = String::from_utf8_lossy(&buf[..size]);
+        let version = answer.split_whitespace().next().unwrap_or_default();
+        if !version.is_empty() && consoles.iter().all(|console| console.address != address) {
+            consoles.push(DiscoveredConsole { address, version: version.to_string(), title_id: "unknown".to_string(), relay: true });
+        }
+    }
+    Ok(consoles)
+}
+
+fn query(stream: &mut BufReader<Stream>, command: &str) -> Option<String> {
+    stream.get_mut().write_all(format!("{}\r\n", command).as_bytes()).ok()?;
+    let mut line = String::new();
+    stream.read_line(&mut line).ok()?;
+    let line = line.trim();
+    if line.is_empty() {
+        None
+    } else {
+        Some(line.to_string())
+    }
+}
+
+// Goes through TLS and the relay challenge when they are configured, as the connection itself would
+fn probe(address: SocketAddr) -> Option<DiscoveredConsole> {
+    let socket = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).ok()?;
+    socket.set_write_timeout(Some(RESPONSE_TIMEOUT)).ok()?;
+    let mut stream = BufReader::new(tls::wrap(socket).ok()?);
+    auth::authenticate(&mut stream).ok()?;
+    stream.get_ref().tcp().set_read_timeout(Some(RESPONSE_TIMEOUT)).ok()?;
+
+    let version = query(&mut stream, "getVersion")?;
+    let title_id = query(&mut stream, "getTitleID").unwrap_or_else(|| "unknown".to_string());
+
+    Some(DiscoveredConsole { address, version, title_id, relay: false })
+}
+
+fn sweep(addresses: Vec<SocketAddr>) -> Vec<DiscoveredConsole> {
+    let next = AtomicUsize::new(0);
+    let found = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..SWEEP_THREADS.min(addresses.len()) {
+            scope.spawn(|| {
+                while let Some(address) = addresses.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if let Some(console) = probe(*address) {
+                        found.lock().unwrap().push(console);
+                    }
+                }
+            });
+        }
+    });
+    let mut found = found.into_inner().unwrap();
+    found.sort_by_key(|console| console.address);
+    found
+}
+
+// Relays answer the broadcast probe, stock sys-botbase only a connection, so the hosts left are swept
+pub fn discover_consoles(port: u16) -> Vec<DiscoveredConsole> {
+    if let Some(consoles) = FOUND.lock().unwrap().get(&port) {
+        return consoles.clone();
+    }
+    let Some(network) = local_network() else {
+        return Vec::new();
+    };
+
+    let mut consoles = broadcast_probe(network, &[port, relay::DEFAULT_PORT]).unwrap_or_default();
+    let addresses = network.hosts().into_iter()
+        .map(|host| SocketAddr::from((host, port)))
+        .filter(|address| consoles.iter().all(|console| console.address != *address))
+        .collect();
+    consoles.extend(sweep(addresses));
+    // The consoles themselves first, the relays after them
+    consoles.sort_by_key(|console| (console.relay, console.address));
+    FOUND.lock().unwrap().insert(port, consoles.clone());
+    consoles
+}
@@ -0,0 +1,165 @@
+use crate::command::{Command, BUTTONS, STICKS};
+use crate::health::SendOutcome;
+use crate::transport::Transport;
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+fn validate(command: &Command) -> Result<(), String> {
+    match command {
+        Command::CLICK(button) | Command::PRESS(button) | Command::RELEASE(button) if !BUTTONS.contains(&button.as_ref()) => {
+            return Err(format!("unknown button {}", button));
+        }
+        Command::SETSTICK(stick, _, _) if !STICKS.contains(&stick.as_ref()) => {
+            return Err(format!("unknown stick {}", stick));
+        }
+        Command::SETSTICK(_, x, y) if !(-0x8000..=0x7FFF).contains(x) || !(-0x8000..=0x7FFF).contains(y) => {
+            return Err(format!("stick value ({}, {}) out of range", x, y));
+        }
+        _ => {}
+    }
+
+    // The text sys-botbase would receive has to read back as the same command
+    match command.to_string().parse::<Command>() {
+        Ok(parsed) if parsed == *command => Ok(()),
+        _ => Err(format!("\"{}\" doesn't round-trip", command)),
+    }
+}
+
+// Stands in for a switch, logging and checking every packet instead of sending it
+pub struct DryRun {
+    packets: u64,
+    // Read back by tests once a router owns the transport
+    #[cfg(test)]
+    pub(crate) sent: Arc<Mutex<Vec<Command>>>,
+}
+
+impl DryRun {
+    pub fn new() -> DryRun {
+        info!("Dry run, packets are only logged");
+        DryRun {
+            packets: 0,
+            #[cfg(test)]
+            sent: Arc::default(),
+        }
+    }
+}
+
+impl Default for DryRun {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for DryRun {
+    fn describe(&self) -> String {
+        "dry run".to_string()
+    }
+
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        for command in commands {
+            self.packets += 1;
+            #[cfg(test)]
+            self.sent.lock().unwrap().push(command.clone());
+            match validate(command) {
+                Ok(()) => info!("[dry-run #{}] {}", self.packets, command),
+                Err(err) => {
+                    warn!("[dry-run #{}] invalid packet: {}", self.packets, err);
+                    outcome.failures += 1;
+                }
+            }
+        }
+        outcome
+    }
+
+    fn runs_sequences(&self) -> bool {
+        true
+    }
+
+    fn try_send_commands(&mut self, commands: &[Command]) -> Result<(), String> {
+        let outcome = self.send_commands(commands);
+        match outcome.failures {
+            0 => Ok(()),
+            failures => Err(format!("{} invalid packet(s)", failures)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Source;
+    use crate::controller::{Button, ControllerState};
+    use crate::router::Router;
+    #[cfg(feature = "gamepad")]
+    use crate::controller::BTN_ASSOCIATION;
+
+    fn send(commands: &[Command]) -> Result<(), String> {
+        DryRun::new().try_send_commands(commands)
+    }
+
+    fn commands(text: &str) -> Vec<Command> {
+        text.split(';').map(|command| command.parse().unwrap()).collect()
+    }
+
+    fn routed() -> (Router, Arc<Mutex<Vec<Command>>>) {
+        let dry_run = DryRun::new();
+        let sent = dry_run.sent.clone();
+        (Router::single(Box::new(dry_run)), sent)
+    }
+
+    #[test]
+    #[cfg(feature = "gamepad")]
+    fn controller_packets_are_valid() {
+        let mut state = ControllerState::new();
+        for button in BTN_ASSOCIATION.fwd().keys() {
+            state.press(*button);
+        }
+        state.l_stick = (-0x8000, 0x7FFF);
+        state.r_stick = (12000, -5000);
+
+        let packets = state.make_packets();
+        assert_eq!(packets.len(), 20);
+        assert_eq!(send(&packets), Ok(()));
+    }
+
+    #[test]
+    fn button_transitions_produce_expected_packets() {
+        let mut state = ControllerState::new();
+        state.press(Button::A);
+        assert_eq!(state.make_packets(), vec![Command::PRESS("A".into())]);
+
+        state.release(Button::A);
+        assert_eq!(state.make_packets(), vec![Command::CLICK("A".into())]);
+    }
+
+    #[test]
+    fn invalid_packets_are_reported() {
+        assert!(send(&[Command::CLICK("START".into())]).is_err());
+        assert!(send(&[Command::SETSTICK("MIDDLE".into(), 0, 0)]).is_err());
+        assert!(send(&[Command::SETSTICK("LEFT".into(), 0x8000, 0)]).is_err());
+        assert!(send(&[Command::PRESS("A".into()), Command::OTHER("getVersion".to_string())]).is_ok());
+    }
+
+    #[test]
+    fn the_router_only_sends_what_changes() {
+        let (mut router, sent) = routed();
+        router.send(Source::GAMEPAD, 0, commands("press A;setStick LEFT 0x100 0"));
+        router.send(Source::GAMEPAD, 0, commands("press A;setStick LEFT 0x100 0;press B"));
+        router.send(Source::MACRO, 0, commands("press X;release X"));
+
+        assert_eq!(*sent.lock().unwrap(), commands("press A;setStick LEFT 0x100 0;press B;click X"));
+    }
+
+    #[test]
+    fn the_router_releases_what_is_held_and_detaches() {
+        let (mut router, sent) = routed();
+        router.send(Source::GAMEPAD, 0, commands("press B;press A;setStick RIGHT 0 0x100;release B"));
+        sent.lock().unwrap().clear();
+
+        router.release_all();
+        router.detach();
+        assert_eq!(*sent.lock().unwrap(), commands("release A;setStick RIGHT 0 0;detachController"));
+    }
+}
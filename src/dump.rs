@@ -0,0 +1,47 @@
+use crate::memory::{self, Region};
+use crate::router::Router;
+use crate::shutdown;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+// Small enough for a single peek reply over every link, hex doubles it over TCP
+pub const DEFAULT_CHUNK: usize = 0x4000;
+
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("Invalid size {}", s))
+}
+
+// An existing file is taken as the start of the same dump and resumed where it stops
+pub fn dump(router: &mut Router, region: Region, offset: u64, size: usize, path: &Path, chunk: usize) -> Result<(), String> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path).map_err(|err| err.to_string())?;
+    let mut done = file.metadata().map_err(|err| err.to_string())?.len() as usize;
+    if done > size {
+        return Err(format!("{} is already bigger than {} bytes", path.display(), size));
+    }
+    if done > 0 {
+        println!("Resuming at 0x{:X}, {} bytes already dumped", offset + done as u64, done);
+    }
+
+    while done < size {
+        if shutdown::requested() {
+            println!();
+            return Err(format!("Stopped at 0x{:X}, run the same dump again to resume", offset + done as u64));
+        }
+        let length = chunk.min(size - done);
+        let bytes = memory::peek(router, region, offset + done as u64, length)
+            .map_err(|err| format!("{} at 0x{:X}, run the same dump again to resume", err, offset + done as u64))?;
+        file.write_all(&bytes).map_err(|err| err.to_string())?;
+        done += length;
+
+        print!("\rDumped {}/{} bytes ({}%)", done, size, done * 100 / size);
+        let _ = io::stdout().flush();
+        router.heartbeat();
+    }
+    println!();
+    Ok(())
+}
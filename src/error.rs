@@ -0,0 +1,77 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use thiserror::Error;
+
+// Why a link couldn't be opened or a file loaded, the callers decide whether to retry, skip or give up
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Unable to watch for USB devices ({0})")]
+    WATCH(#[source] io::Error),
+    #[error("Unable to open the USB switch ({0})")]
+    USB(#[source] io::Error),
+    #[error("Cannot connect to switch at {address} ({source})")]
+    TCP {
+        address: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Cannot open serial port {path} ({source})")]
+    SERIAL {
+        path: String,
+        #[source]
+        source: serialport::Error,
+    },
+    #[error("Cannot open UDP socket for sys-hidplus ({0})")]
+    HIDPLUS(#[source] io::Error),
+    #[error("Cannot emulate a controller over Bluetooth ({0})")]
+    BLUETOOTH(#[source] io::Error),
+    #[error("Cannot set up the USB gadget ({0})")]
+    GADGET(#[source] io::Error),
+    #[error("No link to the switch was chosen")]
+    NOLINK,
+    #[error("Cannot connect to switch over USB nor internet")]
+    UNREACHABLE,
+    #[error("Invalid target {0}")]
+    TARGET(String),
+    #[error("Unable to load {} ({reason})", .path.display())]
+    LOAD { path: PathBuf, reason: String },
+    #[error("Invalid config file {} ({reason})", .path.display())]
+    CONFIG { path: PathBuf, reason: String },
+    #[error("{0}")]
+    TEMPLATE(String),
+    #[error("WebRTC failed ({0})")]
+    WEBRTC(String),
+    #[error("Unable to open the window ({0})")]
+    GUI(String),
+    #[error("Cannot resolve {host} ({reason})")]
+    RESOLVE { host: String, reason: String },
+    #[error("Cannot listen on {address} ({source})")]
+    LISTEN {
+        address: SocketAddr,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Unable to create {} ({source})", .path.display())]
+    CREATE {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Cannot set up TLS ({0})")]
+    TLS(String),
+    #[error("Invalid {what} ({reason})")]
+    INVALID { what: String, reason: String },
+    #[error("Unable to open the dashboard ({0})")]
+    DASHBOARD(#[source] io::Error),
+    #[error("Unable to open the log file {} ({source})", .path.display())]
+    LOG {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("No {what} given ({reason})")]
+    PROMPT { what: String, reason: String },
+    #[error("This build has no USB support, rebuild it with the usb feature")]
+    NOUSB,
+}
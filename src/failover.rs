@@ -0,0 +1,160 @@
+use crate::command::Command;
+use crate::connection::{Tcp, UsbDevice};
+use crate::error::Error;
+use crate::health::SendOutcome;
+use crate::held::HeldState;
+use crate::notify;
+use crate::response::Response;
+use crate::transport::Transport;
+use crate::usb::Usb;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const USB_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+const TCP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// USB while it works, TCP once it's lost. Either can be missing at first, it's added once reachable
+pub struct Failover {
+    device: UsbDevice,
+    address: SocketAddr,
+    usb: Option<Usb>,
+    tcp: Option<Tcp>,
+    held: HeldState,
+    last_usb_check: Instant,
+    last_tcp_check: Instant,
+}
+
+impl Failover {
+    pub fn new(device: UsbDevice, address: SocketAddr) -> Result<Failover, Error> {
+        let usb = Usb::try_connect(&device);
+        let tcp = Tcp::try_connect(address);
+        match (&usb, &tcp) {
+            (None, None) => return Err(Error::UNREACHABLE),
+            (None, Some(_)) => warn!("No USB link available, starting on TCP"),
+            (Some(_), None) => warn!("Switch at {} unreachable, it will be failed over to once reachable", address),
+            (Some(_), Some(_)) => {}
+        }
+
+        Ok(Failover {
+            device,
+            address,
+            usb,
+            tcp,
+            held: HeldState::default(),
+            last_usb_check: Instant::now(),
+            last_tcp_check: Instant::now(),
+        })
+    }
+
+    fn try_failback(&mut self) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        if self.usb.is_some() || self.last_usb_check.elapsed() < USB_CHECK_INTERVAL {
+            return outcome;
+        }
+
+        self.last_usb_check = Instant::now();
+        if let Some(mut usb) = Usb::try_connect(&self.device) {
+            if usb.try_send_commands(&self.held.resync_packets()).is_ok() {
+                info!("USB link is back, switching from TCP to USB");
+                notify::send(notify::SWITCH_BACK, "The USB link is back, switching from TCP to USB");
+                self.usb = Some(usb);
+                outcome.reconnects += 1;
+            }
+        }
+        outcome
+    }
+
+    // Kept ready while USB carries the inputs, so failing over doesn't wait on connecting
+    fn try_restore_tcp(&mut self) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        if self.tcp.is_some() || self.last_tcp_check.elapsed() < TCP_CHECK_INTERVAL {
+            return outcome;
+        }
+
+        self.last_tcp_check = Instant::now();
+        if let Some(tcp) = Tcp::try_connect(self.address) {
+            info!("Switch at {} is reachable again", self.address);
+            self.tcp = Some(tcp);
+            outcome.reconnects += 1;
+        }
+        outcome
+    }
+}
+
+impl Transport for Failover {
+    fn describe(&self) -> String {
+        match (&self.usb, &self.tcp) {
+            (Some(usb), Some(tcp)) => format!("{}, failing over to the {}", usb.describe(), tcp.describe()),
+            (Some(usb), None) => format!("{}, failing over to the switch at {} once reachable", usb.describe(), self.address),
+            (None, Some(tcp)) => format!("{} until USB is back", tcp.describe()),
+            (None, None) => format!("USB switch {} or switch at {}, both unreachable", self.device, self.address),
+        }
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        match (&mut self.usb, &mut self.tcp) {
+            (Some(usb), _) => usb.reconnect(),
+            (None, Some(tcp)) => tcp.reconnect(),
+            (None, None) => Err("No link to the switch available".to_string()),
+        }
+    }
+
+    // Only the link carrying the inputs has to be kept alive
+    fn heartbeat(&mut self) -> SendOutcome {
+        match (&mut self.usb, &mut self.tcp) {
+            (Some(usb), _) => usb.heartbeat(),
+            (None, Some(tcp)) => tcp.heartbeat(),
+            (None, None) => SendOutcome::default(),
+        }
+    }
+
+    // Either link may carry the sequence
+    fn runs_sequences(&self) -> bool {
+        self.tcp.as_ref().is_none_or(|tcp| tcp.runs_sequences())
+    }
+
+    fn request(&mut self, command: &Command) -> Result<Response, String> {
+        match (&mut self.usb, &mut self.tcp) {
+            (Some(usb), _) => usb.request(command),
+            (None, Some(tcp)) => tcp.request(command),
+            (None, None) => Err("No link to the switch available".to_string()),
+        }
+    }
+
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        commands.iter().for_each(|command| self.held.observe(command));
+        let mut outcome = self.try_failback().merge(self.try_restore_tcp());
+
+        let mut resync = false;
+        if let Some(usb) = &mut self.usb {
+            match usb.try_send_commands(commands) {
+                Ok(()) => return outcome,
+                Err(err) => {
+                    warn!("USB link lost ({}), failing over to TCP", err);
+                    notify::send(notify::SWITCH_LOST, &format!("Lost the USB link ({}), failing over to TCP", err));
+                    self.usb = None;
+                    self.last_usb_check = Instant::now();
+                    outcome.failures += 1;
+                    resync = true;
+                }
+            }
+        }
+
+        let Some(tcp) = &mut self.tcp else {
+            warn!("No link to the switch available, dropping inputs");
+            outcome.failures += 1;
+            return outcome;
+        };
+        if resync {
+            outcome = outcome.merge(tcp.send_commands(&self.held.resync_packets()));
+        }
+        let sent = tcp.send_commands(commands);
+        // Gave up reconnecting, it's connected again from scratch once reachable
+        if sent.failures > sent.reconnects {
+            self.tcp = None;
+            self.last_tcp_check = Instant::now();
+        }
+        outcome.merge(sent)
+    }
+}
@@ -0,0 +1,158 @@
+use crate::command::Command;
+use crate::response::Response;
+use crate::router::Router;
+use jpeg_decoder::{Decoder, PixelFormat};
+#[cfg(feature = "scripting")]
+use std::fs::File;
+#[cfg(feature = "scripting")]
+use std::path::Path;
+#[cfg(feature = "scripting")]
+use std::thread;
+#[cfg(feature = "scripting")]
+use std::time::{Duration, Instant};
+
+// pixelPeek is slow, polling faster only stalls the link
+#[cfg(feature = "scripting")]
+const PIXEL_POLL: Duration = Duration::from_millis(250);
+
+// Pixels are 0x00RRGGBB, row by row from the top left corner
+pub struct Frame {
+    pub pixels: Vec<u32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Frame {
+    pub fn decode(jpeg: &[u8]) -> Result<Frame, String> {
+        let mut decoder = Decoder::new(jpeg);
+        let pixels = decoder.decode().map_err(|err| err.to_string())?;
+        let info = decoder.info().ok_or_else(|| "Missing JPEG header".to_string())?;
+        let pixels = match info.pixel_format {
+            PixelFormat::RGB24 => pixels.chunks_exact(3)
+                .map(|rgb| u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]))
+                .collect(),
+            PixelFormat::L8 => pixels.iter().map(|l| u32::from_be_bytes([0, *l, *l, *l])).collect(),
+            format => return Err(format!("Unsupported JPEG pixel format {:?}", format)),
+        };
+        Ok(Frame { pixels, width: info.width as usize, height: info.height as usize })
+    }
+
+    pub fn grab(router: &mut Router) -> Result<Frame, String> {
+        match router.request(&Command::OTHER("pixelPeek".to_string()))? {
+            Response::BYTES(jpeg) => Frame::decode(&jpeg),
+            _ => Err("pixelPeek didn't answer with an image".to_string()),
+        }
+    }
+
+    #[cfg(feature = "scripting")]
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u32> {
+        (x < self.width && y < self.height).then(|| self.pixels[y * self.width + x])
+    }
+}
+
+// Each of red, green and blue may be off by the tolerance, JPEG never gives back exact colors
+#[cfg(feature = "scripting")]
+pub fn close_to(color: u32, expected: u32, tolerance: u8) -> bool {
+    color.to_be_bytes().iter()
+        .zip(expected.to_be_bytes())
+        .skip(1)
+        .all(|(channel, expected)| channel.abs_diff(expected) <= tolerance)
+}
+
+// Polls frames until the pixel gets the color, false once the timeout passes
+#[cfg(feature = "scripting")]
+pub fn wait_for_pixel(router: &mut Router, x: usize, y: usize, color: u32, tolerance: u8, timeout: Option<Duration>) -> Result<bool, String> {
+    let start = Instant::now();
+    loop {
+        let frame = Frame::grab(router)?;
+        let pixel = frame.pixel(x, y)
+            .ok_or_else(|| format!("{},{} is outside the {}x{} screen", x, y, frame.width, frame.height))?;
+        if close_to(pixel, color, tolerance) {
+            return Ok(true);
+        }
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            return Ok(false);
+        }
+        router.heartbeat();
+        thread::sleep(PIXEL_POLL);
+    }
+}
+
+// Transparent pixels of the reference image match anything
+#[cfg(feature = "scripting")]
+pub struct Template {
+    pixels: Vec<Option<u32>>,
+    width: usize,
+    height: usize,
+}
+
+#[cfg(feature = "scripting")]
+impl Template {
+    pub fn load(path: &Path) -> Result<Template, String> {
+        let file = File::open(path).map_err(|err| format!("Unable to open {}: {}", path.display(), err))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|err| err.to_string())?;
+        let buffer = &buffer[..info.buffer_size()];
+        let pixels = match info.color_type {
+            png::ColorType::Rgb => buffer.chunks_exact(3).map(|rgb| Some(u32::from_be_bytes([0, rgb[0], rgb[1], rgb[2]]))).collect(),
+            png::ColorType::Rgba => buffer.chunks_exact(4)
+                .map(|rgba| (rgba[3] >= 0x80).then(|| u32::from_be_bytes([0, rgba[0], rgba[1], rgba[2]])))
+                .collect(),
+            png::ColorType::Grayscale => buffer.iter().map(|l| Some(u32::from_be_bytes([0, *l, *l, *l]))).collect(),
+            png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2)
+                .map(|la| (la[1] >= 0x80).then(|| u32::from_be_bytes([0, la[0], la[0], la[0]])))
+                .collect(),
+            color => return Err(format!("Unsupported PNG color type {:?}", color)),
+        };
+        Ok(Template { pixels, width: info.width as usize, height: info.height as usize })
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn difference(a: u32, b: u32) -> u32 {
+    a.to_be_bytes().iter().zip(b.to_be_bytes()).map(|(a, b)| u32::from(a.abs_diff(b))).sum()
+}
+
+#[cfg(feature = "scripting")]
+impl Frame {
+    // Top left corner of the first place in the area where the template's colors are within the
+    // tolerance on average. A position is dropped as soon as it goes over, which keeps full
+    // screen searches affordable
+    pub fn find(&self, template: &Template, tolerance: u8, area: (usize, usize, usize, usize)) -> Option<(usize, usize)> {
+        let (left, top, width, height) = area;
+        let right = (left + width).min(self.width).checked_sub(template.width)?;
+        let bottom = (top + height).min(self.height).checked_sub(template.height)?;
+        let opaque: Vec<(usize, usize, u32)> = template.pixels.iter()
+            .enumerate()
+            .filter_map(|(index, pixel)| pixel.map(|pixel| (index % template.width, index / template.width, pixel)))
+            .collect();
+        let budget = u32::from(tolerance) * 3 * opaque.len() as u32;
+
+        (top..=bottom).flat_map(|y| (left..=right).map(move |x| (x, y))).find(|(x, y)| {
+            let mut total = 0;
+            opaque.iter().all(|(dx, dy, pixel)| {
+                total += difference(self.pixels[(y + dy) * self.width + x + dx], *pixel);
+                total <= budget
+            })
+        })
+    }
+}
+
+#[cfg(feature = "scripting")]
+pub fn wait_for_template(router: &mut Router, template: &Template, tolerance: u8, timeout: Option<Duration>) -> Result<Option<(usize, usize)>, String> {
+    let start = Instant::now();
+    loop {
+        let frame = Frame::grab(router)?;
+        if let Some(found) = frame.find(template, tolerance, (0, 0, frame.width, frame.height)) {
+            return Ok(Some(found));
+        }
+        if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+            return Ok(None);
+        }
+        router.heartbeat();
+        thread::sleep(PIXEL_POLL);
+    }
+}
@@ -0,0 +1,111 @@
+use crate::command::Command;
+use crate::config::{FreezeSettings, Source};
+use crate::error::Error;
+use crate::hex;
+use crate::memory;
+use crate::response::Response;
+use crate::router::Router;
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::{info, warn};
+
+#[derive(Clone, Debug)]
+struct Freeze {
+    address: u64,
+    value: Vec<u8>,
+}
+
+// sys-botbase keeps rewriting frozen heap values on its own, even after this tool exits
+pub struct Freezes {
+    named: BTreeMap<String, Freeze>,
+    frozen: BTreeSet<u64>,
+}
+
+impl Freezes {
+    pub fn new(settings: &BTreeMap<String, FreezeSettings>) -> Result<Freezes, Error> {
+        let named = settings.iter()
+            .map(|(name, freeze)| {
+                let invalid = |reason: String| Error::INVALID { what: format!("freeze {}", name), reason };
+                let address = memory::parse_address(&freeze.address).map_err(invalid)?;
+                let value = memory::parse_data(&freeze.value).map_err(invalid)?;
+                Ok((name.clone(), Freeze { address, value }))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Freezes { named, frozen: BTreeSet::new() })
+    }
+
+    // A name of the config, or an address which then needs a value to freeze
+    fn lookup(&self, target: &str, value: Option<&str>) -> Result<Freeze, String> {
+        if let Some(freeze) = self.named.get(target) {
+            return Ok(freeze.clone());
+        }
+
+        let address = memory::parse_address(target).map_err(|_| format!("No freeze named {}", target))?;
+        let value = match value {
+            Some(value) => memory::parse_data(value)?,
+            None => Vec::new(),
+        };
+        Ok(Freeze { address, value })
+    }
+
+    pub fn freeze(&mut self, router: &mut Router, target: &str, value: Option<&str>) -> Result<(), String> {
+        let freeze = self.lookup(target, value)?;
+        if freeze.value.is_empty() {
+            return Err(format!("Freezing 0x{:X} needs a value", freeze.address));
+        }
+        self.freeze_at(router, freeze.address, &freeze.value);
+        info!("Froze {}", target);
+        Ok(())
+    }
+
+    pub fn freeze_at(&mut self, router: &mut Router, address: u64, value: &[u8]) {
+        let command = format!("freeze 0x{:X} 0x{}", address, hex::encode(value));
+        router.send(Source::MACRO, 0, vec![Command::OTHER(command)]);
+        self.frozen.insert(address);
+    }
+
+    pub fn unfreeze(&mut self, router: &mut Router, target: &str) -> Result<(), String> {
+        let freeze = self.lookup(target, None)?;
+        self.unfreeze_at(router, freeze.address);
+        info!("Unfroze {}", target);
+        Ok(())
+    }
+
+    pub fn unfreeze_at(&mut self, router: &mut Router, address: u64) {
+        router.send(Source::MACRO, 0, vec![Command::OTHER(format!("unFreeze 0x{:X}", address))]);
+        self.frozen.remove(&address);
+    }
+
+    // Only freezes made during this session are known to be active
+    pub fn toggle(&mut self, router: &mut Router, target: &str) -> Result<(), String> {
+        let freeze = self.lookup(target, None)?;
+        if self.frozen.contains(&freeze.address) {
+            self.unfreeze(router, target)
+        } else {
+            self.freeze(router, target, None)
+        }
+    }
+
+    pub fn clear(&mut self, router: &mut Router) {
+        router.send(Source::MACRO, 0, vec![Command::OTHER("freezeClear".to_string())]);
+        self.frozen.clear();
+        info!("Cleared every freeze");
+    }
+
+    pub fn count(router: &mut Router) -> Result<u64, String> {
+        match router.request(&Command::OTHER("freezeCount".to_string()))? {
+            Response::TEXT(count) => count.parse().map_err(|_| format!("Invalid freeze count {}", count)),
+            response => Err(format!("Unexpected response {}", response)),
+        }
+    }
+
+    pub fn list(&self, router: &mut Router) {
+        for (name, freeze) in &self.named {
+            let state = if self.frozen.contains(&freeze.address) { "frozen" } else { "not frozen by this session" };
+            println!("{}: 0x{:X} = 0x{} ({})", name, freeze.address, hex::encode(&freeze.value), state);
+        }
+        match Freezes::count(router) {
+            Ok(count) => println!("{} address(es) frozen on the switch", count),
+            Err(err) => warn!("Unable to count the freezes on the switch ({})", err),
+        }
+    }
+}
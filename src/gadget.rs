@@ -0,0 +1,240 @@
+use crate::command::Command;
+use crate::health::SendOutcome;
+use crate::transport::Transport;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+const GADGET_PATH: &str = "/sys/kernel/config/usb_gadget/switch-usb-control";
+const UDC_PATH: &str = "/sys/class/udc";
+const HIDG_DEVICE: &str = "/dev/hidg0";
+
+// HORIPAD S, the console accepts it as a wired controller without any handshake
+const VENDOR_ID: &str = "0x0f0d";
+const PRODUCT_ID: &str = "0x0092";
+const REPORT_LENGTH: usize = 8;
+const REPORT_DESCRIPTOR: [u8; 86] = [
+    0x05, 0x01, 0x09, 0x05, 0xA1, 0x01, 0x15, 0x00, 0x25, 0x01, 0x35, 0x00, 0x45, 0x01, 0x75, 0x01,
+    0x95, 0x10, 0x05, 0x09, 0x19, 0x01, 0x29, 0x10, 0x81, 0x02, 0x05, 0x01, 0x25, 0x07, 0x46, 0x3B,
+    0x01, 0x75, 0x04, 0x95, 0x01, 0x65, 0x14, 0x09, 0x39, 0x81, 0x42, 0x65, 0x00, 0x95, 0x01, 0x81,
+    0x01, 0x26, 0xFF, 0x00, 0x46, 0xFF, 0x00, 0x09, 0x30, 0x09, 0x31, 0x09, 0x32, 0x09, 0x35, 0x75,
+    0x08, 0x95, 0x04, 0x81, 0x02, 0x06, 0x00, 0xFF, 0x09, 0x20, 0x95, 0x01, 0x81, 0x02, 0x0A, 0x21,
+    0x26, 0x95, 0x08, 0x91, 0x02, 0xC0,
+];
+
+const HAT_NEUTRAL: u8 = 8;
+const CLICK_DURATION: Duration = Duration::from_millis(50);
+
+fn button_bit(button: &str) -> Option<u16> {
+    let bit = match button {
+        "Y" => 0,
+        "B" => 1,
+        "A" => 2,
+        "X" => 3,
+        "L" => 4,
+        "R" => 5,
+        "ZL" => 6,
+        "ZR" => 7,
+        "MINUS" => 8,
+        "PLUS" => 9,
+        "LSTICK" => 10,
+        "RSTICK" => 11,
+        "HOME" => 12,
+        "CAPTURE" => 13,
+        _ => return None,
+    };
+    Some(1 << bit)
+}
+
+fn axis_byte(value: i32) -> u8 {
+    ((value + 0x8000) >> 8).clamp(0, 0xFF) as u8
+}
+
+fn write_attribute(path: &Path, value: impl AsRef<[u8]>) -> io::Result<()> {
+    fs::write(path, value).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+}
+
+fn find_udc() -> io::Result<String> {
+    fs::read_dir(UDC_PATH)?
+        .flatten()
+        .next()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No USB device controller, is dwc2 loaded?"))
+}
+
+// libcomposite gadget with a single HID function, bound to the first device controller
+fn create_gadget() -> io::Result<PathBuf> {
+    if !cfg!(target_os = "linux") {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "USB gadgets are only available on Linux"));
+    }
+
+    let gadget = PathBuf::from(GADGET_PATH);
+    if gadget.exists() {
+        let _ = write_attribute(&gadget.join("UDC"), "\n");
+    } else {
+        fs::create_dir(&gadget)?;
+    }
+
+    write_attribute(&gadget.join("idVendor"), VENDOR_ID)?;
+    write_attribute(&gadget.join("idProduct"), PRODUCT_ID)?;
+    write_attribute(&gadget.join("bcdDevice"), "0x0100")?;
+    write_attribute(&gadget.join("bcdUSB"), "0x0200")?;
+
+    let strings = gadget.join("strings/0x409");
+    fs::create_dir_all(&strings)?;
+    write_attribute(&strings.join("manufacturer"), "HORI CO.,LTD.")?;
+    write_attribute(&strings.join("product"), "HORIPAD S")?;
+    write_attribute(&strings.join("serialnumber"), "0")?;
+
+    let function = gadget.join("functions/hid.usb0");
+    fs::create_dir_all(&function)?;
+    write_attribute(&function.join("protocol"), "0")?;
+    write_attribute(&function.join("subclass"), "0")?;
+    write_attribute(&function.join("report_length"), REPORT_LENGTH.to_string())?;
+    write_attribute(&function.join("report_desc"), REPORT_DESCRIPTOR)?;
+
+    let config = gadget.join("configs/c.1");
+    fs::create_dir_all(config.join("strings/0x409"))?;
+    write_attribute(&config.join("strings/0x409/configuration"), "HORIPAD")?;
+    write_attribute(&config.join("MaxPower"), "500")?;
+    if !config.join("hid.usb0").exists() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&function, config.join("hid.usb0"))?;
+    }
+
+    write_attribute(&gadget.join("UDC"), find_udc()?)?;
+    Ok(gadget)
+}
+
+pub struct Gadget {
+    gadget: PathBuf,
+    hidg: File,
+    buttons: u16,
+    dpad: [bool; 4],
+    left_stick: (i32, i32),
+    right_stick: (i32, i32),
+}
+
+impl Gadget {
+    pub fn create() -> io::Result<Gadget> {
+        let gadget = create_gadget()?;
+        println!("Presenting this machine as a HORIPAD, plug it into the dock");
+        let hidg = OpenOptions::new().write(true).open(HIDG_DEVICE)?;
+
+        Ok(Gadget {
+            gadget,
+            hidg,
+            buttons: 0,
+            dpad: [false; 4],
+            left_stick: (0, 0),
+            right_stick: (0, 0),
+        })
+    }
+
+    fn dpad_index(button: &str) -> Option<usize> {
+        ["DUP", "DRIGHT", "DDOWN", "DLEFT"].iter().position(|b| *b == button)
+    }
+
+    fn set_button(&mut self, button: &str, pressed: bool) {
+        if let Some(index) = Gadget::dpad_index(button) {
+            self.dpad[index] = pressed;
+        } else if let Some(bit) = button_bit(button) {
+            if pressed {
+                self.buttons |= bit;
+            } else {
+                self.buttons &= !bit;
+            }
+        }
+    }
+
+    // The hat switch counts clockwise from up in eighths, 8 meaning centered
+    fn hat(&self) -> u8 {
+        let [up, right, down, left] = self.dpad;
+        match (up, right, down, left) {
+            (true, false, _, false) => 0,
+            (true, true, _, _) => 1,
+            (false, true, false, _) => 2,
+            (_, true, true, _) => 3,
+            (_, false, true, false) => 4,
+            (_, _, true, true) => 5,
+            (false, _, false, true) => 6,
+            (true, _, _, true) => 7,
+            _ => HAT_NEUTRAL,
+        }
+    }
+
+    fn report(&self) -> [u8; REPORT_LENGTH] {
+        let buttons = self.buttons.to_le_bytes();
+        [
+            buttons[0],
+            buttons[1],
+            self.hat(),
+            axis_byte(self.left_stick.0),
+            // The HORIPAD Y axis points down while sys-botbase's points up
+            axis_byte(-self.left_stick.1),
+            axis_byte(self.right_stick.0),
+            axis_byte(-self.right_stick.1),
+            0,
+        ]
+    }
+
+}
+
+impl Transport for Gadget {
+    fn describe(&self) -> String {
+        "dock over the USB gadget".to_string()
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.hidg = OpenOptions::new().write(true).open(HIDG_DEVICE).map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    // The dock may be asleep or unplugged, the next report will go through once it polls again
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        match self.try_send_commands(commands) {
+            Ok(()) => SendOutcome::default(),
+            Err(err) => {
+                warn!("Unable to send inputs to the dock ({})", err);
+                SendOutcome { failures: 1, ..SendOutcome::default() }
+            }
+        }
+    }
+
+    fn try_send_commands(&mut self, commands: &[Command]) -> Result<(), String> {
+        let mut clicked = Vec::new();
+        for command in commands {
+            match command {
+                Command::CLICK(button) => {
+                    self.set_button(button, true);
+                    clicked.push(button);
+                }
+                Command::PRESS(button) => self.set_button(button, true),
+                Command::RELEASE(button) => self.set_button(button, false),
+                Command::SETSTICK(stick, x, y) => match stick.as_ref() {
+                    "LEFT" => self.left_stick = (*x, *y),
+                    "RIGHT" => self.right_stick = (*x, *y),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        self.hidg.write_all(&self.report()).map_err(|err| err.to_string())?;
+
+        if !clicked.is_empty() {
+            thread::sleep(CLICK_DURATION);
+            clicked.iter().for_each(|button| self.set_button(button, false));
+            self.hidg.write_all(&self.report()).map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Gadget {
+    fn drop(&mut self) {
+        let _ = write_attribute(&self.gadget.join("UDC"), "\n");
+    }
+}
@@ -0,0 +1,48 @@
+use crate::command::Command;
+use crate::response::Response;
+use crate::router::Router;
+use crate::shutdown;
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const POLL: Duration = Duration::from_secs(1);
+
+// isProgramRunning answers 1 when the title is running, older sys-botbase builds lack it
+// so the running title ID is compared instead
+pub fn is_running(router: &mut Router, title: u64) -> Result<bool, String> {
+    match router.request(&Command::OTHER(format!("isProgramRunning 0x{:016X}", title))) {
+        Ok(Response::TEXT(running)) => return Ok(running == "1"),
+        Ok(response) => return Err(format!("Unexpected response {}", response)),
+        Err(_) => {}
+    }
+    match router.request(&Command::OTHER("getTitleID".to_string()))? {
+        Response::NUMBER(running) => Ok(running == title),
+        response => Err(format!("Unexpected response {}", response)),
+    }
+}
+
+// Blocks before any input so nothing lands in the HOME menu while the game starts.
+// A failed check is made again on the next poll, the link may be reconnecting
+pub fn wait_for(router: &mut Router, title: u64) {
+    let mut waiting = false;
+    let mut failing = false;
+    while !shutdown::requested() {
+        let check = is_running(router, title);
+        match &check {
+            Ok(true) => break,
+            Ok(false) if !waiting => {
+                info!("Waiting for title {:016X} to run", title);
+                waiting = true;
+            }
+            Err(err) if !failing => warn!("Unable to check the running title ({}), checking again", err),
+            _ => {}
+        }
+        failing = check.is_err();
+        router.heartbeat();
+        thread::sleep(POLL);
+    }
+    if waiting {
+        info!("Title {:016X} is running", title);
+    }
+}
@@ -0,0 +1,292 @@
+use crate::perform;
+use gilrs::EventType::Disconnected;
+use gilrs::{Axis, Event, EventType, GamepadId, Gilrs};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use switch_usb_controld::capture::{self, Playback};
+use switch_usb_controld::config::{Action, Binding, Source};
+use switch_usb_controld::controller::{get_axis_values, get_button_name, process_button_action, ControllerState, BTN_ASSOCIATION};
+use switch_usb_controld::freeze::Freezes;
+use switch_usb_controld::mash::{self, Mash};
+use switch_usb_controld::overrun::Overrun;
+use switch_usb_controld::router::Router;
+use switch_usb_controld::ticker::Ticker;
+use switch_usb_controld::{controller, macros, notify, shutdown};
+use tracing::{debug, error, info, warn};
+
+// Events are read on their own thread and queued in order, so a stalled link never
+// delays reading the gamepads. They are timed as they are read, for the input log
+fn read_gamepads() -> Result<Receiver<(Instant, Event)>, String> {
+    let (sender, receiver) = mpsc::channel();
+    let (ready, started) = mpsc::channel();
+    thread::spawn(move || {
+        let mut gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(err) => {
+                let _ = ready.send(Err(err.to_string()));
+                return;
+            }
+        };
+        let _ = ready.send(Ok(()));
+        loop {
+            if let Some(event) = gilrs.next_event_blocking(None) {
+                if sender.send((Instant::now(), event)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    started.recv().map_err(|err| err.to_string())??;
+    Ok(receiver)
+}
+
+// A reader thread that went away is noted in lost, so forwarding stops like on a disconnect
+fn next_event(events: &Receiver<(Instant, Event)>, until: Instant, lost: &mut bool) -> Option<(Instant, Event)> {
+    match events.recv_timeout(until.saturating_duration_since(Instant::now())) {
+        Ok(event) => Some(event),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => {
+            *lost = true;
+            None
+        }
+    }
+}
+
+// Only lasts until the tool stops, the printed binding can be pasted into the config to keep it
+fn bind(runtime: &mut Vec<Binding>, file: PathBuf, chord: Vec<String>) {
+    runtime.retain(|binding| binding.buttons != chord);
+    let buttons: Vec<String> = chord.iter().map(|button| format!("\"{}\"", button)).collect();
+    println!("{} now plays {}, to keep it add to the config:", chord.join("+"), file.display());
+    println!("[[bindings]]\nbuttons = [{}]\naction = \"play\"\nfile = \"{}\"", buttons.join(", "), file.display());
+    runtime.push(Binding { buttons: chord, action: Action::PLAY, freeze: None, file: Some(file), button: None, rate: None, speed: None });
+}
+
+// on_tick lets other features share the connection with the gamepad between ticks,
+// on_button sees each button press and release first and drops it by returning false
+pub fn forward_gamepads(
+    router: &mut Router,
+    wait_for: Duration,
+    bindings: &[Binding],
+    freezes: &mut Freezes,
+    on_tick: &mut dyn FnMut(&mut Router),
+    on_button: &mut dyn FnMut(&mut Router, &str, bool) -> bool,
+) {
+    // Checked up front so a mistyped file shows before its chord is pressed, the chord then only warns
+    for binding in bindings {
+        if let (Action::PLAY, Some(file)) = (binding.action, &binding.file) {
+            if let Err(err) = capture::load_macro(file) {
+                warn!("{} won't play anything ({})", binding.buttons.join("+"), err);
+            }
+        }
+    }
+    println!("Please connect and press a button on your controller");
+    let events = match read_gamepads() {
+        Ok(events) => events,
+        Err(err) => {
+            error!("Unable to read the gamepads ({})", err);
+            return;
+        }
+    };
+    let mut lost = false;
+    let max_gamepads = if router.routes_gamepads() { usize::MAX } else { 1 };
+    // With the buttons each one holds, which chords are checked against
+    let mut gamepads: Vec<(GamepadId, ControllerState, BTreeSet<&str>)> = Vec::new();
+    let mut exit = false;
+    let mut actions: Vec<Binding> = Vec::new();
+    let mut playback: Option<Playback> = None;
+    let mut mashing: Option<Mash> = None;
+    // Chords bound while playing, and the macro and chord of the binding being made
+    let mut runtime: Vec<Binding> = Vec::new();
+    let mut arming: Option<(PathBuf, Vec<String>)> = None;
+    let mut ticker = Ticker::new(wait_for);
+    let mut overrun = Overrun::new(wait_for);
+    let mut next_stick = Instant::now();
+    while !exit && !shutdown::requested() {
+        // Sleeps until a gamepad event, the next playback or mash input, held back stick update or tick
+        let sticks_pending = gamepads.iter().any(|(_, controller_state, _)| controller_state.sticks_moved());
+        let wakeups = [playback.as_ref().and_then(Playback::wakeup), mashing.as_ref().map(Mash::due), sticks_pending.then_some(next_stick)];
+        let wakeup = wakeups.into_iter().flatten().fold(ticker.deadline(), Instant::min);
+        let first = next_event(&events, wakeup, &mut lost);
+        // The pass starts once the wait is over, reading the events is its first phase
+        overrun.begin();
+        // Events already queued go out in the same batch, a quick press and release stays a click
+        let queued = std::iter::from_fn(|| next_event(&events, Instant::now(), &mut lost));
+        for (at, Event { id, event, .. }) in first.into_iter().chain(queued) {
+            let index = match gamepads.iter().position(|(gamepad_id, _, _)| *gamepad_id == id) {
+                Some(index) => index,
+                None if gamepads.len() < max_gamepads => {
+                    gamepads.push((id, ControllerState::new(), BTreeSet::new()));
+                    info!("Controller connected !");
+                    gamepads.len() - 1
+                }
+                None => {
+                    router.drop_event();
+                    debug!("Dropped {:?} from gamepad {}, only one gamepad is forwarded without routes", event, id);
+                    continue;
+                }
+            };
+            if let Some(input_log) = router.input_log() {
+                input_log.event(at, index, format!("{:?}", event));
+            }
+            let (_, controller_state, held) = &mut gamepads[index];
+
+            match event {
+                Disconnected => {
+                    warn!("Gamepad disconnected, stopping");
+                    notify::send(notify::GAMEPAD_LOST, "Stopped forwarding inputs to the switch");
+                    exit = true;
+                    break;
+                }
+                e => {
+                    match e {
+                        EventType::ButtonPressed(btn, _) => {
+                            let name = BTN_ASSOCIATION.get_rev(&btn).map(|button| get_button_name(*button)).unwrap_or_default();
+                            held.insert(name);
+                            if !on_button(router, name, true) {
+                                continue;
+                            }
+                            // The chord being bound doesn't reach the switch
+                            if let Some((_, chord)) = &mut arming {
+                                if !name.is_empty() && !chord.iter().any(|button| button == name) {
+                                    chord.push(name.to_string());
+                                }
+                                continue;
+                            }
+                            process_button_action(controller_state, &btn, true);
+                            actions.extend(bindings.iter().chain(&runtime)
+                                .filter(|binding| binding.buttons.iter().any(|button| button.eq_ignore_ascii_case(name)))
+                                .filter(|binding| binding.buttons.iter().all(|button| held.iter().any(|held| held.eq_ignore_ascii_case(button))))
+                                .cloned());
+                        }
+                        EventType::ButtonReleased(btn, _) => {
+                            let name = BTN_ASSOCIATION.get_rev(&btn).map(|button| get_button_name(*button)).unwrap_or_default();
+                            held.remove(name);
+                            if !on_button(router, name, false) {
+                                continue;
+                            }
+                            if arming.as_ref().is_some_and(|(_, chord)| chord.iter().any(|button| button == name)) {
+                                if let Some((file, chord)) = arming.take() {
+                                    bind(&mut runtime, file, chord);
+                                }
+                                continue;
+                            }
+                            process_button_action(controller_state, &btn, false);
+                        }
+                        EventType::AxisChanged(axis, value, _) => {
+                            match axis {
+                                Axis::LeftStickX => { controller_state.l_stick.0 = get_axis_values(value, controller::DEADZONE)}
+                                Axis::LeftStickY => { controller_state.l_stick.1 = get_axis_values(value, controller::DEADZONE) }
+                                Axis::RightStickX => { controller_state.r_stick.0 = get_axis_values(value, controller::DEADZONE)}
+                                Axis::RightStickY => { controller_state.r_stick.1 = get_axis_values(value, controller::DEADZONE)}
+                                _ => { }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        if lost {
+            error!("Stopped reading the gamepads, stopping");
+            notify::send(notify::GAMEPAD_LOST, "Stopped reading the gamepads, no longer forwarding inputs to the switch");
+            exit = true;
+        }
+        if exit {
+            break;
+        }
+        overrun.lap("events");
+
+        if let Some(mash) = &mut mashing {
+            mash.poll(router);
+        }
+        if let Some(running) = &mut playback {
+            running.poll(router);
+            if running.is_finished() {
+                info!("Replay finished");
+                router.set_running("replay", None);
+                playback = None;
+            }
+        }
+        overrun.lap("macros");
+        // Sent as soon as the events are read rather than at the next tick. Sticks moving alone
+        // wait for the link to keep up, with a button they come along in the same batch
+        let sticks_due = Instant::now() >= next_stick;
+        for (index, (_, controller_state, _)) in gamepads.iter_mut().enumerate() {
+            if !controller_state.buttons_changed() && !sticks_due {
+                continue;
+            }
+            let commands = controller_state.make_packets();
+            if !commands.is_empty() {
+                if controller_state.sticks_moved() {
+                    next_stick = Instant::now() + router.stick_interval();
+                }
+                router.send(Source::GAMEPAD, index, commands);
+            }
+            controller_state.next_tick();
+        }
+        overrun.lap("gamepads");
+        for binding in actions.drain(..) {
+            match (binding.action, &binding.file) {
+                (Action::PLAY, _) if playback.take().is_some() => {
+                    router.set_running("replay", None);
+                    info!("Stopped replaying")
+                }
+                (Action::PLAY, Some(file)) => match capture::load_macro(file) {
+                    Ok(instructions) => {
+                        let speed = binding.speed.unwrap_or(1.0).clamp(capture::MIN_SPEED, capture::MAX_SPEED);
+                        playback = Some(Playback::new(&instructions, router, speed));
+                        router.count_run("replay");
+                        router.set_running("replay", Some(format!("{} at {}x", file.display(), speed)));
+                    }
+                    Err(err) => warn!("{}", err),
+                },
+                (Action::PLAY, None) => warn!("The play action needs a macro file"),
+                (Action::MASH, _) if mashing.take().is_some() => {
+                    router.set_running("mash", None);
+                    info!("Stopped mashing")
+                }
+                (Action::MASH, _) => match macros::button(binding.button.as_deref().unwrap_or("A")) {
+                    Ok(button) => {
+                        let rate = binding.rate.unwrap_or(mash::DEFAULT_RATE).max(0.1);
+                        router.set_running("mash", Some(format!("{} {} times a second", button, rate)));
+                        router.count_run(&format!("mash {}", button));
+                        mashing = Some(Mash::new(button, rate));
+                    }
+                    Err(err) => warn!("{}", err),
+                },
+                (Action::PAUSE | Action::STEP, _) => match &mut playback {
+                    None => warn!("No macro is being replayed"),
+                    Some(running) if binding.action == Action::STEP => running.step(router),
+                    Some(running) if running.is_paused() => running.resume(),
+                    Some(running) => running.pause(),
+                },
+                (Action::BIND, _) if arming.take().is_some() => info!("Stopped binding"),
+                (Action::BIND, file) => match file.clone().or_else(|| router.last_recording().map(Path::to_path_buf)) {
+                    Some(file) => {
+                        println!("Press the buttons that should play {}", file.display());
+                        arming = Some((file, Vec::new()));
+                    }
+                    None => warn!("Record a macro first, or give the bind action a macro file"),
+                },
+                _ => perform(router, freezes, binding.action, Some(&binding)),
+            }
+        }
+
+        overrun.lap("actions");
+
+        // What doesn't come from the gamepads runs at the tick rate
+        if ticker.due() {
+            on_tick(router);
+            overrun.lap("tick");
+            router.poll_sources();
+            overrun.lap("sources");
+            router.heartbeat();
+            overrun.lap("heartbeat");
+        }
+        overrun.finish();
+    }
+}
@@ -0,0 +1,488 @@
+use crate::capture::{self, Playback};
+use crate::client::SwitchClient;
+use crate::command::{BUTTONS, STICKS};
+use crate::controller::{get_button_name, Button, ALL_BUTTONS, BTN_ASSOCIATION};
+use crate::discovery::{self, DiscoveredConsole};
+use crate::error::Error;
+use crate::logging;
+use crate::router::Router;
+use crate::ticker::Ticker;
+use crate::transport::{Target, BACKENDS};
+use eframe::egui::{self, Color32, RichText};
+use gilrs::{EventType, Gilrs};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// The window only shows what the session thread publishes, it redraws about as often
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const LOG_LINES: usize = 200;
+// Long enough to release and detach over a healthy link, a stuck one is left behind after that
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+const CLOSE_POLL: Duration = Duration::from_millis(20);
+
+// What a gamepad has to offer, Unknown stands for the buttons gilrs can't name
+const GAMEPAD_BUTTONS: [gilrs::Button; 20] = [
+    gilrs::Button::South, gilrs::Button::East, gilrs::Button::North, gilrs::Button::West,
+    gilrs::Button::C, gilrs::Button::Z,
+    gilrs::Button::LeftTrigger, gilrs::Button::LeftTrigger2, gilrs::Button::RightTrigger, gilrs::Button::RightTrigger2,
+    gilrs::Button::Select, gilrs::Button::Start, gilrs::Button::Mode,
+    gilrs::Button::LeftThumb, gilrs::Button::RightThumb,
+    gilrs::Button::DPadUp, gilrs::Button::DPadDown, gilrs::Button::DPadLeft, gilrs::Button::DPadRight,
+    gilrs::Button::Unknown,
+];
+
+// What the window asks of the session thread, which owns the link
+enum Request {
+    REMAP(HashMap<gilrs::Button, Button>),
+    PLAY(PathBuf, f64),
+    STOP,
+    PAUSE,
+    STEP,
+    RECORD(PathBuf),
+}
+
+struct LinkView {
+    name: String,
+    health: String,
+    degraded: bool,
+    buttons: Vec<String>,
+    sticks: Vec<(i32, i32)>,
+}
+
+// Published by the session thread after each tick
+#[derive(Default)]
+struct Snapshot {
+    connected: Option<String>,
+    links: Vec<LinkView>,
+    running: Vec<(String, String)>,
+    paused: Option<bool>,
+    ended: Option<String>,
+}
+
+impl Snapshot {
+    fn update(&mut self, router: &Router, playback: Option<&Playback>) {
+        self.links = router.health().zip(router.held())
+            .map(|((name, health), (_, held))| LinkView {
+                name: name.to_string(),
+                health: format!("{}, now {}", health, health.rates),
+                degraded: health.is_degraded(),
+                buttons: held.buttons().map(str::to_string).collect(),
+                sticks: STICKS.iter().map(|stick| held.stick(stick)).collect(),
+            })
+            .collect();
+        self.running = router.running().map(|(what, detail)| (what.to_string(), detail.to_string())).collect();
+        self.paused = playback.map(Playback::is_paused);
+    }
+}
+
+fn default_gamepad(button: Button) -> Option<gilrs::Button> {
+    BTN_ASSOCIATION.rev().iter().find_map(|(gamepad, mapped)| (*mapped == button).then_some(*gamepad))
+}
+
+struct Session {
+    requests: Sender<Request>,
+    state: Arc<Mutex<Snapshot>>,
+    cancel: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    fn start(target: Target, mapping: HashMap<gilrs::Button, Button>, tick: Duration) -> Session {
+        let (requests, received) = mpsc::channel();
+        let state = Arc::new(Mutex::new(Snapshot::default()));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (published, cancelled) = (state.clone(), cancel.clone());
+        let thread = thread::spawn(move || run_session(target, mapping, tick, received, published, cancelled));
+        Session { requests, state, cancel, thread: Some(thread) }
+    }
+
+    fn ask(&self, request: Request) {
+        let _ = self.requests.send(request);
+    }
+}
+
+// Gives the session a moment to release everything and detach. A link still connecting, or stuck
+// sending, is left to its thread rather than freezing the window
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        let Some(handle) = self.thread.take() else { return };
+        if self.state.lock().unwrap().connected.is_none() {
+            return;
+        }
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        while !handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(CLOSE_POLL);
+        }
+        match handle.is_finished() {
+            true => {
+                let _ = handle.join();
+            }
+            false => warn!("The link didn't close within {:?}, leaving it behind", CLOSE_TIMEOUT),
+        }
+    }
+}
+
+// Reads the gamepads and sends at the tick rate, like forwarding from the terminal
+fn run_session(target: Target, mapping: HashMap<gilrs::Button, Button>, tick: Duration, requests: Receiver<Request>, state: Arc<Mutex<Snapshot>>, cancel: Arc<AtomicBool>) {
+    let mut client = match SwitchClient::builder().target(target).mapping(mapping).build() {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("{}", err);
+            state.lock().unwrap().ended = Some(err.to_string());
+            return;
+        }
+    };
+    if cancel.load(Ordering::Relaxed) {
+        info!("Connected to {} after being cancelled, disconnecting", client.describe());
+        client.close();
+        return;
+    }
+    info!("Successfully connected to {}!", client.describe());
+    state.lock().unwrap().connected = Some(client.describe());
+    // Macros still play without a gamepad
+    let mut gilrs = Gilrs::new()
+        .map_err(|err| warn!("Unable to read the gamepads ({}), only macros will be sent", err))
+        .ok();
+    let mut playback: Option<Playback> = None;
+    let mut ticker = Ticker::new(tick);
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            info!("Disconnecting from {}", client.describe());
+            client.close();
+            return;
+        }
+        loop {
+            let Ok(request) = requests.try_recv() else { break };
+            match request {
+                Request::REMAP(mapping) => client.remap(mapping),
+                Request::PLAY(file, speed) => match capture::load_macro(&file) {
+                    Ok(instructions) => {
+                        let router = client.router();
+                        playback = Some(Playback::new(&instructions, router, speed));
+                        router.count_run("replay");
+                        router.set_running("replay", Some(format!("{} at {}x", file.display(), speed)));
+                    }
+                    Err(err) => warn!("{}", err),
+                },
+                Request::STOP => {
+                    if playback.take().is_some() {
+                        client.router().set_running("replay", None);
+                        info!("Stopped replaying");
+                    }
+                }
+                Request::PAUSE => match &mut playback {
+                    Some(running) if running.is_paused() => running.resume(),
+                    Some(running) => running.pause(),
+                    None => warn!("No macro is being replayed"),
+                },
+                Request::STEP => match &mut playback {
+                    Some(running) => running.step(client.router()),
+                    None => warn!("No macro is being replayed"),
+                },
+                Request::RECORD(file) => client.router().toggle_recording(&file),
+            }
+        }
+
+        while let Some(event) = gilrs.as_mut().and_then(Gilrs::next_event) {
+            match event.event {
+                EventType::Connected => info!("Controller connected !"),
+                EventType::Disconnected => warn!("Gamepad disconnected, connect it again to keep playing"),
+                other => client.handle(&other),
+            }
+        }
+        if let Some(running) = &mut playback {
+            running.poll(client.router());
+            if running.is_finished() {
+                info!("Replay finished");
+                client.router().set_running("replay", None);
+                playback = None;
+            }
+        }
+        client.tick();
+        state.lock().unwrap().update(client.router(), playback.as_ref());
+        ticker.wait();
+    }
+}
+
+// Picks a link, edits the button layout, shows what the consoles hold and runs macros,
+// for those who'd rather not answer prompts in a terminal
+pub struct Gui {
+    target: String,
+    targets: Vec<(String, Target)>,
+    found: Vec<DiscoveredConsole>,
+    searching: Option<Receiver<Vec<DiscoveredConsole>>>,
+    mapping: Vec<(Button, Option<gilrs::Button>)>,
+    macro_file: String,
+    record_file: String,
+    speed: f64,
+    tick: Duration,
+    session: Option<Session>,
+    log: Receiver<String>,
+    lines: VecDeque<String>,
+}
+
+impl Gui {
+    fn new(targets: &BTreeMap<String, Target>, tick: Duration) -> Gui {
+        let mapping = ALL_BUTTONS.iter().map(|button| (*button, default_gamepad(*button))).collect();
+        let (sender, log) = mpsc::channel();
+        logging::divert(Some(sender));
+        Gui {
+            target: String::new(),
+            targets: targets.iter().map(|(name, target)| (name.clone(), target.clone())).collect(),
+            found: Vec::new(),
+            searching: None,
+            mapping,
+            macro_file: String::new(),
+            record_file: "macro.jsonl".to_string(),
+            speed: 1.0,
+            tick,
+            session: None,
+            log,
+            lines: VecDeque::new(),
+        }
+    }
+
+    fn mapping(&self) -> HashMap<gilrs::Button, Button> {
+        self.mapping.iter().filter_map(|(button, gamepad)| gamepad.map(|gamepad| (gamepad, *button))).collect()
+    }
+
+    fn connection_picker(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Connect to a switch");
+        if !self.targets.is_empty() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label("From the config:");
+                for (name, target) in &self.targets {
+                    if ui.button(name).clicked() {
+                        self.target = format!("{:?}", target);
+                    }
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            let searching = self.searching.is_some();
+            if ui.add_enabled(!searching, egui::Button::new("Find switches on the network")).clicked() {
+                let (sender, found) = mpsc::channel();
+                thread::spawn(move || sender.send(discovery::discover_consoles(discovery::DEFAULT_PORT)));
+                self.searching = Some(found);
+            }
+            if searching {
+                ui.spinner();
+            }
+        });
+        for console in &self.found {
+            if ui.selectable_label(self.target == console.address.to_string(), console.to_string()).clicked() {
+                self.target = console.address.to_string();
+            }
+        }
+
+        ui.add_space(8.0);
+        let syntaxes: Vec<&str> = BACKENDS.iter().map(|backend| backend.syntax).collect();
+        ui.label("Target:");
+        ui.text_edit_singleline(&mut self.target).on_hover_text(syntaxes.join("\n"));
+        let target = self.target.parse::<Target>();
+        if let (Err(err), false) = (&target, self.target.is_empty()) {
+            ui.colored_label(Color32::RED, err);
+        }
+        if ui.add_enabled(target.is_ok(), egui::Button::new("Connect")).clicked() {
+            if let Ok(target) = target {
+                info!("Connecting to {}...", self.target);
+                self.session = Some(Session::start(target, self.mapping(), self.tick));
+            }
+        }
+    }
+
+    fn mapping_editor(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Buttons");
+        ui.weak("Gamepad button pressing each switch button");
+        let mut changed = false;
+        egui::Grid::new("mapping").striped(true).show(ui, |ui| {
+            for (button, gamepad) in &mut self.mapping {
+                ui.label(get_button_name(*button));
+                let before = *gamepad;
+                egui::ComboBox::from_id_salt(get_button_name(*button))
+                    .selected_text(gamepad.map(|gamepad| format!("{:?}", gamepad)).unwrap_or_else(|| "none".to_string()))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(gamepad, None, "none");
+                        for candidate in GAMEPAD_BUTTONS {
+                            ui.selectable_value(gamepad, Some(candidate), format!("{:?}", candidate));
+                        }
+                    });
+                changed |= *gamepad != before;
+                ui.end_row();
+            }
+        });
+        if ui.button("Reset").clicked() {
+            for (button, gamepad) in &mut self.mapping {
+                *gamepad = default_gamepad(*button);
+            }
+            changed = true;
+        }
+        if changed {
+            if let Some(session) = &self.session {
+                session.ask(Request::REMAP(self.mapping()));
+            }
+        }
+    }
+
+    fn session_view(&mut self, ui: &mut egui::Ui) {
+        let Some(session) = &self.session else { return };
+        let state = session.state.lock().unwrap();
+        let mut disconnect = false;
+        match (&state.ended, &state.connected) {
+            (Some(reason), _) => {
+                ui.colored_label(Color32::RED, reason);
+                disconnect = ui.button("Back").clicked();
+            }
+            (None, None) => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(format!("Connecting to {}...", self.target));
+                    disconnect = ui.button("Cancel").clicked();
+                });
+            }
+            (None, Some(connected)) => {
+                ui.horizontal(|ui| {
+                    ui.heading(format!("Connected to {}", connected));
+                    disconnect = ui.button("Disconnect").clicked();
+                });
+            }
+        }
+
+        for link in &state.links {
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.strong(&link.name);
+                match link.degraded {
+                    true => ui.colored_label(Color32::YELLOW, "DEGRADED"),
+                    false => ui.colored_label(Color32::GREEN, "ok"),
+                };
+                ui.label(&link.health);
+            });
+            ui.horizontal_wrapped(|ui| {
+                for button in BUTTONS {
+                    let text = RichText::new(button).monospace();
+                    match link.buttons.iter().any(|held| held == button) {
+                        true => ui.label(text.strong().background_color(ui.visuals().selection.bg_fill)),
+                        false => ui.label(text.weak()),
+                    };
+                }
+            });
+            ui.horizontal(|ui| {
+                for (stick, (x, y)) in STICKS.iter().zip(&link.sticks) {
+                    ui.monospace(format!("{:<5} x {:>6} y {:>6}", stick, x, y));
+                }
+            });
+        }
+
+        if state.connected.is_some() && state.ended.is_none() {
+            ui.separator();
+            ui.heading("Macros");
+            ui.horizontal(|ui| {
+                ui.label("Macro:");
+                ui.text_edit_singleline(&mut self.macro_file);
+                ui.add(egui::Slider::new(&mut self.speed, capture::MIN_SPEED..=capture::MAX_SPEED).logarithmic(true).suffix("x"));
+            });
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!self.macro_file.is_empty(), egui::Button::new("Play")).clicked() {
+                    session.ask(Request::PLAY(PathBuf::from(&self.macro_file), self.speed));
+                }
+                let replaying = state.paused.is_some();
+                if ui.add_enabled(replaying, egui::Button::new("Stop")).clicked() {
+                    session.ask(Request::STOP);
+                }
+                let pause = if state.paused == Some(true) { "Resume" } else { "Pause" };
+                if ui.add_enabled(replaying, egui::Button::new(pause)).clicked() {
+                    session.ask(Request::PAUSE);
+                }
+                if ui.add_enabled(replaying, egui::Button::new("Step")).clicked() {
+                    session.ask(Request::STEP);
+                }
+            });
+            ui.horizontal(|ui| {
+                let recording = state.running.iter().any(|(what, _)| what == "recording");
+                ui.label("Record to:");
+                ui.add_enabled(!recording, egui::TextEdit::singleline(&mut self.record_file));
+                let record = if recording { "Stop recording" } else { "Record" };
+                if ui.add_enabled(!self.record_file.is_empty(), egui::Button::new(record)).clicked() {
+                    session.ask(Request::RECORD(PathBuf::from(&self.record_file)));
+                    // What was just recorded is the likeliest to be played next
+                    if recording {
+                        self.macro_file = self.record_file.clone();
+                    }
+                }
+            });
+
+            ui.separator();
+            ui.heading("Running");
+            if state.running.is_empty() {
+                ui.weak("nothing");
+            }
+            for (what, detail) in &state.running {
+                ui.label(format!("{} {}", what, detail));
+            }
+        }
+        drop(state);
+        if disconnect {
+            self.session = None;
+        }
+    }
+}
+
+impl eframe::App for Gui {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.lines.extend(self.log.try_iter());
+        while self.lines.len() > LOG_LINES {
+            self.lines.pop_front();
+        }
+        if let Some(found) = self.searching.as_ref().and_then(|searching| searching.try_recv().ok()) {
+            if found.is_empty() {
+                warn!("No switch found on the local network");
+            }
+            self.found = found;
+            self.searching = None;
+        }
+
+        egui::TopBottomPanel::bottom("log").resizable(true).show(ctx, |ui| {
+            egui::ScrollArea::vertical().stick_to_bottom(true).auto_shrink(false).show(ui, |ui| {
+                for line in &self.lines {
+                    ui.monospace(line);
+                }
+            });
+        });
+        egui::SidePanel::left("mapping").show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| self.mapping_editor(ui));
+        });
+        egui::CentralPanel::default().show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| match self.session {
+                Some(_) => self.session_view(ui),
+                None => self.connection_picker(ui),
+            });
+        });
+        ctx.request_repaint_after(FRAME_INTERVAL);
+    }
+}
+
+impl Drop for Gui {
+    fn drop(&mut self) {
+        // Released and detached before the log goes back to the terminal
+        self.session = None;
+        logging::divert(None);
+    }
+}
+
+// Blocks until the window is closed, the targets of the config are offered as shortcuts
+pub fn run(targets: &BTreeMap<String, Target>, tick: Duration) -> Result<(), Error> {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([960.0, 640.0]).with_title("Switch controller"),
+        ..eframe::NativeOptions::default()
+    };
+    eframe::run_native("switch-usb-controld", options, Box::new(|_| Ok(Box::new(Gui::new(targets, tick)))))
+        .map_err(|err| Error::GUI(err.to_string()))
+}
@@ -0,0 +1,179 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+const DEGRADED_LATENCY: Duration = Duration::from_millis(50);
+// About what the console polls at, and what the old fixed tick sent at
+const MIN_STICK_INTERVAL: Duration = Duration::from_millis(8);
+const MAX_STICK_INTERVAL: Duration = Duration::from_millis(100);
+// Buckets grow by a quarter power of two from the first one, a percentile is off by 19% at most
+const FIRST_BUCKET: Duration = Duration::from_micros(64);
+const BUCKETS: usize = 80;
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Default, Clone, Copy)]
+pub struct SendOutcome {
+    pub failures: u32,
+    pub reconnects: u32,
+    // Links sending state reports rather than commands count none
+    pub bytes: u64,
+}
+
+impl SendOutcome {
+    pub fn merge(self, other: SendOutcome) -> SendOutcome {
+        SendOutcome {
+            failures: self.failures + other.failures,
+            reconnects: self.reconnects + other.reconnects,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+// Every latency of the session in constant memory, for averages and percentiles
+pub struct Histogram {
+    counts: [u64; BUCKETS],
+    pub total: u64,
+    pub sum: Duration,
+    pub max: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram { counts: [0; BUCKETS], total: 0, sum: Duration::ZERO, max: Duration::ZERO }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        let ratio = latency.as_secs_f64() / FIRST_BUCKET.as_secs_f64();
+        let index = (ratio.max(1.0).log2() * 4.0) as usize;
+        self.counts[index.min(BUCKETS - 1)] += 1;
+        self.total += 1;
+        self.sum += latency;
+        self.max = self.max.max(latency);
+    }
+
+    // Upper bound of a bucket
+    pub fn bound(index: usize) -> Duration {
+        FIRST_BUCKET.mul_f64(2f64.powf((index + 1) as f64 / 4.0))
+    }
+
+    pub fn average(&self) -> Duration {
+        self.sum.checked_div(self.total as u32).unwrap_or_default()
+    }
+
+    // The bound of the bucket holding it, a slight overestimate, but never over the slowest
+    pub fn percentile(&self, percent: f64) -> Duration {
+        let rank = (self.total as f64 * percent / 100.0).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Histogram::bound(index).min(self.max);
+            }
+        }
+        Duration::ZERO
+    }
+
+    // Upper bound of each bucket with how many latencies are at most that
+    pub fn cumulative(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.counts.iter()
+            .enumerate()
+            .scan(0, |seen, (index, count)| {
+                *seen += count;
+                Some((Histogram::bound(index), *seen))
+            })
+    }
+}
+
+// The sends of the last second, what stick spam and tight thresholds show up in
+#[derive(Default)]
+pub struct Rates {
+    recent: VecDeque<(Instant, u64, u64)>,
+}
+
+impl Rates {
+    fn record(&mut self, commands: u64, bytes: u64) {
+        let now = Instant::now();
+        self.recent.push_back((now, commands, bytes));
+        while self.recent.front().is_some_and(|(at, _, _)| now.duration_since(*at) > RATE_WINDOW) {
+            self.recent.pop_front();
+        }
+    }
+
+    // Packets, commands and bytes per second
+    pub fn per_second(&self) -> (u64, u64, u64) {
+        self.recent.iter()
+            .filter(|(at, _, _)| at.elapsed() <= RATE_WINDOW)
+            .fold((0, 0, 0), |(packets, commands, bytes), (_, sent, size)| (packets + 1, commands + sent, bytes + size))
+    }
+}
+
+impl Display for Rates {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (packets, commands, bytes) = self.per_second();
+        write!(f, "{} packets/s, {} commands/s, {:.1} KB/s", packets, commands, bytes as f64 / 1000.)
+    }
+}
+
+#[derive(Default)]
+pub struct LinkHealth {
+    pub sends: u64,
+    pub commands: u64,
+    pub bytes: u64,
+    pub failures: u64,
+    pub reconnects: u64,
+    pub latency: Duration,
+    pub max_latency: Duration,
+    pub latencies: Histogram,
+    pub rates: Rates,
+    degraded: bool,
+}
+
+impl LinkHealth {
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    // How often sticks can be updated without updates queuing up on a console that falls behind
+    pub fn stick_interval(&self) -> Duration {
+        let latency = if self.degraded { self.latency * 4 } else { self.latency * 2 };
+        latency.clamp(MIN_STICK_INTERVAL, MAX_STICK_INTERVAL)
+    }
+
+    // Returns true when the link just changed between healthy and degraded
+    pub fn record(&mut self, commands: usize, latency: Duration, outcome: SendOutcome) -> bool {
+        self.sends += 1;
+        self.commands += commands as u64;
+        self.bytes += outcome.bytes;
+        self.rates.record(commands as u64, outcome.bytes);
+        self.latencies.record(latency);
+        self.failures += outcome.failures as u64;
+        self.reconnects += outcome.reconnects as u64;
+        self.latency = (self.latency * 7 + latency) / 8;
+        self.max_latency = self.max_latency.max(latency);
+
+        let degraded = outcome.failures > 0 || self.latency > DEGRADED_LATENCY;
+        let changed = degraded != self.degraded;
+        self.degraded = degraded;
+        changed
+    }
+}
+
+impl Display for LinkHealth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} sends of {} commands ({} bytes), latency avg {:.1}ms p50 {:.1}ms p99 {:.1}ms max {:.1}ms, {} failures, {} reconnects",
+            self.sends,
+            self.commands,
+            self.bytes,
+            self.latencies.average().as_secs_f64() * 1000.,
+            self.latencies.percentile(50.0).as_secs_f64() * 1000.,
+            self.latencies.percentile(99.0).as_secs_f64() * 1000.,
+            self.max_latency.as_secs_f64() * 1000.,
+            self.failures,
+            self.reconnects
+        )
+    }
+}
@@ -0,0 +1,121 @@
+use crate::command::{Command, Name};
+use crate::router::DETACH;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Mirrors what the console currently holds so it can be replayed on the other link
+#[derive(Default)]
+pub struct HeldState {
+    buttons: BTreeSet<Name>,
+    sticks: BTreeMap<Name, Command>,
+}
+
+impl HeldState {
+    pub fn observe(&mut self, command: &Command) {
+        match command {
+            Command::PRESS(button) => {
+                self.buttons.insert(button.clone());
+            }
+            Command::RELEASE(button) => {
+                self.buttons.remove(button);
+            }
+            Command::SETSTICK(stick, _, _) => {
+                self.sticks.insert(stick.clone(), command.clone());
+            }
+            // A controller attached again starts with nothing held
+            _ if command.name() == DETACH => *self = HeldState::default(),
+            _ => {}
+        }
+    }
+
+    // Drops what wouldn't change anything on the console from a batch about to be sent.
+    // Pressing and releasing in the same batch is too quick for the console to notice,
+    // it becomes a click, which sys-botbase holds long enough
+    pub fn coalesce(&self, commands: Vec<Command>) -> Vec<Command> {
+        let mut buttons = self.buttons.clone();
+        let mut sticks = self.sticks.clone();
+        let mut coalesced: Vec<Command> = Vec::with_capacity(commands.len());
+        for command in commands {
+            match &command {
+                Command::PRESS(button) if !buttons.insert(button.clone()) => continue,
+                Command::RELEASE(button) => {
+                    buttons.remove(button);
+                    let pressed = coalesced.iter().rposition(|sent| matches!(sent, Command::PRESS(pressed) if pressed == button));
+                    if let Some(index) = pressed {
+                        coalesced[index] = Command::CLICK(button.clone());
+                        continue;
+                    }
+                }
+                Command::SETSTICK(stick, _, _) => {
+                    if sticks.get(stick) == Some(&command) {
+                        continue;
+                    }
+                    // Only the last position of a stick matters
+                    coalesced.retain(|sent| !matches!(sent, Command::SETSTICK(moved, _, _) if moved == stick));
+                    sticks.insert(stick.clone(), command.clone());
+                }
+                _ => {}
+            }
+            coalesced.push(command);
+        }
+        coalesced
+    }
+
+    pub fn buttons(&self) -> impl Iterator<Item = &str> {
+        self.buttons.iter().map(|button| button.as_ref())
+    }
+
+    pub fn stick(&self, stick: &str) -> (i32, i32) {
+        match self.sticks.get(stick) {
+            Some(Command::SETSTICK(_, x, y)) => (*x, *y),
+            _ => (0, 0),
+        }
+    }
+
+    pub fn release_packets(&self) -> Vec<Command> {
+        self.buttons.iter()
+            .map(|button| Command::RELEASE(button.clone()))
+            .chain(self.sticks.keys().map(|stick| Command::SETSTICK(stick.clone(), 0, 0)))
+            .collect()
+    }
+
+    pub fn resync_packets(&self) -> Vec<Command> {
+        self.buttons.iter()
+            .map(|button| Command::PRESS(button.clone()))
+            .chain(self.sticks.values().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands(text: &str) -> Vec<Command> {
+        text.split(';').map(|command| command.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn repeated_presses_and_unchanged_sticks_are_dropped() {
+        let mut held = HeldState::default();
+        held.observe(&"press A".parse().unwrap());
+        held.observe(&"setStick LEFT 0x100 0".parse().unwrap());
+
+        let coalesced = held.coalesce(commands("press A;press B;press B;setStick LEFT 0x100 0;setStick RIGHT 0 0x100"));
+        assert_eq!(coalesced, commands("press B;setStick RIGHT 0 0x100"));
+    }
+
+    #[test]
+    fn only_the_last_stick_position_is_sent() {
+        let coalesced = HeldState::default().coalesce(commands("setStick LEFT 1 0;click A;setStick LEFT 2 0;setStick LEFT 3 0"));
+        assert_eq!(coalesced, commands("click A;setStick LEFT 3 0"));
+    }
+
+    #[test]
+    fn press_and_release_in_one_batch_become_a_click() {
+        let mut held = HeldState::default();
+        held.observe(&"press B".parse().unwrap());
+
+        let coalesced = held.coalesce(commands("press A;setStick LEFT 1 0;release A;release B;click X"));
+        assert_eq!(coalesced, commands("click A;setStick LEFT 1 0;release B;click X"));
+    }
+}
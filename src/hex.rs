@@ -0,0 +1,21 @@
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len()).step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Stick values are written as 0x1234 or -0x1234 in sys-botbase commands
+pub fn parse_signed(s: &str) -> Option<i32> {
+    match s.strip_prefix('-') {
+        Some(s) => parse_signed(s).map(|v| -v),
+        None => i32::from_str_radix(s.trim_start_matches("0x"), 16).ok(),
+    }
+}
@@ -0,0 +1,150 @@
+use crate::command::Command;
+use crate::health::SendOutcome;
+use crate::transport::Transport;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+pub const DEFAULT_PORT: u16 = 8000;
+
+const MAGIC: u16 = 0x3275;
+const PRO_CONTROLLER: u16 = 1;
+const CONTROLLER_SLOTS: usize = 4;
+const SLOT_SIZE: usize = 26;
+const CLICK_DURATION: Duration = Duration::from_millis(50);
+
+fn button_bit(button: &str) -> Option<u64> {
+    let bit = match button {
+        "A" => 0,
+        "B" => 1,
+        "X" => 2,
+        "Y" => 3,
+        "LSTICK" => 4,
+        "RSTICK" => 5,
+        "L" => 6,
+        "R" => 7,
+        "ZL" => 8,
+        "ZR" => 9,
+        "PLUS" => 10,
+        "MINUS" => 11,
+        "DLEFT" => 12,
+        "DUP" => 13,
+        "DRIGHT" => 14,
+        "DDOWN" => 15,
+        _ => return None,
+    };
+    Some(1 << bit)
+}
+
+pub fn parse_address(address: &str) -> Result<SocketAddr, String> {
+    address.parse()
+        .or_else(|_| address.parse().map(|ip: IpAddr| SocketAddr::new(ip, DEFAULT_PORT)))
+        .map_err(|_| format!("Expected <ip>[:port], got {}", address))
+}
+
+// sys-hidplus takes whole controller states over UDP instead of commands, so the
+// sys-botbase commands are folded into the state of the first controller slot
+pub struct HidPlus {
+    socket: UdpSocket,
+    keys: u64,
+    left_stick: (i32, i32),
+    right_stick: (i32, i32),
+}
+
+impl HidPlus {
+    pub fn connect(address: SocketAddr) -> io::Result<HidPlus> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.connect(address)?;
+        Ok(HidPlus {
+            socket,
+            keys: 0,
+            left_stick: (0, 0),
+            right_stick: (0, 0),
+        })
+    }
+
+    // Returns the button bits that have to be let go once the click has registered
+    fn apply(&mut self, command: &Command) -> u64 {
+        match command {
+            Command::CLICK(button) => {
+                let bit = button_bit(button).unwrap_or_default();
+                self.keys |= bit;
+                bit
+            }
+            Command::PRESS(button) => {
+                self.keys |= button_bit(button).unwrap_or_default();
+                0
+            }
+            Command::RELEASE(button) => {
+                self.keys &= !button_bit(button).unwrap_or_default();
+                0
+            }
+            Command::SETSTICK(stick, x, y) => {
+                match stick.as_ref() {
+                    "LEFT" => self.left_stick = (*x, *y),
+                    "RIGHT" => self.right_stick = (*x, *y),
+                    _ => {}
+                }
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    fn report(&self) -> Vec<u8> {
+        let mut report = MAGIC.to_le_bytes().to_vec();
+        report.extend_from_slice(&PRO_CONTROLLER.to_le_bytes());
+        report.extend_from_slice(&self.keys.to_le_bytes());
+        for axis in [self.left_stick.0, self.left_stick.1, self.right_stick.0, self.right_stick.1] {
+            report.extend_from_slice(&axis.to_le_bytes());
+        }
+        // Unused slots are sent as disconnected
+        report.resize(report.len() + (CONTROLLER_SLOTS - 1) * SLOT_SIZE, 0);
+        report
+    }
+
+    fn resend(&self) -> io::Result<()> {
+        self.socket.send(&self.report()).map(|_| ())
+    }
+}
+
+impl Transport for HidPlus {
+    fn describe(&self) -> String {
+        match self.socket.peer_addr() {
+            Ok(address) => format!("HID+ switch at {}", address),
+            Err(_) => "HID+ switch".to_string(),
+        }
+    }
+
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        match self.try_send_commands(commands) {
+            Ok(()) => SendOutcome::default(),
+            Err(err) => {
+                warn!("Unable to send inputs to sys-hidplus ({})", err);
+                SendOutcome { failures: 1, ..SendOutcome::default() }
+            }
+        }
+    }
+
+    fn try_send_commands(&mut self, commands: &[Command]) -> Result<(), String> {
+        let clicked = commands.iter().fold(0, |clicked, command| clicked | self.apply(command));
+        self.resend().map_err(|err| err.to_string())?;
+
+        if clicked != 0 {
+            thread::sleep(CLICK_DURATION);
+            self.keys &= !clicked;
+            self.resend().map_err(|err| err.to_string())?;
+        }
+        Ok(())
+    }
+
+    // UDP gives no way to tell whether sys-hidplus is listening, resending the state is all we can do
+    fn heartbeat(&mut self) -> SendOutcome {
+        match self.resend() {
+            Ok(()) => SendOutcome::default(),
+            Err(_) => SendOutcome { failures: 1, ..SendOutcome::default() },
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::command::Command;
+use crate::hex;
+use crate::memory::{Bases, Region};
+use crate::response::Response;
+use crate::router::Router;
+
+// Indexes of the switch SetLanguage
+const LANGUAGES: [&str; 18] = [
+    "Japanese",
+    "American English",
+    "French",
+    "German",
+    "Italian",
+    "Spanish",
+    "Chinese",
+    "Korean",
+    "Dutch",
+    "Portuguese",
+    "Russian",
+    "Taiwanese",
+    "British English",
+    "Canadian French",
+    "Latin American Spanish",
+    "Simplified Chinese",
+    "Traditional Chinese",
+    "Brazilian Portuguese",
+];
+
+fn describe(command: &str, response: Response) -> String {
+    match (command, response) {
+        ("getSystemLanguage", Response::NUMBER(index)) => match LANGUAGES.get(index as usize) {
+            Some(language) => format!("{} ({})", language, index),
+            None => format!("unknown ({})", index),
+        },
+        ("getTitleID", Response::NUMBER(id)) => format!("{:016X}", id),
+        (_, Response::NUMBER(number)) => format!("0x{:X}", number),
+        (_, Response::BYTES(bytes)) => hex::encode(&bytes),
+        (_, Response::TEXT(text)) => text,
+    }
+}
+
+// Every answer is printed on its own, a title not running doesn't hide the rest
+pub fn print(router: &mut Router, bases: &mut Bases) {
+    let queries = [
+        ("Title ID", "getTitleID"),
+        ("Build ID", "getBuildID"),
+        ("System language", "getSystemLanguage"),
+        ("sys-botbase version", "getVersion"),
+    ];
+    for (label, command) in queries {
+        match router.request(&Command::OTHER(command.to_string())) {
+            Ok(response) => println!("{}: {}", label, describe(command, response)),
+            Err(err) => println!("{}: unavailable ({})", label, err),
+        }
+    }
+    for (label, region) in [("Heap base", Region::HEAP), ("Main base", Region::MAIN)] {
+        match bases.base(router, region) {
+            Ok(base) => println!("{}: 0x{:X}", label, base),
+            Err(err) => println!("{}: unavailable ({})", label, err),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::command::Command;
+use crate::config::Source;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use tracing::warn;
+
+// Timestamps are microseconds on the monotonic clock since the log started, so gamepad events
+// and the commands they produced can be compared whatever the wall clock does
+#[derive(Serialize)]
+struct Entry<'a> {
+    micros: u64,
+    kind: &'a str,
+    source: &'a str,
+    gamepad: usize,
+    target: &'a str,
+    data: String,
+}
+
+enum Format {
+    CSV,
+    JSONL,
+}
+
+// Every raw gamepad event and forwarded command, for latency analysis, TAS verification and bug reports.
+// Unlike a capture it isn't meant to be replayed
+pub struct InputLog {
+    writer: BufWriter<File>,
+    format: Format,
+    start: Instant,
+}
+
+impl InputLog {
+    // CSV when the file ends in .csv, JSON lines otherwise
+    pub fn create(path: &Path) -> io::Result<InputLog> {
+        let format = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => Format::CSV,
+            _ => Format::JSONL,
+        };
+        let mut writer = BufWriter::new(File::create(path)?);
+        if let Format::CSV = format {
+            writeln!(writer, "micros,kind,source,gamepad,target,data")?;
+        }
+        Ok(InputLog { writer, format, start: Instant::now() })
+    }
+
+    // Timed when the event was read rather than when it is handled
+    pub fn event(&mut self, at: Instant, gamepad: usize, event: String) {
+        let entry = Entry { micros: self.micros(at), kind: "event", source: Source::GAMEPAD.name(), gamepad, target: "", data: event };
+        let written = self.write(&entry);
+        self.flush(written);
+    }
+
+    pub fn commands(&mut self, source: Source, gamepad: usize, target: &str, commands: &[Command]) {
+        let micros = self.micros(Instant::now());
+        let written = commands.iter().try_for_each(|command| {
+            self.write(&Entry { micros, kind: "command", source: source.name(), gamepad, target, data: command.to_string() })
+        });
+        self.flush(written);
+    }
+
+    fn micros(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.start).as_micros() as u64
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        match self.format {
+            Format::CSV => writeln!(
+                self.writer,
+                "{},{},{},{},\"{}\",\"{}\"",
+                entry.micros, entry.kind, entry.source, entry.gamepad, entry.target.replace('"', "\"\""), entry.data.replace('"', "\"\"")
+            ),
+            Format::JSONL => {
+                serde_json::to_writer(&mut self.writer, entry)?;
+                self.writer.write_all(b"\n")
+            }
+        }
+    }
+
+    // Flushed every time so the log survives the process being killed
+    fn flush(&mut self, written: io::Result<()>) {
+        if let Err(err) = written.and_then(|_| self.writer.flush()) {
+            warn!("Unable to write the input log ({})", err);
+        }
+    }
+}
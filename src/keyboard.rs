@@ -0,0 +1,72 @@
+use crate::command::Command;
+use std::process;
+
+const LEFT_SHIFT: u8 = 225;
+
+// Punctuation keys of a US layout with their plain and shifted characters
+const SYMBOLS: [(u8, char, char); 21] = [
+    (30, '1', '!'), (31, '2', '@'), (32, '3', '#'), (33, '4', '$'), (34, '5', '%'),
+    (35, '6', '^'), (36, '7', '&'), (37, '8', '*'), (38, '9', '('), (39, '0', ')'),
+    (45, '-', '_'), (46, '=', '+'), (47, '[', '{'), (48, ']', '}'), (49, '\\', '|'),
+    (51, ';', ':'), (52, '\'', '"'), (53, '`', '~'), (54, ',', '<'), (55, '.', '>'),
+    (56, '/', '?'),
+];
+
+// HID usage of the key typing the character, and whether it needs shift
+fn usage(c: char) -> Option<(u8, bool)> {
+    match c {
+        'a'..='z' => Some((4 + (c as u8 - b'a'), false)),
+        'A'..='Z' => Some((4 + (c as u8 - b'A'), true)),
+        '\n' => Some((40, false)),
+        '\t' => Some((43, false)),
+        ' ' => Some((44, false)),
+        _ => SYMBOLS.iter().find_map(|(key, plain, shifted)| match c {
+            _ if c == *plain => Some((*key, false)),
+            _ if c == *shifted => Some((*key, true)),
+            _ => None,
+        }),
+    }
+}
+
+// Runs of plain keys go out as one key command, shifted characters need keyMulti
+pub fn type_commands(text: &str) -> Result<Vec<Command>, String> {
+    let mut commands = Vec::new();
+    let mut keys: Vec<String> = Vec::new();
+    for c in text.chars().filter(|c| *c != '\r') {
+        let (key, shift) = usage(c).ok_or_else(|| format!("The switch keyboard can't type {:?}", c))?;
+        if shift {
+            if !keys.is_empty() {
+                commands.push(Command::OTHER(format!("key {}", keys.join(" "))));
+                keys.clear();
+            }
+            commands.push(Command::OTHER(format!("keyMulti {} {}", LEFT_SHIFT, key)));
+        } else {
+            keys.push(key.to_string());
+        }
+    }
+    if !keys.is_empty() {
+        commands.push(Command::OTHER(format!("key {}", keys.join(" "))));
+    }
+    Ok(commands)
+}
+
+// There is no portable clipboard API, so ask whichever clipboard tool the system has
+pub fn clipboard() -> Option<String> {
+    let tools: [(&str, &[&str]); 5] = [
+        ("wl-paste", &["--no-newline"]),
+        ("xclip", &["-selection", "clipboard", "-o"]),
+        ("xsel", &["--clipboard", "--output"]),
+        ("pbpaste", &[]),
+        ("powershell", &["-NoProfile", "-Command", "Get-Clipboard"]),
+    ];
+
+    tools.iter().find_map(|(tool, args)| {
+        let output = process::Command::new(tool).args(*args).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    })
+}
+
+pub fn paste_commands() -> Result<Vec<Command>, String> {
+    let text = clipboard().ok_or_else(|| "Unable to read the clipboard".to_string())?;
+    type_commands(text.trim_end_matches(['\r', '\n']))
+}
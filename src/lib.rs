@@ -0,0 +1,85 @@
+//! Control a Nintendo Switch running sys-botbase, or one of the other supported backends.
+//!
+//! A [`router::Router`] sends [`command::Command`]s to one or several [`transport::Transport`]s,
+//! such as [`connection::Tcp`] or [`serial::Serial`]. [`controller::ControllerState`] turns a
+//! gamepad into those commands and [`capture`] replays recorded macros through a router.
+//! [`client::SwitchClient`] puts these together for a single switch.
+
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod auth;
+pub mod bench;
+pub mod bluetooth;
+pub mod capture;
+pub mod cheat;
+pub mod client;
+pub mod command;
+pub mod compact;
+pub mod config;
+pub mod connection;
+pub mod controller;
+pub mod dateskip;
+pub mod discovery;
+pub mod dryrun;
+pub mod dump;
+pub mod error;
+#[cfg(feature = "usb")]
+pub mod failover;
+#[cfg(any(feature = "preview", feature = "scripting"))]
+pub mod frame;
+pub mod freeze;
+pub mod gadget;
+pub mod game;
+#[cfg(feature = "gui")]
+pub mod gui;
+pub mod health;
+pub mod held;
+pub mod hex;
+pub mod hidplus;
+pub mod info;
+pub mod inputlog;
+pub mod keyboard;
+pub mod library;
+pub mod logging;
+pub mod macros;
+pub mod mash;
+pub mod memory;
+pub mod metrics;
+pub mod notify;
+pub mod nxtas;
+pub mod overlay;
+pub mod overrun;
+pub mod plugin;
+#[cfg(feature = "preview")]
+pub mod preview;
+pub mod protocol;
+pub mod realtime;
+#[cfg(feature = "usb")]
+pub mod redundant;
+pub mod relay;
+pub mod repl;
+pub mod report;
+pub mod response;
+pub mod router;
+pub mod scan;
+pub mod schedule;
+pub mod screenshot;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod sequence;
+pub mod serial;
+pub mod shutdown;
+pub mod template;
+pub mod ticker;
+pub mod tls;
+pub mod transport;
+#[cfg(feature = "tray")]
+pub mod tray;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod version;
+pub mod watch;
+#[cfg(feature = "webrtc")]
+pub mod webrtc;
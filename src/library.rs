@@ -0,0 +1,229 @@
+use crate::capture::{self, Instruction};
+use crate::error::Error;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_DIR: &str = "macros";
+// Kept next to the macros, it holds what the files themselves can't say
+const INDEX: &str = "library.json";
+const ARCHIVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Metadata {
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub added: String,
+}
+
+// A bundle of macros to share, every kind of macro being a text file
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    version: u32,
+    macros: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    file: String,
+    #[serde(default)]
+    metadata: Metadata,
+    content: String,
+}
+
+pub struct Library {
+    dir: PathBuf,
+    index: BTreeMap<String, Metadata>,
+}
+
+impl Library {
+    pub fn open(dir: &Path) -> Result<Library, Error> {
+        let index = match fs::read_to_string(dir.join(INDEX)) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|err| Error::LOAD { path: dir.join(INDEX), reason: err.to_string() })?,
+            Err(_) => BTreeMap::new(),
+        };
+        Ok(Library { dir: dir.to_path_buf(), index })
+    }
+
+    fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.dir.join(INDEX), serde_json::to_string_pretty(&self.index)?)
+    }
+
+    fn files(&self) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(&self.dir).into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|file| file != INDEX)
+            .collect();
+        files.sort();
+        files
+    }
+
+    // A macro can be named with or without its extension
+    fn resolve(&self, name: &str) -> Result<String, String> {
+        let files = self.files();
+        if files.iter().any(|file| file == name) {
+            return Ok(name.to_string());
+        }
+        let matching: Vec<&String> = files.iter()
+            .filter(|file| Path::new(file).file_stem().is_some_and(|stem| stem == name))
+            .collect();
+        match matching.as_slice() {
+            [file] => Ok(file.to_string()),
+            [] => Err(format!("No macro named {} in {}", name, self.dir.display())),
+            _ => Err(format!("Several macros are named {}, give the extension too", name)),
+        }
+    }
+
+    pub fn list(&self) {
+        let files = self.files();
+        if files.is_empty() {
+            println!("No macro in {}", self.dir.display());
+        }
+        for file in files {
+            let description = self.index.get(&file).map(|metadata| metadata.description.as_str()).unwrap_or_default();
+            println!("{:<30} {:<8} {}", file, kind(Path::new(&file)), description);
+        }
+    }
+
+    pub fn show(&self, name: &str) -> Result<(), String> {
+        let file = self.resolve(name)?;
+        let path = self.dir.join(&file);
+        let metadata = self.index.get(&file).cloned().unwrap_or_default();
+        let instructions = capture::load_macro(&path).map_err(|err| err.to_string())?;
+        println!("{} ({})", file, kind(&path));
+        if !metadata.description.is_empty() {
+            println!("{}", metadata.description);
+        }
+        if !metadata.added.is_empty() {
+            println!("Added {}", metadata.added);
+        }
+        println!("{} instructions, {:.1}s long", instructions.len(), length(&instructions).as_secs_f64());
+        if kind(&path) == "macro" {
+            let content = fs::read_to_string(&path).map_err(|err| err.to_string())?;
+            println!("\n{}", content.trim_end());
+        }
+        Ok(())
+    }
+
+    pub fn describe(&mut self, name: &str, description: &str) -> Result<(), String> {
+        let file = self.resolve(name)?;
+        self.index.entry(file.clone()).or_insert_with(added_now).description = description.to_string();
+        self.save().map_err(|err| err.to_string())?;
+        println!("Described {}", file);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), String> {
+        let file = self.resolve(name)?;
+        fs::remove_file(self.dir.join(&file)).map_err(|err| format!("Unable to delete {} ({})", file, err))?;
+        if self.index.remove(&file).is_some() {
+            self.save().map_err(|err| err.to_string())?;
+        }
+        println!("Deleted {}", file);
+        Ok(())
+    }
+
+    // The extension is kept when the new name has none, it decides how the macro is read
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), String> {
+        let file = self.resolve(name)?;
+        let mut new_file = PathBuf::from(new_name);
+        if new_file.extension().is_none() {
+            if let Some(extension) = Path::new(&file).extension() {
+                new_file.set_extension(extension);
+            }
+        }
+        let new_file = new_file.to_string_lossy().to_string();
+        if self.dir.join(&new_file).exists() {
+            return Err(format!("{} already exists", new_file));
+        }
+        fs::rename(self.dir.join(&file), self.dir.join(&new_file)).map_err(|err| format!("Unable to rename {} ({})", file, err))?;
+        if let Some(metadata) = self.index.remove(&file) {
+            self.index.insert(new_file.clone(), metadata);
+            self.save().map_err(|err| err.to_string())?;
+        }
+        println!("Renamed {} to {}", file, new_file);
+        Ok(())
+    }
+
+    // Every macro when none is named
+    pub fn export(&self, names: &[String], archive: &Path) -> Result<(), String> {
+        let files = match names.is_empty() {
+            true => self.files(),
+            false => names.iter().map(|name| self.resolve(name)).collect::<Result<_, _>>()?,
+        };
+        let macros = files.into_iter()
+            .map(|file| {
+                let content = fs::read_to_string(self.dir.join(&file)).map_err(|err| format!("Unable to read {} ({})", file, err))?;
+                let metadata = self.index.get(&file).cloned().unwrap_or_default();
+                Ok(Entry { file, metadata, content })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let count = macros.len();
+        let archive_content = serde_json::to_string_pretty(&Archive { version: ARCHIVE_VERSION, macros }).map_err(|err| err.to_string())?;
+        fs::write(archive, archive_content).map_err(|err| format!("Unable to write {} ({})", archive.display(), err))?;
+        println!("Exported {} macro(s) to {}", count, archive.display());
+        Ok(())
+    }
+
+    // Macros already in the library are only replaced when forced
+    pub fn import(&mut self, archive: &Path, force: bool) -> Result<(), String> {
+        let content = fs::read_to_string(archive).map_err(|err| format!("Unable to read {} ({})", archive.display(), err))?;
+        let archive: Archive = serde_json::from_str(&content).map_err(|err| format!("Invalid macro archive: {}", err))?;
+        if archive.version > ARCHIVE_VERSION {
+            return Err(format!("The archive is version {}, this version only reads up to {}", archive.version, ARCHIVE_VERSION));
+        }
+        fs::create_dir_all(&self.dir).map_err(|err| err.to_string())?;
+        for entry in archive.macros {
+            // Names come from someone else, they can't point outside of the library
+            if Path::new(&entry.file).file_name().is_none_or(|name| name != entry.file.as_str()) || entry.file == INDEX {
+                println!("Skipping {}, not a plain file name", entry.file);
+                continue;
+            }
+            let path = self.dir.join(&entry.file);
+            if path.exists() && !force {
+                println!("Skipping {}, it already exists", entry.file);
+                continue;
+            }
+            fs::write(&path, &entry.content).map_err(|err| format!("Unable to write {} ({})", path.display(), err))?;
+            let mut metadata = entry.metadata;
+            if metadata.added.is_empty() {
+                metadata.added = added_now().added;
+            }
+            self.index.insert(entry.file.clone(), metadata);
+            println!("Imported {}", entry.file);
+        }
+        self.save().map_err(|err| err.to_string())
+    }
+}
+
+fn added_now() -> Metadata {
+    Metadata { description: String::new(), added: Local::now().format("%Y-%m-%d %H:%M").to_string() }
+}
+
+fn kind(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("txt") => "nx-TAS",
+        Some("macro") => "macro",
+        _ => "capture",
+    }
+}
+
+// Branches may skip some of it, the length is the one of every batch played in a row
+fn length(instructions: &[Instruction]) -> Duration {
+    instructions.iter()
+        .map(|instruction| match instruction {
+            Instruction::BATCH(delay, _) => *delay,
+            _ => Duration::ZERO,
+        })
+        .sum()
+}
@@ -0,0 +1,192 @@
+use crate::error::Error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+// Other crates only get through with their warnings, their debug output is about their own internals
+const CRATE: &str = env!("CARGO_CRATE_NAME");
+
+// Set while something else owns the terminal, the lines go to it instead of stderr
+static DIVERTED: Mutex<Option<Sender<String>>> = Mutex::new(None);
+
+thread_local! {
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+// Renders the message as is and the other fields after it as name=value
+#[derive(Default)]
+struct Fields {
+    message: String,
+    fields: String,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message.push_str(value),
+            name => {
+                let _ = write!(self.fields, " {}={}", name, value);
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        match field.name() {
+            "message" => {
+                let _ = write!(self.message, "{:?}", value);
+            }
+            name => {
+                let _ = write!(self.fields, " {}={:?}", name, value);
+            }
+        }
+    }
+}
+
+struct Span {
+    name: &'static str,
+    fields: String,
+    references: usize,
+}
+
+// Prints events to the terminal, and to a file when asked, each one after the spans it happened in
+pub struct Logger {
+    level: Level,
+    file: Option<Mutex<File>>,
+    spans: Mutex<HashMap<u64, Span>>,
+    next_id: AtomicU64,
+}
+
+impl Logger {
+    fn context(&self) -> String {
+        let spans = self.spans.lock().unwrap();
+        ENTERED.with(|entered| {
+            entered.borrow().iter()
+                .filter_map(|id| spans.get(id))
+                .map(|span| match span.fields.is_empty() {
+                    true => format!("{}: ", span.name),
+                    false => format!("{}{{{}}}: ", span.name, span.fields.trim_start()),
+                })
+                .collect()
+        })
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        let level = match metadata.target().starts_with(CRATE) {
+            true => self.level,
+            false => self.level.min(Level::WARN),
+        };
+        *metadata.level() <= level
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::from_level(self.level))
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        span.record(&mut fields);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let span = Span { name: span.metadata().name(), fields: fields.fields, references: 1 };
+        self.spans.lock().unwrap().insert(id, span);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            span.fields.push_str(&fields.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        let line = format!("{:>5} {}{}{}", metadata.level(), self.context(), fields.message, fields.fields);
+        let now = chrono::Local::now();
+        match &*DIVERTED.lock().unwrap() {
+            Some(sender) => {
+                let _ = sender.send(format!("{} {}", now.format("%H:%M:%S%.3f"), line));
+            }
+            None => {
+                let _ = writeln!(io::stderr().lock(), "{} {}", now.format("%H:%M:%S%.3f"), line);
+            }
+        }
+        if let Some(file) = &self.file {
+            let _ = writeln!(file.lock().unwrap(), "{} {} {}", now.format("%Y-%m-%dT%H:%M:%S%.3f"), metadata.target(), line);
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(index) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(index);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            span.references += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let Some(entry) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        entry.references -= 1;
+        if entry.references > 0 {
+            return false;
+        }
+        spans.remove(&span.into_u64());
+        true
+    }
+}
+
+// Info by default, -v adds debug and -vv trace. The file, appended to, gets the same events. A file
+// that can't be opened is reported once logging to the console is set up
+pub fn init(verbosity: u8, file: Option<&Path>) -> Result<(), Error> {
+    let level = match verbosity {
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let (file, opened) = match file.map(|path| (path, OpenOptions::new().create(true).append(true).open(path))) {
+        Some((_, Ok(file))) => (Some(file), Ok(())),
+        Some((path, Err(source))) => (None, Err(Error::LOG { path: path.to_path_buf(), source })),
+        None => (None, Ok(())),
+    };
+    let logger = Logger { level, file: file.map(Mutex::new), spans: Mutex::new(HashMap::new()), next_id: AtomicU64::new(1) };
+    if tracing::subscriber::set_global_default(logger).is_err() {
+        panic!("Logging is already initialized!");
+    }
+    opened
+}
+
+// The log file, when there is one, keeps getting every event
+pub fn divert(to: Option<Sender<String>>) {
+    *DIVERTED.lock().unwrap() = to;
+}
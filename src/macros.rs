@@ -0,0 +1,297 @@
+use crate::capture::Instruction;
+use crate::command::{self, Command, Name, BUTTONS, STICKS};
+use crate::error::Error;
+use crate::memory::Location;
+use crate::watch::Condition;
+use std::fs;
+use std::iter::Peekable;
+use std::path::Path;
+use std::time::Duration;
+use std::vec::IntoIter;
+
+// Statements are separated by ; or new lines, # starts a comment:
+//   press A; wait 500ms; stick LEFT 0 32767 for 2s; click HOME
+//   if peek([main+4C8]+10, 4) == 0 then click A else click B end
+// Sticks go back to the center after a for, wait without a unit is in milliseconds
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration {}, expected something like 500ms or 2s", s);
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(index) => s.split_at(index),
+        None => (s, "ms"),
+    };
+    let value: f64 = value.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" | "min" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(invalid()),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| invalid())
+}
+
+pub fn button(name: &str) -> Result<Name, String> {
+    let name = name.to_uppercase();
+    match BUTTONS.contains(&name.as_str()) {
+        true => Ok(command::name(&BUTTONS, &name)),
+        false => Err(format!("Unknown button {}", name)),
+    }
+}
+
+fn axis(value: &str) -> Result<i32, String> {
+    value.parse::<i32>()
+        .ok()
+        .filter(|value| (-32767..=32767).contains(value))
+        .ok_or_else(|| format!("Invalid stick value {}, expected -32767 to 32767", value))
+}
+
+// The commands of a statement, with their offset from where it starts, and how long it takes
+fn parse_statement(statement: &str) -> Result<(Vec<(Duration, Command)>, Duration), String> {
+    let words: Vec<&str> = statement.split_whitespace().collect();
+    let at_start = |command| Ok((vec![(Duration::ZERO, command)], Duration::ZERO));
+    match words.as_slice() {
+        ["press", name] => at_start(Command::PRESS(button(name)?)),
+        ["release", name] => at_start(Command::RELEASE(button(name)?)),
+        ["click", name] => at_start(Command::CLICK(button(name)?)),
+        ["wait", duration] => Ok((Vec::new(), parse_duration(duration)?)),
+        ["stick", stick, x, y, rest @ ..] => {
+            let stick = stick.to_uppercase();
+            if !STICKS.contains(&stick.as_str()) {
+                return Err(format!("Unknown stick {}", stick));
+            }
+            let stick = command::name(&STICKS, &stick);
+            let set = Command::SETSTICK(stick.clone(), axis(x)?, axis(y)?);
+            match rest {
+                [] => at_start(set),
+                ["for", duration] => {
+                    let duration = parse_duration(duration)?;
+                    Ok((vec![(Duration::ZERO, set), (duration, Command::SETSTICK(stick, 0, 0))], duration))
+                }
+                _ => Err(format!("Expected for <duration> after the stick values in {}", statement)),
+            }
+        }
+        _ => Err(format!("Unknown statement {}", statement)),
+    }
+}
+
+enum Token {
+    STATEMENT(String),
+    THEN,
+    ELSE,
+    END,
+}
+
+// then, else and end also end the statement before them, so a whole if fits on a line
+fn tokens(text: &str) -> Vec<(usize, Token)> {
+    let mut tokens = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        for piece in line.split(';') {
+            let mut statement: Vec<&str> = Vec::new();
+            for word in piece.split_whitespace().chain([";"]) {
+                let keyword = match word {
+                    "then" => Some(Token::THEN),
+                    "else" => Some(Token::ELSE),
+                    "end" => Some(Token::END),
+                    ";" => None,
+                    word => {
+                        statement.push(word);
+                        continue;
+                    }
+                };
+                if !statement.is_empty() {
+                    tokens.push((index + 1, Token::STATEMENT(statement.join(" "))));
+                    statement.clear();
+                }
+                tokens.extend(keyword.map(|keyword| (index + 1, keyword)));
+            }
+        }
+    }
+    tokens
+}
+
+// if peek(<location>, <size>) <operator> <value>, the location being a heap address or pointer chain
+fn parse_condition(condition: &str) -> Result<(Location, usize, Condition), String> {
+    let invalid = || format!("Invalid condition {}, expected something like peek(0x1234, 4) == 0", condition);
+    let arguments = condition.strip_prefix("peek(").ok_or_else(invalid)?;
+    let (arguments, comparison) = arguments.split_once(')').ok_or_else(invalid)?;
+    let (location, size) = arguments.rsplit_once(',').ok_or_else(invalid)?;
+    let size = size.trim().parse().map_err(|_| invalid())?;
+    Ok((location.trim().parse()?, size, comparison.parse()?))
+}
+
+// Statements become batches a delay after the previous one, the delay of a statement's
+// own duration is carried to whatever comes next
+struct Builder {
+    instructions: Vec<Instruction>,
+    pending: Duration,
+}
+
+impl Builder {
+    fn statement(&mut self, statement: &str) -> Result<(), String> {
+        let (commands, duration) = parse_statement(statement)?;
+        let mut at = Duration::ZERO;
+        for (offset, command) in commands {
+            self.instructions.push(Instruction::BATCH(self.pending + offset - at, vec![command]));
+            self.pending = Duration::ZERO;
+            at = offset;
+        }
+        self.pending += duration - at;
+        Ok(())
+    }
+
+    // Both ways of a branch have to start and end on time
+    fn flush(&mut self) {
+        if !self.pending.is_zero() {
+            self.instructions.push(Instruction::BATCH(std::mem::take(&mut self.pending), Vec::new()));
+        }
+    }
+
+    // Reads statements until the else or end closing the block, which is returned
+    fn block(&mut self, tokens: &mut Peekable<IntoIter<(usize, Token)>>) -> Result<Option<Token>, String> {
+        while let Some((line, token)) = tokens.next() {
+            let at_line = |err: String| format!("Line {}: {}", line, err);
+            let statement = match token {
+                Token::STATEMENT(statement) => statement,
+                closing => return Ok(Some(closing)),
+            };
+            let Some(condition) = statement.strip_prefix("if ") else {
+                self.statement(&statement).map_err(at_line)?;
+                continue;
+            };
+
+            let (location, size, condition) = parse_condition(condition.trim()).map_err(at_line)?;
+            if !matches!(tokens.next(), Some((_, Token::THEN))) {
+                return Err(at_line("Expected then after the condition".to_string()));
+            }
+            self.flush();
+            let branch = self.instructions.len();
+            self.instructions.push(Instruction::BRANCH(location.clone(), size, condition, 0));
+            let closing = self.block(tokens)?;
+            self.flush();
+            let otherwise = match closing {
+                Some(Token::ELSE) => {
+                    let jump = self.instructions.len();
+                    self.instructions.push(Instruction::JUMP(0));
+                    let otherwise = self.instructions.len();
+                    if !matches!(self.block(tokens)?, Some(Token::END)) {
+                        return Err(at_line("Missing end of the if".to_string()));
+                    }
+                    self.flush();
+                    self.instructions[jump] = Instruction::JUMP(self.instructions.len());
+                    otherwise
+                }
+                Some(Token::END) => self.instructions.len(),
+                _ => return Err(at_line("Missing end of the if".to_string())),
+            };
+            self.instructions[branch] = Instruction::BRANCH(location, size, condition, otherwise);
+        }
+        Ok(None)
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Instruction>, String> {
+    let mut builder = Builder { instructions: Vec::new(), pending: Duration::ZERO };
+    let mut tokens = tokens(text).into_iter().peekable();
+    match builder.block(&mut tokens)? {
+        None => {}
+        Some(Token::ELSE) => return Err("else outside of an if".to_string()),
+        Some(_) => return Err("then or end outside of an if".to_string()),
+    }
+    builder.flush();
+    Ok(builder.instructions)
+}
+
+pub fn load(path: &Path) -> Result<Vec<Instruction>, Error> {
+    fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|content| parse(&content))
+        .map_err(|reason| Error::LOAD { path: path.to_path_buf(), reason })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(text: &str) -> Command {
+        text.parse().unwrap()
+    }
+
+    // Batches as their delay and commands, branches and jumps as where they go
+    enum Step {
+        BATCH(u128, Vec<Command>),
+        BRANCH(usize),
+        JUMP(usize),
+    }
+
+    fn steps(text: &str) -> Vec<Step> {
+        parse(text).unwrap()
+            .into_iter()
+            .map(|instruction| match instruction {
+                Instruction::BATCH(delay, commands) => Step::BATCH(delay.as_millis(), commands),
+                Instruction::BRANCH(_, _, _, otherwise) => Step::BRANCH(otherwise),
+                Instruction::JUMP(to) => Step::JUMP(to),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn durations_default_to_milliseconds() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("250"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn waits_delay_the_next_batch_and_trail_at_the_end() {
+        let steps = steps("wait 500ms; press A\nwait 1s");
+        assert!(matches!(&steps[..], [Step::BATCH(500, pressed), Step::BATCH(1000, trailing)]
+            if *pressed == [command("press A")] && trailing.is_empty()));
+    }
+
+    #[test]
+    fn stick_for_centers_the_stick_after_the_duration() {
+        let steps = steps("stick LEFT 0 32767 for 2s; click A");
+        assert!(matches!(&steps[..], [Step::BATCH(0, tilted), Step::BATCH(2000, centered), Step::BATCH(0, clicked)]
+            if *tilted == [Command::SETSTICK("LEFT".into(), 0, 32767)]
+                && *centered == [Command::SETSTICK("LEFT".into(), 0, 0)]
+                && *clicked == [command("click A")]));
+    }
+
+    #[test]
+    fn nested_ifs_jump_past_their_own_branches() {
+        let steps = steps("click A
+            if peek(0x100, 4) == 1 then
+                if peek([main+10]+8, 1) != 0 then click B else click X end
+            else
+                click Y
+            end
+            click HOME");
+        assert!(matches!(&steps[..], [
+            Step::BATCH(0, _),
+            Step::BRANCH(7),
+            Step::BRANCH(5),
+            Step::BATCH(0, b),
+            Step::JUMP(6),
+            Step::BATCH(0, x),
+            Step::JUMP(8),
+            Step::BATCH(0, y),
+            Step::BATCH(0, home),
+        ] if *b == [command("click B")] && *x == [command("click X")] && *y == [command("click Y")] && *home == [command("click HOME")]));
+    }
+
+    #[test]
+    fn an_if_without_end_is_an_error() {
+        assert_eq!(parse("click A\nif peek(0x100, 4) == 1 then click B").err().as_deref(), Some("Line 2: Missing end of the if"));
+        assert_eq!(parse("if peek(0x100, 4) == 1 then click B else click X").err().as_deref(), Some("Line 1: Missing end of the if"));
+    }
+
+    #[test]
+    fn an_else_outside_of_an_if_is_an_error() {
+        assert_eq!(parse("click A; else click B").err().as_deref(), Some("else outside of an if"));
+    }
+}
@@ -1,410 +1,657 @@
-use bidirectional_map::Bimap;
-use futures_lite::future::block_on;
-use gilrs::EventType::Disconnected;
-use gilrs::{ev, Axis, Event, EventType, Gilrs};
-use lazy_static::lazy_static;
-use nusb::{Device, DeviceInfo, Interface};
-use std::collections::HashMap;
-use std::io::Write;
-use std::net::{Ipv4Addr, SocketAddr, TcpStream};
-use std::time::{Duration, SystemTime};
+#![allow(clippy::upper_case_acronyms)]
+
+mod cli;
+#[cfg(feature = "gamepad")]
+mod gamepad;
+
+use clap::Parser;
+use router::Router;
+use config::{Action, Binding, Config, Source, TlsSettings};
+use dryrun::DryRun;
+use command::Command;
+use connection::{ConnectionType, Mirror, Tcp};
+#[cfg(feature = "usb")]
+use connection::UsbDevice;
+use error::Error;
+#[cfg(feature = "usb")]
+use failover::Failover;
+use freeze::Freezes;
+use library::Library;
+use cli::MacroAction;
+use memory::{Bases, Location};
+#[cfg(feature = "usb")]
+use redundant::Redundant;
+use scan::Scan;
+use schedule::Scheduler;
+use serial::{Serial, SerialDevice};
+use transport::{Target, Transport};
+#[cfg(feature = "usb")]
+use usb::Usb;
+use watch::Watches;
+#[cfg(feature = "gamepad")]
+use gamepad::forward_gamepads;
+use std::fmt::Display;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use switch_usb_controld::*;
+use tracing::{error, info, warn};
+
+fn select_discovered_console() -> Option<SocketAddr> {
+    info!("Looking for switches on the local network...");
+    let consoles = discovery::discover_consoles(discovery::DEFAULT_PORT);
+    if consoles.is_empty() {
+        warn!("No switch found on the local network");
+        return None;
+    }
 
-lazy_static! {
-    static ref BTN_ASSOCIATION: Bimap<Button, gilrs::Button> = Bimap::from_hash_map(HashMap::from([
-        (Button::A, ev::Button::East),
-        (Button::B, ev::Button::South),
-        (Button::Y, ev::Button::West),
-        (Button::X, ev::Button::North),
-        (Button::DPADRIGHT, ev::Button::DPadRight),
-        (Button::DPADDOWN, ev::Button::DPadDown),
-        (Button::DPADLEFT, ev::Button::DPadLeft),
-        (Button::DPADUP, ev::Button::DPadUp),
-        (Button::R1, ev::Button::RightTrigger),
-        (Button::L1, ev::Button::LeftTrigger),
-        (Button::R2, ev::Button::RightTrigger2),
-        (Button::L2, ev::Button::LeftTrigger2),
-        (Button::R3, ev::Button::RightThumb),
-        (Button::L3, ev::Button::LeftThumb),
-        (Button::START, ev::Button::Start),
-        (Button::SELECT, ev::Button::Select),
-        (Button::HOME, ev::Button::Mode),
-        (Button::CAPTURE, ev::Button::Unknown)
-    ]));
-}
+    let mut options: Vec<String> = consoles.iter().map(|c| c.to_string()).collect();
+    options.push("Enter address manually".to_string());
+    let ans = inquire::Select::new("Which switch do you want to control?", options.clone())
+        .prompt()
+        .expect("No switch selected");
 
-enum ConnectionType {
-    USB,
-    INTERNET,
+    options.iter().position(|o| *o == ans)
+        .and_then(|i| consoles.get(i))
+        .map(|c| c.address)
 }
 
-enum Connection {
-    USB(Interface),
-    INTERNET(TcpStream),
-}
+fn input_ip_address() -> SocketAddr {
+    if let Some(address) = select_discovered_console() {
+        return address;
+    }
 
-#[derive(Eq, PartialEq, Clone, Copy)]
-#[derive(Hash, Debug)]
-enum Button {
-    A,
-    B,
-    X,
-    Y,
-    DPADRIGHT,
-    DPADDOWN,
-    DPADLEFT,
-    DPADUP,
-    R1,
-    L1,
-    R2,
-    L2,
-    R3,
-    L3,
-    START,
-    SELECT,
-    CAPTURE,
-    HOME,
-}
+    let ip = inquire::CustomType::<Ipv4Addr>::new("Enter the IP Address of the switch")
+        .with_error_message("Please type an IPv4 address like 192.168.1.20")
+        .prompt()
+        .expect("No IP entered");
 
-enum Stick {
-    RIGHT,
-    LEFT
-}
+    let port = inquire::CustomType::<u16>::new("Enter the port of the switch")
+        .with_default(6000)
+        .with_error_message("Please type a port number")
+        .prompt()
+        .expect("No port entered");
 
-#[derive(Debug, Copy, Clone)]
-enum ButtonState {
-    PRESSED,
-    HELD,
-    RELEASED,
+    SocketAddr::from((ip, port))
 }
 
-#[derive(Debug)]
-struct ControllerState {
-    button_states: HashMap<Button, ButtonState>,
-    r_stick: (i32, i32),
-    l_stick: (i32, i32),
-    old_r_stick: (i32, i32),
-    old_l_stick: (i32, i32),
-    old_state: Option<HashMap<Button, ButtonState>>,
-}
+fn input_targets() -> Vec<Target> {
+    let targets = inquire::Text::new("Enter the switches to mirror inputs to (e.g. usb, usb:1, usb:<serial>, 192.168.1.20:6000)")
+        .prompt()
+        .expect("No switch entered");
 
-const fn get_button_name(button: Button) -> &'static str {
-    match button {
-        Button::A => "A",
-        Button::B => "B",
-        Button::X => "X",
-        Button::Y => "Y",
-        Button::DPADRIGHT => "DRIGHT",
-        Button::DPADDOWN => "DDOWN",
-        Button::DPADLEFT => "DLEFT",
-        Button::DPADUP => "DUP",
-        Button::R1 => "R",
-        Button::L1 => "L",
-        Button::R2 => "ZR",
-        Button::L2 => "ZL",
-        Button::R3 => "RSTICK",
-        Button::L3 => "LSTICK",
-        Button::START => "PLUS",
-        Button::SELECT => "MINUS",
-        Button::CAPTURE => "CAPTURE",
-        Button::HOME => "HOME"
-    }
+    targets.split(',')
+        .map(|target| target.parse().expect("Invalid switch"))
+        .collect()
 }
 
-impl ControllerState {
-    fn new() -> ControllerState {
-        ControllerState {
-            r_stick: (0, 0),
-            l_stick: (0, 0),
-            old_l_stick: (0, 0),
-            old_r_stick: (0, 0),
-            button_states: HashMap::new(),
-            old_state: None,
-        }
+#[cfg(feature = "usb")]
+fn input_usb_device() -> UsbDevice {
+    let devices = usb::list_switch_devices();
+    if devices.len() < 2 {
+        return UsbDevice::INDEX(0);
     }
 
-    fn get_old_state(&self) -> HashMap<Button, ButtonState> {
-        let x = &self.old_state;
-        x.clone().unwrap_or_default()
-    }
+    let options: Vec<String> = devices.iter().map(usb::describe_device).collect();
+    let ans = inquire::Select::new("Several switches are plugged in, which one do you want to use?", options.clone())
+        .prompt()
+        .expect("No switch selected");
 
-    fn set_button_states(&mut self, (new_button, new_state): (Button, ButtonState)) -> (Button, ButtonState) {
-        let binding = self.get_old_state();
-        let old_button_state = binding.get(&new_button);
-        if let Some(reference) = self.button_states.get_mut(&new_button) {
-            match *reference {
-                ButtonState::PRESSED => {
-                    match new_state {
-                        ButtonState::PRESSED => {(new_button, *reference)},
-                        ButtonState::HELD => {(new_button, *reference)}
-                        ButtonState::RELEASED => {(new_button, *reference)}
-                    }
-                }
-                ButtonState::HELD => {
-                    match new_state {
-                        ButtonState::PRESSED => { *reference = ButtonState::PRESSED; (new_button, *reference)}
-                        ButtonState::HELD => {(new_button, *reference)}
-                        ButtonState::RELEASED => { *reference = ButtonState::PRESSED; (new_button, *reference)}
-                    }
-                }
-                ButtonState::RELEASED => {
-                    match new_state {
-                        ButtonState::PRESSED => { *reference = ButtonState::HELD; (new_button, *reference) }
-                        ButtonState::HELD => { *reference = ButtonState::HELD; (new_button, *reference) }
-                        ButtonState::RELEASED => {(new_button, *reference)}
-                    }
-                }
-            }
-        } else if let Some(old_state) = old_button_state {
-            match *old_state {
-                ButtonState::PRESSED => {
-                    match new_state {
-                        ButtonState::PRESSED => { self.button_states.insert(new_button, ButtonState::PRESSED); (new_button, ButtonState::PRESSED)}
-                        ButtonState::HELD => { self.button_states.insert(new_button, ButtonState::HELD); (new_button, ButtonState::HELD)}
-                        ButtonState::RELEASED => { self.button_states.insert(new_button, ButtonState::RELEASED); (new_button, ButtonState::RELEASED)}
-                    }
-                }
-                ButtonState::HELD => {
-                    match new_state {
-                        ButtonState::PRESSED => { self.button_states.insert(new_button, ButtonState::PRESSED); (new_button, ButtonState::PRESSED)}
-                        ButtonState::HELD => {(new_button, ButtonState::HELD)}
-                        ButtonState::RELEASED => {
-                            self.button_states.insert(new_button, ButtonState::RELEASED);
-                            (new_button, ButtonState::RELEASED)
-                        }
-                    }
-                }
-                ButtonState::RELEASED => {
-                    match new_state {
-                        ButtonState::PRESSED => { self.button_states.insert(new_button, ButtonState::PRESSED); (new_button, ButtonState::PRESSED)}
-                        ButtonState::HELD => { self.button_states.insert(new_button, ButtonState::HELD); (new_button, ButtonState::HELD)}
-                        ButtonState::RELEASED => {(new_button, ButtonState::RELEASED)}
-                    }
-                }
-            }
-        } else {
-            self.button_states.insert(new_button, new_state);
-            (new_button, new_state)
-        }
+    UsbDevice::INDEX(options.iter().position(|o| *o == ans).unwrap_or(0))
+}
+
+fn input_serial_port() -> String {
+    let ports = serial::list_ports();
+    if ports.is_empty() {
+        return inquire::Text::new("Serial port of the microcontroller:").prompt().expect("No serial port given");
     }
 
+    inquire::Select::new("Which serial port is the microcontroller on?", ports)
+        .prompt()
+        .expect("No serial port selected")
+}
 
-    fn make_packets(&self) -> Vec<String> {
-        let mut packets: Vec<_> = self.button_states.iter().map(|(button, state)| make_packet_for_button_state(*button, *state)).collect();
+fn input_connection_type() -> ConnectionType {
+    let ans = inquire::Select::new("What kind of connection do you want?", vec!["Internet", "USB", "USB with internet failover", "USB and internet simultaneously", "Several switches at once", "Serial microcontroller", "Bluetooth Pro Controller (no CFW)", "USB gadget plugged into the dock (no CFW)"]).prompt().expect("No connection type selected");
 
-        if self.old_r_stick != self.r_stick {
-            packets.push(make_packet_for_stick(Stick::RIGHT, self.r_stick))
-        }
+    match ans {
+        "Internet" => ConnectionType::INTERNET,
+        "USB" => ConnectionType::USB,
+        "USB with internet failover" => ConnectionType::FAILOVER,
+        "USB and internet simultaneously" => ConnectionType::REDUNDANT,
+        "Several switches at once" => ConnectionType::MIRROR,
+        "Serial microcontroller" => ConnectionType::SERIAL,
+        "Bluetooth Pro Controller (no CFW)" => ConnectionType::BLUETOOTH,
+        "USB gadget plugged into the dock (no CFW)" => ConnectionType::GADGET,
+        _ => panic!("Unknown connection type!")
+    }
+}
 
-        if self.old_l_stick != self.l_stick {
-            packets.push(make_packet_for_stick(Stick::LEFT, self.l_stick))
+// What wasn't given on the command line is asked for again on each attempt, so a mistyped address can be fixed
+fn connect(args: &cli::Args, connection_type: ConnectionType) -> Result<Box<dyn Transport>, Error> {
+    Ok(match connection_type {
+        #[cfg(feature = "usb")]
+        ConnectionType::USB => Box::new(Usb::connect(args.serial.clone().unwrap_or_else(input_usb_device))?),
+        ConnectionType::INTERNET => Box::new(Tcp::connect(args.address.unwrap_or_else(input_ip_address))?),
+        #[cfg(feature = "usb")]
+        ConnectionType::FAILOVER => {
+            let device = args.serial.clone().unwrap_or_else(input_usb_device);
+            Box::new(Failover::new(device, args.address.unwrap_or_else(input_ip_address))?)
         }
-
-        packets
-    }
+        #[cfg(feature = "usb")]
+        ConnectionType::REDUNDANT => {
+            let device = args.serial.clone().unwrap_or_else(input_usb_device);
+            Box::new(Redundant::new(device, args.address.unwrap_or_else(input_ip_address))?)
+        }
+        ConnectionType::MIRROR => {
+            let targets = if args.targets.is_empty() { input_targets() } else { args.targets.clone() };
+            Box::new(Mirror::connect(&targets))
+        }
+        ConnectionType::SERIAL => {
+            let path = args.port.clone().unwrap_or_else(input_serial_port);
+            Box::new(Serial::connect(SerialDevice { path, baud: args.baud })?)
+        }
+        ConnectionType::BLUETOOTH => "bluetooth".parse::<Target>().unwrap().connect()?,
+        ConnectionType::GADGET => "gadget".parse::<Target>().unwrap().connect()?,
+        #[cfg(not(feature = "usb"))]
+        ConnectionType::USB | ConnectionType::FAILOVER | ConnectionType::REDUNDANT => return Err(Error::NOUSB),
+    })
 }
 
-fn make_packet_for_stick(stick: Stick, value: (i32, i32)) -> String {
-    match stick {
-        Stick::RIGHT => format!("setStick RIGHT {} {}", to_hex_string(value.0), to_hex_string(value.1)),
-        Stick::LEFT => format!("setStick LEFT {} {}", to_hex_string(value.0), to_hex_string(value.1))
-    }
+// Nothing to run without it, the process ends as a failure
+fn fail(err: impl Display) -> ! {
+    error!("{}", err);
+    std::process::exit(1)
 }
 
-fn make_packet_for_button_state(button: Button, state: ButtonState) -> String {
-    match state {
-        ButtonState::PRESSED => {
-            format!("click {}", get_button_name(button))
+fn main() {
+    let args = cli::Args::parse();
+    if let Err(err) = logging::init(args.verbose, args.log_file.as_deref()) {
+        fail(err);
+    }
+    if let Some(cli::Command::Export { capture, script }) = &args.command {
+        match capture::load(capture).map_err(|err| err.to_string()).and_then(|records| nxtas::export(&records, script)) {
+            Ok(0) => println!("Exported {} to {}", capture.display(), script.display()),
+            Ok(skipped) => println!("Exported {} to {}, leaving out {} command(s) nx-TAS can't express", capture.display(), script.display(), skipped),
+            Err(err) => println!("Unable to export {} ({})", capture.display(), err),
         }
-        ButtonState::HELD => {
-            format!("press {}", get_button_name(button))
+        return;
+    }
+    if let Some(cli::Command::Macro { dir, action }) = &args.command {
+        let mut library = Library::open(dir).unwrap_or_else(|err| fail(err));
+        let result = match action {
+            MacroAction::List => {
+                library.list();
+                Ok(())
+            }
+            MacroAction::Show { name } => library.show(name),
+            MacroAction::Describe { name, description } => library.describe(name, description),
+            MacroAction::Delete { name } => library.delete(name),
+            MacroAction::Rename { name, new_name } => library.rename(name, new_name),
+            MacroAction::Export { archive, names } => library.export(names, archive),
+            MacroAction::Import { archive, force } => library.import(archive, *force),
+        };
+        if let Err(err) = result {
+            println!("{}", err);
         }
-        ButtonState::RELEASED => {
-            format!("release {}", get_button_name(button))
+        return;
+    }
+    // Asked before connecting, so the prompts don't wait with a controller attached
+    let recipe = match &args.command {
+        Some(cli::Command::Template { name, save }) => {
+            let text = match name.as_deref().map(template::find).unwrap_or_else(template::select).and_then(|template| template.prompt()) {
+                Ok(text) => text,
+                Err(err) => fail(err),
+            };
+            if let Some(save) = save {
+                match std::fs::write(save, &text) {
+                    Ok(()) => println!("Saved the automation to {}", save.display()),
+                    Err(err) => println!("Unable to write {} ({})", save.display(), err),
+                }
+                return;
+            }
+            Some(template::instructions(&text).unwrap_or_else(|err| fail(err)))
+        }
+        _ => None,
+    };
+    let date_skip = match &args.command {
+        Some(cli::Command::DateSkip { count, firmware }) => {
+            let profile = firmware.map(Ok).unwrap_or_else(dateskip::prompt_profile).unwrap_or_else(|err| fail(err));
+            let count = count.map(Ok).unwrap_or_else(dateskip::prompt_count).unwrap_or_else(|err| fail(err));
+            Some((profile, count))
         }
+        _ => None,
+    };
+    let config = Config::load(args.config.as_deref()).unwrap_or_else(|err| fail(err));
+    #[cfg(feature = "tui")]
+    let profile = format!(
+        "{}, {} controller",
+        match args.config.as_deref() {
+            Some(path) => path.display().to_string(),
+            None if Path::new(config::DEFAULT_CONFIG_PATH).exists() => config::DEFAULT_CONFIG_PATH.to_string(),
+            None => "no config file".to_string(),
+        },
+        args.controller.or(config.controller).map(|controller| format!("{:?}", controller)).unwrap_or_else(|| "default".to_string())
+    );
+    #[cfg(feature = "usb")]
+    usb::configure_usb(config.usb);
+    protocol::configure(config.protocol);
+    connection::configure_tcp(config.tcp);
+    notify::configure(args.notify);
+    let tls_settings = match &args.tls_server_name {
+        Some(server_name) => Some(TlsSettings {
+            server_name: server_name.clone(),
+            ca_file: config.tls.as_ref().and_then(|tls| tls.ca_file.clone()),
+        }),
+        None => config.tls.clone(),
+    };
+    if let Some(tls_settings) = &tls_settings {
+        tls::configure(tls_settings).unwrap_or_else(|err| fail(err));
     }
-}
-
-
-fn to_hex_string(n: i32) -> String {
-    if !(-0x8000..=0x7FFF).contains(&n) {
-        panic!("Number out of range for 16-bit signed integer");
+    let secret = args.secret.clone().or(config.secret.clone());
+    // Only a relay asks for it, a plain sys-botbase would never send the challenge
+    if let Some(secret) = &secret {
+        if matches!(args.command, Some(cli::Command::Join { .. })) {
+            auth::configure(secret.clone());
+        }
     }
 
-    if n < 0 {
-        format!("-0x{:>04X}", -n)
-    } else {
-        format!("0x{:>04X}", n)
+    // The window picks and opens the link itself
+    #[cfg(feature = "gui")]
+    if matches!(args.command, Some(cli::Command::Gui)) {
+        let tick = Duration::from_millis(config.tick_interval_ms.unwrap_or(100));
+        if let Err(err) = gui::run(&config.targets, tick) {
+            fail(err);
+        }
+        return;
     }
-}
 
+    let (mut router, wait_for) = if let Some(cli::Command::Join { host, .. }) = &args.command {
+        let address = match host {
+            #[cfg(feature = "webrtc")]
+            _ if matches!(args.command, Some(cli::Command::Join { webrtc: true, .. })) => webrtc::join().unwrap_or_else(|err| fail(err)),
+            Some(host) => relay::resolve(host).unwrap_or_else(|err| fail(err)),
+            None => fail(Error::INVALID { what: "join".to_string(), reason: "no relay host given".to_string() }),
+        };
+        info!("Joining relay at {}", address);
+        (Router::single(Box::new(transport::retry(|| Tcp::connect(address)))), Duration::from_millis(100))
+    } else if args.dry_run {
+        (Router::single(Box::new(DryRun::new())), Duration::from_millis(100))
+    } else if config.targets.is_empty() {
+        let connection_type = match args.connection {
+            Some(connection_type) => connection_type,
+            None if !args.targets.is_empty() => ConnectionType::MIRROR,
+            None => input_connection_type(),
+        };
+        // Retrying wouldn't help, the build itself can't reach the switch this way
+        #[cfg(not(feature = "usb"))]
+        if matches!(connection_type, ConnectionType::USB | ConnectionType::FAILOVER | ConnectionType::REDUNDANT) {
+            fail(Error::NOUSB);
+        }
 
-fn get_axis_values(value: f32) -> i32 {
-    let val = (value * 32767.) as i32;
-    if val.abs() < 5000 {
-        0
+        let wait_for = match connection_type {
+            ConnectionType::USB | ConnectionType::REDUNDANT => Duration::from_millis(66),
+            ConnectionType::INTERNET | ConnectionType::FAILOVER | ConnectionType::MIRROR | ConnectionType::SERIAL | ConnectionType::BLUETOOTH | ConnectionType::GADGET => Duration::from_millis(100)
+        };
+        (Router::single(transport::retry(|| connect(&args, connection_type))), wait_for)
     } else {
-        val
-    }
-}
+        (Router::new(&config.targets, config.routes).unwrap_or_else(|err| fail(err)), Duration::from_millis(100))
+    };
+    let wait_for = config.tick_interval_ms.map(Duration::from_millis).unwrap_or(wait_for);
 
-fn get_switch_device_info() -> DeviceInfo {
-    for device_info in nusb::list_devices().unwrap() {
-        if device_info.vendor_id() == 0x057e && device_info.product_id() == 0x3000 {
-            return device_info;
-        }
+    info!("Successfully connected to {}!", router.describe());
+    shutdown::install();
+    for spec in &config.sources {
+        router.add_source(plugin::open_source(spec).unwrap_or_else(|err| fail(err)));
     }
-
-    panic!("Unable to find a switch device!");
-}
-
-fn get_device(device_info: DeviceInfo) -> Device {
-    match device_info.open() {
-        Ok(handle) => {
-            println!("Opened switch device!");
-            handle
+    for spec in &config.sinks {
+        router.add_sink(plugin::open_sink(spec).unwrap_or_else(|err| fail(err)));
+    }
+    let wait_for_title = args.wait_for_title.or_else(|| config.wait_for_title.as_deref().map(|title| {
+        memory::parse_address(title).unwrap_or_else(|reason| fail(Error::INVALID { what: "title to wait for".to_string(), reason }))
+    }));
+    if let Some(title) = wait_for_title {
+        game::wait_for(&mut router, title);
+        if shutdown::requested() {
+            return;
         }
-        Err(err) => {
-            panic!("Failed to open switch device! Error: {}", err)
+    }
+    // sys-botbase keeps these settings until it restarts, they are sent before any input
+    let mut configure = args.timing.or(config.timing).commands();
+    if let Some(controller) = args.controller.or(config.controller) {
+        configure.push(Command::OTHER(format!("configure controllerType {}", controller.device_type())));
+    }
+    if !configure.is_empty() {
+        router.send(Source::MACRO, 0, configure);
+    }
+    if let Some(minutes) = args.anti_idle.or(config.anti_idle_minutes) {
+        router.keep_awake(Duration::from_secs(minutes * 60));
+    }
+    if let Some(path) = &args.capture {
+        router.capture_to(capture::Capture::create(path).unwrap_or_else(|source| fail(Error::CREATE { path: path.clone(), source })));
+        info!("Capturing outgoing commands to {}", path.display());
+    }
+    if let Some(path) = &args.input_log {
+        router.log_inputs_to(inputlog::InputLog::create(path).unwrap_or_else(|source| fail(Error::CREATE { path: path.clone(), source })));
+        info!("Logging inputs to {}", path.display());
+    }
+    if let Some(listen) = args.metrics {
+        match metrics::Metrics::serve(listen) {
+            Ok(metrics) => router.serve_metrics(metrics),
+            Err(err) => warn!("{}, running without metrics", err),
         }
     }
-}
-
-fn build_packets(data: Vec<String>) -> Vec<Vec<u8>> {
-    data.iter().flat_map(|s| {
-        [
-            ((s.len() + 2) as i32).to_le_bytes().to_vec(),
-            s.as_bytes().to_vec()
-        ]
-    }).collect()
-}
-fn write_packet(interface: &Interface, data: Vec<Vec<u8>>) {
-    let mut queue = interface.bulk_out_queue(0x1);
-    let data_len = data.len();
-    data.into_iter().for_each(|x| queue.submit(x));
-    for _ in 0..data_len {
-        let _ = block_on(queue.next_complete());
+    if args.report.is_some() {
+        router.keep_report();
     }
-}
-
-fn process_button_action(controller_state: &mut ControllerState, btn: &gilrs::Button, state: ButtonState) {
-    if let Some(switch_key) = BTN_ASSOCIATION.get_rev(btn) {
-        controller_state.set_button_states((*switch_key, state));
+    if let Some(listen) = args.overlay {
+        match overlay::Overlay::serve(listen) {
+            Ok(overlay) => router.broadcast_to(overlay),
+            Err(err) => warn!("{}, running without the overlay", err),
+        }
     }
-}
 
-fn input_ip_address() -> SocketAddr {
-    let ip = inquire::Text::new("Enter the IP Address of the switch")
-        .prompt()
-        .expect("Invalid IP");
-
-    let port = inquire::Text::new("Enter the port of the switch")
-        .prompt()
-        .expect("Invalid Port");
-
-    SocketAddr::from((ip.as_str().parse::<Ipv4Addr>().expect("Invalid IP"), port.parse::<u16>().expect("Invalid port number")))
-}
-
-fn main() {
-    let ans = inquire::Select::new("What kind of connection do you want?", vec!["Internet", "USB"]).prompt().expect("No connection type selected");
+    if args.realtime {
+        match realtime::raise() {
+            Ok(core) => info!("Sending at real-time priority on core {}", core),
+            Err(err) => warn!("Unable to raise the priority ({}), ticks may be less regular", err),
+        }
+    }
 
-    let connection_type = match ans {
-        "Internet" => ConnectionType::INTERNET,
-        "USB" => ConnectionType::USB,
-        _ => panic!("Unknown connection type!")
+    let bindings = config.bindings;
+    let mut freezes = Freezes::new(&config.freezes).unwrap_or_else(|err| fail(err));
+    let mut watches = Watches::new(config.watches);
+    let mut bases = Bases::new();
+    #[cfg(feature = "tray")]
+    let mut tray_icon = match args.tray {
+        true => {
+            let active = args.config.clone().or_else(|| Path::new(config::DEFAULT_CONFIG_PATH).exists().then(|| config::DEFAULT_CONFIG_PATH.into()));
+            tray::Tray::open(active.as_deref())
+                .map_err(|err| warn!("Unable to show the tray icon ({}), forwarding without it", err))
+                .ok()
+        }
+        false => None,
     };
-
-    let mut connection = match connection_type {
-        ConnectionType::USB => {
-            let device_info = get_switch_device_info();
-            let device = get_device(device_info);
-            device.reset().expect("cannot reset");
-            Connection::USB(device.claim_interface(0).unwrap())
+    match &args.command {
+        Some(cli::Command::Serve { listen, .. }) => {
+            #[cfg(feature = "webrtc")]
+            if matches!(args.command, Some(cli::Command::Serve { webrtc: true, .. })) {
+                if let Err(err) = webrtc::serve(*listen) {
+                    fail(err);
+                }
+            }
+            if let Err(err) = relay::serve(*listen, secret, &mut router) {
+                fail(err);
+            }
         }
-        ConnectionType::INTERNET => {
-            Connection::INTERNET(TcpStream::connect(input_ip_address()).expect("Cannot connect to switch"))
+        Some(cli::Command::WaitFor { title }) => game::wait_for(&mut router, *title),
+        #[cfg(feature = "scripting")]
+        Some(cli::Command::Script { file }) => script::run(&mut router, file),
+        Some(cli::Command::Mash { button, rate, count }) => {
+            let button = macros::button(button).unwrap_or_else(|err| fail(err));
+            mash::run(&mut router, button, rate.max(0.1), *count)
         }
-    };
-
-    println!("Successfully connected to switch device!");
-
-    println!("Please connect and press a button on your controller");
-    let mut gilrs = Gilrs::new().unwrap();
-    let mut active_gamepad = None;
-    let mut exit = false;
-    let mut controller_state = ControllerState::new();
-    while !exit {
-        let a = SystemTime::now();
-        let wait_for: Duration = match connection_type {
-            ConnectionType::USB => Duration::from_millis(66),
-            ConnectionType::INTERNET => Duration::from_millis(100)
-        };
-
-        while SystemTime::now().duration_since(a).unwrap_or(Duration::from_millis(0)).lt(&wait_for) {
-            while let Some(Event { id, event, .. }) = gilrs.next_event() {
-                if active_gamepad.is_none() {
-                    active_gamepad = Some(id);
-                    println!("Controller connected !");
-                } else if active_gamepad.unwrap() != id {
-                    continue
+        Some(cli::Command::Export { .. }) => unreachable!("exports don't connect"),
+        Some(cli::Command::Macro { .. }) => unreachable!("the macro library doesn't connect"),
+        #[cfg(feature = "gui")]
+        Some(cli::Command::Gui) => unreachable!("the window connects itself"),
+        Some(cli::Command::Template { .. }) => capture::play(&recipe.unwrap_or_default(), &mut router),
+        Some(cli::Command::DateSkip { .. }) => {
+            if let Some((profile, count)) = date_skip {
+                if let Err(err) = dateskip::run(&mut router, profile, count) {
+                    fail(err);
                 }
-
-                match event {
-                    Disconnected => {
-                        exit = true;
-                        break;
-                    }
-                    e => {
-                        match e {
-                            EventType::ButtonPressed(btn, _) => {
-                                process_button_action(&mut controller_state, &btn, ButtonState::HELD);
-                            }
-                            EventType::ButtonReleased(btn, _) => {
-                                process_button_action(&mut controller_state, &btn, ButtonState::RELEASED);
-                            }
-                            EventType::AxisChanged(axis, value, _) => {
-                                match axis {
-                                    Axis::LeftStickX => { controller_state.l_stick.0 = get_axis_values(value)}
-                                    Axis::LeftStickY => { controller_state.l_stick.1 = get_axis_values(value) }
-                                    Axis::RightStickX => { controller_state.r_stick.0 = get_axis_values(value)}
-                                    Axis::RightStickY => { controller_state.r_stick.1 = get_axis_values(value)}
-                                    _ => { }
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
+            }
+        }
+        Some(cli::Command::Play { capture, repeat, loop_forever, loop_delay, speed }) => {
+            let repeat = if *loop_forever { None } else { Some(*repeat) };
+            match capture::load_macro(capture) {
+                Ok(instructions) => capture::play_looped(&instructions, &mut router, repeat, Duration::from_millis(*loop_delay), *speed),
+                Err(err) => error!("{}", err),
+            }
+        }
+        Some(cli::Command::Query { command }) => {
+            let command = command.parse::<Command>().unwrap_or_else(|reason| fail(Error::INVALID { what: format!("command {}", command), reason }));
+            repl::execute(&mut router, command)
+        }
+        Some(cli::Command::Peek { location, size, region }) => {
+            let bytes = match location {
+                Location::ADDRESS(offset) => bases.peek(&mut router, *region, *offset, *size),
+                Location::POINTER(_) => memory::peek_at(&mut router, *region, location, *size),
+            };
+            match bytes {
+                Ok(bytes) => println!("{}", memory::describe(&bytes)),
+                Err(err) => println!("Unable to read {} ({})", location, err),
+            }
+        }
+        Some(cli::Command::Poke { location, data, region }) => {
+            let data = memory::parse_data(data).unwrap_or_else(|reason| fail(Error::INVALID { what: "data".to_string(), reason }));
+            memory::poke_at(&mut router, *region, location, &data)
+        }
+        Some(cli::Command::Dump { region, offset, size, file, chunk }) => {
+            if let Err(err) = dump::dump(&mut router, *region, *offset, *size, file, (*chunk).max(1)) {
+                println!("Unable to dump 0x{:X} ({})", offset, err);
+            }
+        }
+        Some(cli::Command::Pointer { chain }) => match memory::resolve(&mut router, chain) {
+            Ok(address) => match bases.relative(&mut router, address) {
+                Ok((region, offset)) => println!("{} = 0x{:X} ({}+0x{:X})", chain, address, region.name(), offset),
+                Err(_) => println!("{} = 0x{:X}", chain, address),
+            },
+            Err(err) => println!("Unable to follow {} ({})", chain, err),
+        },
+        Some(cli::Command::Scan { step }) => {
+            let result = match step {
+                cli::ScanStep::New { region, offset, size, value, width, file } => {
+                    Scan::first(&mut router, *region, *offset, *size, *width, *value).and_then(|scan| {
+                        scan.print(0);
+                        scan.save(file)
+                    })
                 }
+                cli::ScanStep::Next { filter, value, file } => Scan::load(file).and_then(|mut scan| {
+                    scan.next(&mut router, *filter, *value)?;
+                    scan.print(0);
+                    scan.save(file)
+                }),
+                cli::ScanStep::List { limit, file } => Scan::load(file).map(|scan| scan.print(*limit)),
+            };
+            if let Err(err) = result {
+                println!("Unable to scan ({})", err);
             }
         }
-
-        if !exit && active_gamepad.is_some() {
-            use Connection::*;
-            let packet_strings = controller_state.make_packets();
-            if !packet_strings.is_empty() {
-                match connection {
-                    USB(ref interface) => {
-                        let packets = build_packets(packet_strings);
-                        write_packet(interface, packets);
-                    }
-                    INTERNET(ref mut socket) => {
-                        packet_strings.iter()
-                            .map(|s| format!("{}\r\n", s))
-                            .for_each(|p| {
-                                socket.write_all(p.as_bytes()).expect("Unable to send packet");
-                            });
-                    }
+        Some(cli::Command::Cheat { file, names, off }) => {
+            let cheats = cheat::load(file).unwrap_or_else(|err| fail(err));
+            if names.is_empty() {
+                cheat::list(&cheats);
+            }
+            for name in names {
+                let Some(found) = cheat::find(&cheats, name) else {
+                    println!("No cheat named {} in {}", name, file.display());
+                    continue;
+                };
+                let applied = match off {
+                    true => cheat::remove(&mut router, &mut bases, &mut freezes, found),
+                    false => cheat::apply(&mut router, &mut bases, &mut freezes, found),
+                };
+                if let Err(err) = applied {
+                    println!("Unable to {} {} ({})", if *off { "remove" } else { "apply" }, name, err);
                 }
             }
         }
+        Some(cli::Command::Freeze { target, value }) => {
+            if let Err(err) = freezes.freeze(&mut router, target, value.as_deref()) {
+                println!("Unable to freeze {} ({})", target, err);
+            }
+        }
+        Some(cli::Command::Unfreeze { all: true, .. }) => freezes.clear(&mut router),
+        Some(cli::Command::Unfreeze { target, .. }) => {
+            let target = target.as_deref().unwrap_or_default();
+            if let Err(err) = freezes.unfreeze(&mut router, target) {
+                println!("Unable to unfreeze {} ({})", target, err);
+            }
+        }
+        Some(cli::Command::Freezes) => freezes.list(&mut router),
+        Some(cli::Command::Watch { location: Some(location), size, interval, when, play }) => {
+            let Some(size) = size else {
+                fail(Error::INVALID { what: format!("watch {}", location), reason: "no size given".to_string() });
+            };
+            let mut watches = watch::adhoc(location.clone(), *size, *interval, *when, play.clone());
+            watch::run(&mut router, &mut watches)
+        }
+        Some(cli::Command::Watch { .. }) => watch::run(&mut router, &mut watches),
+        Some(cli::Command::Info) => info::print(&mut router, &mut bases),
+        Some(cli::Command::Bench { count, interval }) => bench::run(&mut router, *count, Duration::from_millis(*interval)),
+        Some(cli::Command::Screenshot { dir }) => screenshot::save(&mut router, dir),
+        Some(cli::Command::ScreenOff) => perform(&mut router, &mut freezes, Action::SCREENOFF, None),
+        Some(cli::Command::ScreenOn) => perform(&mut router, &mut freezes, Action::SCREENON, None),
+        Some(cli::Command::Detach) => perform(&mut router, &mut freezes, Action::DETACH, None),
+        Some(cli::Command::Attach) => perform(&mut router, &mut freezes, Action::ATTACH, None),
+        Some(cli::Command::Type { text, clipboard }) => {
+            let commands = match text {
+                Some(text) if !clipboard => keyboard::type_commands(text),
+                _ => keyboard::paste_commands(),
+            };
+            router.send(Source::MACRO, 0, commands.unwrap_or_else(|err| fail(err)));
+        }
+        Some(cli::Command::Repl { forward: true }) => {
+            let typed = repl::spawn();
+            forward_gamepads(&mut router, wait_for, &bindings, &mut freezes, &mut |router| typed.try_iter().for_each(|typed| repl::handle(router, typed)), &mut |_, _, _| true)
+        }
+        Some(cli::Command::Repl { forward: false }) => repl::run(&mut router),
+        #[cfg(feature = "preview")]
+        Some(cli::Command::Preview { forward: true }) => {
+            let mut preview = preview::Preview::open().unwrap_or_else(|err| fail(err));
+            forward_gamepads(&mut router, wait_for, &bindings, &mut freezes, &mut |router| preview.refresh(router), &mut |_, _, _| true)
+        }
+        #[cfg(feature = "preview")]
+        Some(cli::Command::Preview { forward: false }) => {
+            if let Err(err) = preview::run(&mut router) {
+                fail(err);
+            }
+        }
+        #[cfg(feature = "scripting")]
+        Some(cli::Command::Join { .. }) | None if args.script.is_some() => {
+            // Both closures call into the script, which only ever runs one of them at a time
+            let path = args.script.as_deref().expect("Checked by the match guard");
+            let hooks = std::cell::RefCell::new(script::Hooks::load(path, &mut router).unwrap_or_else(|err| fail(err)));
+            let mut scheduler = Scheduler::new(&config.schedule).unwrap_or_else(|err| fail(err));
+            #[cfg(feature = "tui")]
+            let mut dashboard = match args.tui {
+                true => tui::Dashboard::open(profile).map_err(|err| warn!("{}, forwarding without it", err)).ok(),
+                false => None,
+            };
+            let mut on_tick = |router: &mut Router| {
+                watches.poll(router);
+                scheduler.poll(router);
+                hooks.borrow_mut().tick(router);
+                #[cfg(feature = "tui")]
+                if let Some(dashboard) = &mut dashboard {
+                    dashboard.refresh(router);
+                }
+                #[cfg(feature = "tray")]
+                if let Some(tray_icon) = &mut tray_icon {
+                    tray_icon.refresh(router);
+                }
+            };
+            forward_gamepads(&mut router, wait_for, &bindings, &mut freezes, &mut on_tick, &mut |router, button, pressed| hooks.borrow_mut().button(router, button, pressed))
+        }
+        Some(cli::Command::Join { .. }) | None => {
+            let mut scheduler = Scheduler::new(&config.schedule).unwrap_or_else(|err| fail(err));
+            #[cfg(feature = "tui")]
+            let mut dashboard = match args.tui {
+                true => tui::Dashboard::open(profile).map_err(|err| warn!("{}, forwarding without it", err)).ok(),
+                false => None,
+            };
+            let mut on_tick = |router: &mut Router| {
+                watches.poll(router);
+                scheduler.poll(router);
+                #[cfg(feature = "tui")]
+                if let Some(dashboard) = &mut dashboard {
+                    dashboard.refresh(router);
+                }
+                #[cfg(feature = "tray")]
+                if let Some(tray_icon) = &mut tray_icon {
+                    tray_icon.refresh(router);
+                }
+            };
+            forward_gamepads(&mut router, wait_for, &bindings, &mut freezes, &mut on_tick, &mut |_, _, _| true)
+        }
+    }
 
-        //Crappy but oh well
-        controller_state.old_state = Some(controller_state.button_states);
-        controller_state.old_l_stick = controller_state.l_stick;
-        controller_state.old_r_stick = controller_state.r_stick;
-        controller_state.button_states = HashMap::new();
+    router.release_all();
+    // Attaching is the whole point of the attach command
+    if !args.stay_attached && !matches!(args.command, Some(cli::Command::Attach)) {
+        router.detach();
+    }
+    router.stats().iter().for_each(|line| info!("{}", line));
+    if let Some(path) = &args.report {
+        match router.write_report(path) {
+            Ok(()) => info!("Wrote the session report to {}", path.display()),
+            Err(err) => warn!("Unable to write the session report to {} ({})", path.display(), err),
+        }
+    }
+    #[cfg(feature = "tray")]
+    if let Some(profile) = tray_icon.as_ref().and_then(tray::Tray::switched_to).map(Path::to_path_buf) {
+        drop(tray_icon);
+        fail(format!("Unable to start again with {} ({})", profile.display(), tray::restart(&profile)));
     }
 }
 
+fn perform(router: &mut Router, freezes: &mut Freezes, action: Action, binding: Option<&Binding>) {
+    match action {
+        Action::PASTE => match keyboard::paste_commands() {
+            Ok(commands) => router.send(Source::MACRO, 0, commands),
+            Err(err) => warn!("{}", err),
+        },
+        Action::SCREENSHOT => screenshot::save(router, Path::new(".")),
+        Action::SCREENOFF => router.send(Source::MACRO, 0, vec![Command::OTHER("screenOff".to_string())]),
+        Action::SCREENON => router.send(Source::MACRO, 0, vec![Command::OTHER("screenOn".to_string())]),
+        Action::DETACH => router.send(Source::MACRO, 0, vec![Command::OTHER(router::DETACH.to_string())]),
+        // Any input attaches the controller, a centered stick doesn't move anything
+        Action::ATTACH => router.send(Source::MACRO, 0, vec![Command::SETSTICK("LEFT".into(), 0, 0)]),
+        Action::FREEZE => match binding.and_then(|binding| binding.freeze.as_deref()) {
+            Some(freeze) => {
+                if let Err(err) = freezes.toggle(router, freeze) {
+                    warn!("Unable to toggle {} ({})", freeze, err);
+                }
+            }
+            None => warn!("The freeze action needs the name of a freeze"),
+        },
+        // Playing and mashing are started and stopped by forward_gamepads, which keeps them going between ticks
+        Action::PLAY | Action::MASH | Action::BIND | Action::PAUSE | Action::STEP => {}
+        Action::STATS => router.stats().iter().for_each(|line| println!("{}", line)),
+        Action::RECORD => match binding.and_then(|binding| binding.file.clone()) {
+            Some(file) => router.toggle_recording(&file),
+            None => {
+                let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                router.toggle_recording(Path::new(&format!("macro-{}.jsonl", timestamp.as_secs())))
+            }
+        },
+    }
+}
 
-
-
+// Headless builds have no gamepad to forward, what runs between ticks keeps going until stopped
+#[cfg(not(feature = "gamepad"))]
+fn forward_gamepads(
+    router: &mut Router,
+    wait_for: Duration,
+    _bindings: &[Binding],
+    _freezes: &mut Freezes,
+    on_tick: &mut dyn FnMut(&mut Router),
+    _on_button: &mut dyn FnMut(&mut Router, &str, bool) -> bool,
+) {
+    println!("Built without gamepad support, press Ctrl+C to stop");
+    let mut ticker = ticker::Ticker::new(wait_for);
+    let mut overrun = overrun::Overrun::new(wait_for);
+    while !shutdown::requested() {
+        overrun.begin();
+        on_tick(router);
+        overrun.lap("tick");
+        router.poll_sources();
+        overrun.lap("sources");
+        router.heartbeat();
+        overrun.lap("heartbeat");
+        overrun.finish();
+        ticker.wait();
+    }
+}
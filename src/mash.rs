@@ -0,0 +1,51 @@
+use crate::command::{Command, Name};
+use crate::config::Source;
+use crate::router::Router;
+use crate::shutdown;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// sys-botbase holds each click for its buttonClickSleepTime (50ms by default), faster rates
+// only queue clicks up
+pub const DEFAULT_RATE: f64 = 10.0;
+
+pub struct Mash {
+    button: Name,
+    interval: Duration,
+    next: Instant,
+}
+
+impl Mash {
+    pub fn new(button: Name, rate: f64) -> Mash {
+        let interval = Duration::try_from_secs_f64(1.0 / rate).unwrap_or(Duration::from_millis(100));
+        info!("Mashing {} {} times a second", button, rate);
+        Mash { button, interval, next: Instant::now() }
+    }
+
+    pub fn due(&self) -> Instant {
+        self.next
+    }
+
+    // Clicks once when due, late clicks aren't caught up on
+    pub fn poll(&mut self, router: &mut Router) -> bool {
+        if Instant::now() < self.next {
+            return false;
+        }
+        router.send(Source::MACRO, 0, vec![Command::CLICK(self.button.clone())]);
+        self.next = Instant::now().max(self.next + self.interval);
+        true
+    }
+}
+
+pub fn run(router: &mut Router, button: Name, rate: f64, count: Option<u64>) {
+    let mut mash = Mash::new(button, rate);
+    let mut clicks = 0;
+    while count.is_none_or(|count| clicks < count) && !shutdown::requested() {
+        if mash.poll(router) {
+            clicks += 1;
+        }
+        router.heartbeat();
+        thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::command::Command;
+use crate::config::Source;
+use crate::hex;
+use crate::response::Response;
+use crate::router::Router;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// How long cached bases are trusted before checking the running title again
+const TITLE_CHECK: Duration = Duration::from_secs(1);
+
+// heap addresses are relative to the game heap, main ones to the main executable
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    HEAP,
+    MAIN,
+    ABSOLUTE,
+}
+
+impl Region {
+    fn suffix(self) -> &'static str {
+        match self {
+            Region::HEAP => "",
+            Region::MAIN => "Main",
+            Region::ABSOLUTE => "Absolute",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::HEAP => "heap",
+            Region::MAIN => "main",
+            Region::ABSOLUTE => "absolute",
+        }
+    }
+}
+
+pub fn parse_address(s: &str) -> Result<u64, String> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|_| format!("Invalid address {}", s))
+}
+
+fn parse_offset(s: &str) -> Result<i64, String> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let offset = i64::from_str_radix(digits.trim_start_matches("0x"), 16).map_err(|_| format!("Invalid offset {}", s))?;
+    Ok(if negative { -offset } else { offset })
+}
+
+fn format_offset(offset: i64) -> String {
+    if offset < 0 {
+        format!("-0x{:X}", -offset)
+    } else {
+        format!("0x{:X}", offset)
+    }
+}
+
+// [[main+A]+B]+C reads main+A, then that value+B, and ends up at the second value+C,
+// which sys-botbase takes as the jumps A B C
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PointerChain(Vec<i64>);
+
+impl PointerChain {
+    fn jumps(&self) -> String {
+        self.0.iter().map(|jump| format_offset(*jump)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl FromStr for PointerChain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid pointer chain {}, expected something like [[main+A]+B]+C", s);
+        let s = s.trim().replace(' ', "");
+        let depth = s.chars().take_while(|c| *c == '[').count();
+        let rest = s[depth..].strip_prefix("main").filter(|_| depth > 0).ok_or_else(invalid)?;
+
+        let mut jumps = Vec::new();
+        let mut parts = rest.split(']');
+        for _ in 0..depth {
+            jumps.push(parse_offset(parts.next().ok_or_else(invalid)?).map_err(|_| invalid())?);
+        }
+        match (parts.next(), parts.next()) {
+            (Some(""), None) => jumps.push(0),
+            (Some(last), None) => jumps.push(parse_offset(last).map_err(|_| invalid())?),
+            _ => return Err(invalid()),
+        }
+        Ok(PointerChain(jumps))
+    }
+}
+
+impl Display for PointerChain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (last, dereferenced) = self.0.split_last().ok_or(std::fmt::Error)?;
+        write!(f, "{}main", "[".repeat(dereferenced.len()))?;
+        for jump in dereferenced {
+            match jump {
+                0.. => write!(f, "+{}]", format_offset(*jump))?,
+                _ => write!(f, "{}]", format_offset(*jump))?,
+            }
+        }
+        match last {
+            0 => Ok(()),
+            1.. => write!(f, "+{}", format_offset(*last)),
+            _ => write!(f, "{}", format_offset(*last)),
+        }
+    }
+}
+
+// Where peek and poke go, a plain address in a region or the end of a pointer chain
+#[derive(Clone, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Location {
+    ADDRESS(u64),
+    POINTER(PointerChain),
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start().starts_with('[') {
+            true => s.parse().map(Location::POINTER),
+            false => parse_address(s).map(Location::ADDRESS),
+        }
+    }
+}
+
+impl TryFrom<String> for Location {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::ADDRESS(address) => write!(f, "0x{:X}", address),
+            Location::POINTER(chain) => write!(f, "{}", chain),
+        }
+    }
+}
+
+// Bytes are written in memory order, 0x0102 pokes 01 then 02
+pub fn parse_data(s: &str) -> Result<Vec<u8>, String> {
+    hex::decode(s.trim_start_matches("0x"))
+        .filter(|data| !data.is_empty())
+        .ok_or_else(|| format!("Invalid data {}, expected hex bytes", s))
+}
+
+fn read(router: &mut Router, command: Command, size: usize) -> Result<Vec<u8>, String> {
+    match router.request(&command)? {
+        Response::BYTES(bytes) if bytes.len() == size => Ok(bytes),
+        Response::BYTES(bytes) => Err(format!("Asked for {} bytes, got {}", size, bytes.len())),
+        response => Err(format!("Unexpected response {}", response)),
+    }
+}
+
+pub fn peek(router: &mut Router, region: Region, address: u64, size: usize) -> Result<Vec<u8>, String> {
+    read(router, Command::OTHER(format!("peek{} 0x{:X} {}", region.suffix(), address, size)), size)
+}
+
+pub fn poke(router: &mut Router, region: Region, address: u64, data: &[u8]) {
+    let command = Command::OTHER(format!("poke{} 0x{:X} 0x{}", region.suffix(), address, hex::encode(data)));
+    router.send(Source::MACRO, 0, vec![command]);
+}
+
+// The absolute address a pointer chain currently ends at
+pub fn resolve(router: &mut Router, chain: &PointerChain) -> Result<u64, String> {
+    match router.request(&Command::OTHER(format!("pointerAll {}", chain.jumps())))? {
+        Response::NUMBER(address) => Ok(address),
+        response => Err(format!("Unexpected response {}", response)),
+    }
+}
+
+pub fn pointer_peek(router: &mut Router, chain: &PointerChain, size: usize) -> Result<Vec<u8>, String> {
+    read(router, Command::OTHER(format!("pointerPeek {} {}", size, chain.jumps())), size)
+}
+
+pub fn pointer_poke(router: &mut Router, chain: &PointerChain, data: &[u8]) {
+    let command = Command::OTHER(format!("pointerPoke 0x{} {}", hex::encode(data), chain.jumps()));
+    router.send(Source::MACRO, 0, vec![command]);
+}
+
+pub fn peek_at(router: &mut Router, region: Region, location: &Location, size: usize) -> Result<Vec<u8>, String> {
+    match location {
+        Location::ADDRESS(address) => peek(router, region, *address, size),
+        Location::POINTER(chain) => pointer_peek(router, chain, size),
+    }
+}
+
+pub fn poke_at(router: &mut Router, region: Region, location: &Location, data: &[u8]) {
+    match location {
+        Location::ADDRESS(address) => poke(router, region, *address, data),
+        Location::POINTER(chain) => pointer_poke(router, chain, data),
+    }
+}
+
+fn number(router: &mut Router, command: &str) -> Result<u64, String> {
+    match router.request(&Command::OTHER(command.to_string()))? {
+        Response::NUMBER(number) => Ok(number),
+        response => Err(format!("Unexpected response {}", response)),
+    }
+}
+
+// getHeapBase and getMainNsoBase are asked once per title, launching another game drops them.
+// Writes keep the relative poke commands so that links which can't read still take them
+#[derive(Default)]
+pub struct Bases {
+    title: Option<u64>,
+    checked: Option<Instant>,
+    heap: Option<u64>,
+    main: Option<u64>,
+}
+
+impl Bases {
+    pub fn new() -> Bases {
+        Bases::default()
+    }
+
+    pub fn invalidate(&mut self) {
+        *self = Bases::default();
+    }
+
+    fn check_title(&mut self, router: &mut Router) -> Result<(), String> {
+        if self.checked.is_some_and(|checked| checked.elapsed() < TITLE_CHECK) {
+            return Ok(());
+        }
+        let title = number(router, "getTitleID")?;
+        if self.title != Some(title) {
+            self.invalidate();
+            self.title = Some(title);
+        }
+        self.checked = Some(Instant::now());
+        Ok(())
+    }
+
+    pub fn base(&mut self, router: &mut Router, region: Region) -> Result<u64, String> {
+        if region == Region::ABSOLUTE {
+            return Ok(0);
+        }
+        self.check_title(router)?;
+        let (cached, command) = match region {
+            Region::MAIN => (&mut self.main, "getMainNsoBase"),
+            _ => (&mut self.heap, "getHeapBase"),
+        };
+        if let Some(base) = cached {
+            return Ok(*base);
+        }
+        let base = number(router, command)?;
+        *cached = Some(base);
+        Ok(base)
+    }
+
+    pub fn absolute(&mut self, router: &mut Router, region: Region, offset: u64) -> Result<u64, String> {
+        let base = self.base(router, region)?;
+        base.checked_add(offset).ok_or_else(|| format!("0x{:X} is past the end of memory", offset))
+    }
+
+    // The region an absolute address falls in, the closest base below it
+    pub fn relative(&mut self, router: &mut Router, address: u64) -> Result<(Region, u64), String> {
+        let heap = self.base(router, Region::HEAP)?;
+        let main = self.base(router, Region::MAIN)?;
+        let mut regions = [(Region::HEAP, heap), (Region::MAIN, main)];
+        regions.sort_by_key(|(_, base)| std::cmp::Reverse(*base));
+        Ok(regions.into_iter()
+            .find(|(_, base)| address >= *base)
+            .map(|(region, base)| (region, address - base))
+            .unwrap_or((Region::ABSOLUTE, address)))
+    }
+
+    pub fn peek(&mut self, router: &mut Router, region: Region, offset: u64, size: usize) -> Result<Vec<u8>, String> {
+        let address = self.absolute(router, region, offset)?;
+        peek(router, Region::ABSOLUTE, address, size)
+    }
+}
+
+// Small values are also shown as the little endian integer the game most likely stores
+pub fn describe(bytes: &[u8]) -> String {
+    if bytes.is_empty() || bytes.len() > 8 {
+        return hex::encode(bytes);
+    }
+
+    let mut value = [0; 8];
+    value[..bytes.len()].copy_from_slice(bytes);
+    format!("{} ({})", hex::encode(bytes), u64::from_le_bytes(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(s: &str) -> PointerChain {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn nested_chains_read_back_as_written() {
+        let parsed = chain("[[main+A]+B]+C");
+        assert_eq!(parsed, PointerChain(vec![0xA, 0xB, 0xC]));
+        assert_eq!(parsed.to_string(), "[[main+0xA]+0xB]+0xC");
+        assert_eq!(chain(&parsed.to_string()), parsed);
+        assert_eq!(parsed.jumps(), "0xA 0xB 0xC");
+    }
+
+    #[test]
+    fn negative_offsets_keep_their_sign() {
+        let parsed = chain("[[main-10]+0x20]-8");
+        assert_eq!(parsed, PointerChain(vec![-0x10, 0x20, -0x8]));
+        assert_eq!(parsed.to_string(), "[[main-0x10]+0x20]-0x8");
+        assert_eq!(chain(&parsed.to_string()), parsed);
+    }
+
+    #[test]
+    fn a_missing_last_offset_is_zero() {
+        let parsed = chain("[main+4C8]");
+        assert_eq!(parsed, PointerChain(vec![0x4C8, 0]));
+        assert_eq!(parsed, chain("[main+4C8]+0"));
+        assert_eq!(parsed.to_string(), "[main+0x4C8]");
+        assert_eq!(chain(&parsed.to_string()), parsed);
+    }
+
+    #[test]
+    fn malformed_chains_are_refused() {
+        for invalid in ["main+A", "[heap+A]", "[main+A]]", "[main+G]", "[main+A]+B+C"] {
+            assert!(invalid.parse::<PointerChain>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::error::Error;
+use crate::health::{Histogram, LinkHealth};
+use crate::router::Router;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+const PREFIX: &str = "switch_usb_controld";
+// Scrapes are seconds apart, rendering on every tick would be wasted
+const PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// Powers of two of the health histogram, from 128µs to about a minute
+const BUCKET_STEP: usize = 4;
+
+// The router renders its counters now and then, the listening thread serves the last rendering
+pub struct Metrics {
+    text: Arc<Mutex<String>>,
+    published: Option<Instant>,
+}
+
+impl Metrics {
+    pub fn serve(listen: SocketAddr) -> Result<Metrics, Error> {
+        let listener = TcpListener::bind(listen).map_err(|source| Error::LISTEN { address: listen, source })?;
+        info!("Serving metrics at http://{}/metrics", listen);
+        let text = Arc::new(Mutex::new(String::new()));
+        let served = text.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(err) = answer(stream, &served) {
+                    debug!("Unable to answer a metrics scrape ({})", err);
+                }
+            }
+        });
+        Ok(Metrics { text, published: None })
+    }
+
+    pub fn due(&self) -> bool {
+        self.published.is_none_or(|published| published.elapsed() >= PUBLISH_INTERVAL)
+    }
+
+    pub fn publish(&mut self, text: String) {
+        *self.text.lock().unwrap() = text;
+        self.published = Some(Instant::now());
+    }
+}
+
+fn answer(stream: TcpStream, text: &Mutex<String>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers don't matter, they are read so the client isn't reset before it gets the answer
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut stream = reader.into_inner();
+    match request.split_whitespace().nth(1) {
+        Some("/metrics") => {
+            let text = text.lock().unwrap().clone();
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", text.len(), text)
+        }
+        _ => write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    }
+}
+
+fn label(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn header(text: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(text, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(text, "# TYPE {}_{} {}", PREFIX, name, kind);
+}
+
+fn counter(text: &mut String, router: &Router, name: &str, help: &str, value: fn(&LinkHealth) -> u64) {
+    header(text, name, "counter", help);
+    for (target, health) in router.health() {
+        let _ = writeln!(text, "{}_{}{{target=\"{}\"}} {}", PREFIX, name, label(target), value(health));
+    }
+}
+
+fn histogram(text: &mut String, name: &str, target: &str, latencies: &Histogram) {
+    for (bound, count) in latencies.cumulative().skip(BUCKET_STEP - 1).step_by(BUCKET_STEP) {
+        let _ = writeln!(text, "{}_{}_bucket{{target=\"{}\",le=\"{}\"}} {}", PREFIX, name, target, bound.as_secs_f64(), count);
+    }
+    let _ = writeln!(text, "{}_{}_bucket{{target=\"{}\",le=\"+Inf\"}} {}", PREFIX, name, target, latencies.total);
+    let _ = writeln!(text, "{}_{}_sum{{target=\"{}\"}} {}", PREFIX, name, target, latencies.sum.as_secs_f64());
+    let _ = writeln!(text, "{}_{}_count{{target=\"{}\"}} {}", PREFIX, name, target, latencies.total);
+}
+
+// Prometheus text exposition format
+pub fn render(router: &Router) -> String {
+    let mut text = String::new();
+    header(&mut text, "uptime_seconds", "gauge", "Time since the session started");
+    let _ = writeln!(text, "{}_uptime_seconds {}", PREFIX, router.uptime().as_secs_f64());
+    header(&mut text, "dropped_events_total", "counter", "Input events that never made it to a console");
+    let _ = writeln!(text, "{}_dropped_events_total {}", PREFIX, router.dropped_events());
+    header(&mut text, "coalesced_commands_total", "counter", "Commands left out because they wouldn't change anything");
+    let _ = writeln!(text, "{}_coalesced_commands_total {}", PREFIX, router.coalesced());
+
+    counter(&mut text, router, "packets_total", "Batches and heartbeats sent", |health| health.sends);
+    counter(&mut text, router, "commands_total", "Commands sent", |health| health.commands);
+    counter(&mut text, router, "bytes_total", "Bytes of commands sent", |health| health.bytes);
+    counter(&mut text, router, "failures_total", "Sends that failed", |health| health.failures);
+    counter(&mut text, router, "reconnects_total", "Sends that needed a reconnection", |health| health.reconnects);
+    header(&mut text, "send_latency_seconds", "histogram", "Time a send took, reconnections included");
+    for (target, health) in router.health() {
+        histogram(&mut text, "send_latency_seconds", &label(target), &health.latencies);
+    }
+    text
+}
@@ -0,0 +1,55 @@
+use notify_rust::Notification;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use tracing::{debug, warn};
+
+const APP: &str = "switch-usb-controld";
+
+// The summaries of the events shown, listeners like the tray tell them apart by these
+pub const SWITCH_LOST: &str = "Switch disconnected";
+pub const SWITCH_BACK: &str = "Switch reconnected";
+pub const GAMEPAD_LOST: &str = "Gamepad disconnected";
+
+static NOTIFIER: OnceLock<Sender<(String, String)>> = OnceLock::new();
+static LISTENERS: Mutex<Vec<Sender<(String, String)>>> = Mutex::new(Vec::new());
+
+// Off unless asked for, most of the time someone is watching the terminal. Shown one at a time
+// from a single thread, a notification server that is slow to answer doesn't hold up sending
+pub fn configure(enabled: bool) {
+    if !enabled {
+        return;
+    }
+    let (sender, notifications) = mpsc::channel::<(String, String)>();
+    if NOTIFIER.set(sender).is_err() {
+        return;
+    }
+    thread::spawn(move || {
+        let mut warned = false;
+        for (summary, body) in notifications {
+            let Err(failure) = Notification::new().appname(APP).summary(&summary).body(&body).show() else {
+                continue;
+            };
+            match warned {
+                false => warn!("Unable to show a desktop notification ({}), later failures only show in the debug log", failure),
+                true => debug!("Unable to show a desktop notification ({})", failure),
+            }
+            warned = true;
+        }
+    });
+}
+
+// Never waited for, the notifier thread shows them in order
+pub fn send(summary: &str, body: &str) {
+    if let Some(sender) = NOTIFIER.get() {
+        let _ = sender.send((summary.to_string(), body.to_string()));
+    }
+    LISTENERS.lock().unwrap().retain(|listener| listener.send((summary.to_string(), body.to_string())).is_ok());
+}
+
+// Gets the same events whether notifications are shown or not, like the tray does
+pub fn listen() -> Receiver<(String, String)> {
+    let (sender, events) = mpsc::channel();
+    LISTENERS.lock().unwrap().push(sender);
+    events
+}
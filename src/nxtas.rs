@@ -0,0 +1,225 @@
+use crate::capture::Record;
+use crate::command::{self, Command, Name, BUTTONS};
+use crate::error::Error;
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::Path;
+
+// nx-TAS scripts run at 60 frames per second, frames without a line hold nothing
+const FRAME_RATE: u64 = 60;
+
+// A line is "<frame> <KEY_A;KEY_B or NONE> <lx;ly> <rx;ry>"
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Frame {
+    keys: BTreeSet<Name>,
+    left: (i32, i32),
+    right: (i32, i32),
+}
+
+fn parse_stick(s: &str) -> Result<(i32, i32), String> {
+    let (x, y) = s.split_once(';').ok_or_else(|| format!("Invalid stick {}", s))?;
+    let axis = |value: &str| {
+        value.trim().parse::<i32>().ok()
+            .filter(|value| (-32767..=32767).contains(value))
+            .ok_or_else(|| format!("Invalid stick value {}, expected -32767 to 32767", value))
+    };
+    Ok((axis(x)?, axis(y)?))
+}
+
+fn parse_line(line: &str) -> Result<(u64, Frame), String> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let [frame, keys, left, right] = parts.as_slice() else {
+        return Err(format!("Expected a frame, keys and two sticks in {}", line));
+    };
+    let frame = frame.parse().map_err(|_| format!("Invalid frame {}", frame))?;
+    let keys = match *keys {
+        "NONE" => BTreeSet::new(),
+        keys => keys.split(';')
+            .map(|key| {
+                key.strip_prefix("KEY_")
+                    .filter(|button| BUTTONS.contains(button))
+                    .map(|button| command::name(&BUTTONS, button))
+                    .ok_or_else(|| format!("Invalid key {}", key))
+            })
+            .collect::<Result<_, _>>()?,
+    };
+    Ok((frame, Frame { keys, left: parse_stick(left)?, right: parse_stick(right)? }))
+}
+
+fn changes(from: &Frame, to: &Frame) -> Vec<Command> {
+    let mut commands: Vec<Command> = from.keys.difference(&to.keys).map(|key| Command::RELEASE(key.clone())).collect();
+    commands.extend(to.keys.difference(&from.keys).map(|key| Command::PRESS(key.clone())));
+    if from.left != to.left {
+        commands.push(Command::SETSTICK("LEFT".into(), to.left.0, to.left.1));
+    }
+    if from.right != to.right {
+        commands.push(Command::SETSTICK("RIGHT".into(), to.right.0, to.right.1));
+    }
+    commands
+}
+
+// Converted to press, release and setStick records at the time of their frame
+pub fn load(path: &Path) -> Result<Vec<Record>, Error> {
+    let invalid = |reason: String| Error::LOAD { path: path.to_path_buf(), reason };
+    let content = fs::read_to_string(path).map_err(|err| invalid(err.to_string()))?;
+    let mut frames: Vec<(u64, Frame)> = content.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| parse_line(line).map_err(|err| invalid(format!("invalid line {}: {}", index + 1, err))))
+        .collect::<Result<_, _>>()?;
+    frames.sort_by_key(|(frame, _)| *frame);
+
+    let mut records = Vec::new();
+    let mut held = Frame::default();
+    let mut previous = None;
+    let mut push = |frame: u64, state: &Frame, held: &mut Frame| {
+        let micros = frame * 1_000_000 / FRAME_RATE;
+        for command in changes(held, state) {
+            records.push(Record { micros, target: "nx-tas".to_string(), command: command.to_string() });
+        }
+        *held = state.clone();
+    };
+    for (frame, state) in &frames {
+        // The frame after a line without a follow-up goes back to nothing held
+        if let Some(previous) = previous.filter(|previous| previous + 1 < *frame) {
+            push(previous + 1, &Frame::default(), &mut held);
+        }
+        push(*frame, state, &mut held);
+        previous = Some(*frame);
+    }
+    if let Some(previous) = previous {
+        push(previous + 1, &Frame::default(), &mut held);
+    }
+    Ok(records)
+}
+
+fn format_line(frame: u64, state: &Frame) -> String {
+    let keys = match state.keys.is_empty() {
+        true => "NONE".to_string(),
+        false => state.keys.iter().map(|key| format!("KEY_{}", key)).collect::<Vec<_>>().join(";"),
+    };
+    format!("{} {} {};{} {};{}", frame, keys, state.left.0, state.left.1, state.right.0, state.right.1)
+}
+
+// Every frame with something held gets a line, a click holds its button for one frame.
+// Commands nx-TAS can't express are left out and counted
+pub fn export(records: &[Record], path: &Path) -> Result<usize, String> {
+    let mut events: Vec<(u64, Command)> = Vec::new();
+    let mut seen: HashSet<(u64, &str)> = HashSet::new();
+    for record in records {
+        let command: Command = record.command.parse()
+            .map_err(|err| format!("Invalid command {} in capture: {}", record.command, err))?;
+        let frame = (record.micros * FRAME_RATE + 500_000) / 1_000_000;
+        // Broadcast commands were captured once per target
+        if seen.insert((frame, record.command.as_str())) {
+            events.push((frame, command));
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut skipped = 0;
+    let mut state = Frame::default();
+    let mut clicked: Vec<Name> = Vec::new();
+    let mut pressed: Vec<Name> = Vec::new();
+    let mut events = events.into_iter().peekable();
+    let mut frame = 0;
+    loop {
+        for key in clicked.drain(..) {
+            state.keys.remove(&key);
+        }
+        pressed.clear();
+        while let Some((_, command)) = events.next_if(|(at, _)| *at <= frame) {
+            match command {
+                Command::PRESS(key) => {
+                    if state.keys.insert(key.clone()) {
+                        pressed.push(key);
+                    }
+                }
+                // Let go in the frame it was pressed, it is held for that frame like a click
+                Command::RELEASE(key) if pressed.contains(&key) => clicked.push(key),
+                Command::RELEASE(key) => {
+                    state.keys.remove(&key);
+                }
+                Command::CLICK(key) => {
+                    state.keys.insert(key.clone());
+                    clicked.push(key);
+                }
+                Command::SETSTICK(stick, x, y) if stick == "LEFT" => state.left = (x, y),
+                Command::SETSTICK(_, x, y) => state.right = (x, y),
+                _ => skipped += 1,
+            }
+        }
+        if state != Frame::default() {
+            lines.push(format_line(frame, &state));
+        }
+        // A held state never ends on its own, stop after the last event if nothing releases it
+        if events.peek().is_none() && clicked.is_empty() {
+            break;
+        }
+        frame += 1;
+    }
+
+    let mut content = lines.join("\n");
+    content.push('\n');
+    fs::write(path, content).map_err(|err| format!("Unable to write {}: {}", path.display(), err))?;
+    Ok(skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("switch-usb-controld-{}-{}", std::process::id(), name))
+    }
+
+    fn record(micros: u64, target: &str, command: &str) -> Record {
+        Record { micros, target: target.to_string(), command: command.to_string() }
+    }
+
+    #[test]
+    fn a_script_exports_back_to_itself() {
+        let script = "0 KEY_A 0;0 0;0\n1 KEY_A;KEY_B 0;0 0;0\n2 NONE 32767;0 0;0\n5 KEY_ZR 0;0 -100;200\n";
+        let (loaded, exported) = (temp("load.txt"), temp("export.txt"));
+        fs::write(&loaded, script).unwrap();
+
+        let records = load(&loaded).unwrap();
+        assert_eq!(export(&records, &exported), Ok(0));
+        let content = fs::read_to_string(&exported).unwrap();
+        let _ = fs::remove_file(&loaded);
+        let _ = fs::remove_file(&exported);
+        assert_eq!(content, script);
+    }
+
+    #[test]
+    fn a_line_without_follow_up_is_released_the_next_frame() {
+        let path = temp("release.txt");
+        fs::write(&path, "3 KEY_A 0;0 0;0\n").unwrap();
+        let records = load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        let commands: Vec<(u64, &str)> = records.iter().map(|record| (record.micros, record.command.as_str())).collect();
+        assert_eq!(commands, [(50_000, "press A"), (66_666, "release A")]);
+    }
+
+    #[test]
+    fn a_press_and_release_within_a_frame_hold_for_one_frame() {
+        let path = temp("same-frame.txt");
+        let records = [record(0, "a", "press A"), record(4_000, "a", "release A"), record(50_000, "a", "click B")];
+        assert_eq!(export(&records, &path), Ok(0));
+        let content = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(content, "0 KEY_A 0;0 0;0\n3 KEY_B 0;0 0;0\n");
+    }
+
+    #[test]
+    fn broadcast_commands_count_once() {
+        let path = temp("broadcast.txt");
+        let records = [record(0, "a", "press A"), record(0, "b", "press A"), record(16_667, "a", "release A"), record(16_667, "b", "release A"), record(16_667, "b", "detachController")];
+        assert_eq!(export(&records, &path), Ok(1));
+        let content = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(content, "0 KEY_A 0;0 0;0\n");
+    }
+}
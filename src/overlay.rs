@@ -0,0 +1,142 @@
+use crate::command::{Command, STICKS};
+use crate::error::Error;
+use crate::held::HeldState;
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info};
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// A browser source that stops reading is dropped rather than slowing the others down
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+enum OverlayEvent {
+    CONNECTED(TcpStream),
+    STATE(String, String),
+}
+
+// Sends what each console holds as JSON to the connected browser sources, after every batch
+// of inputs. Clients are written to on their own thread so a stalled one never delays a send
+pub struct Overlay {
+    sender: Sender<OverlayEvent>,
+}
+
+impl Overlay {
+    pub fn serve(listen: SocketAddr) -> Result<Overlay, Error> {
+        let listener = TcpListener::bind(listen).map_err(|source| Error::LISTEN { address: listen, source })?;
+        info!("Broadcasting the controller state at ws://{}", listen);
+        let (sender, events) = mpsc::channel();
+        let accepted = sender.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let accepted = accepted.clone();
+                thread::spawn(move || match handshake(&stream) {
+                    Ok(()) => {
+                        let _ = accepted.send(OverlayEvent::CONNECTED(stream));
+                    }
+                    Err(err) => debug!("Rejected an overlay client ({})", err),
+                });
+            }
+        });
+        thread::spawn(move || {
+            let mut clients: Vec<TcpStream> = Vec::new();
+            // New clients start from the last state of each target
+            let mut states: BTreeMap<String, String> = BTreeMap::new();
+            for event in events {
+                match event {
+                    OverlayEvent::CONNECTED(mut stream) => {
+                        if states.values().all(|state| write_text(&mut stream, state).is_ok()) {
+                            clients.push(stream);
+                        }
+                    }
+                    OverlayEvent::STATE(target, state) => {
+                        clients.retain_mut(|stream| write_text(stream, &state).is_ok());
+                        states.insert(target, state);
+                    }
+                }
+            }
+        });
+        Ok(Overlay { sender })
+    }
+
+    // Clicks are only in the batch that sent them, what is held stays until released
+    pub fn publish(&self, target: &str, held: &HeldState, commands: &[Command]) {
+        let clicked: Vec<&str> = commands.iter()
+            .filter_map(|command| match command {
+                Command::CLICK(button) => Some(button.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let sticks: BTreeMap<&str, (i32, i32)> = STICKS.iter().map(|stick| (*stick, held.stick(stick))).collect();
+        let state = json!({
+            "target": target,
+            "buttons": held.buttons().collect::<Vec<_>>(),
+            "clicked": clicked,
+            "sticks": sticks,
+        });
+        let _ = self.sender.send(OverlayEvent::STATE(target.to_string(), state.to_string()));
+    }
+}
+
+fn handshake(stream: &TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut key = None;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_string());
+            }
+        }
+        line.clear();
+    }
+
+    let mut stream = reader.into_inner();
+    let Some(key) = key else {
+        write!(stream, "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a WebSocket request"));
+    };
+    let accept = base64(digest(&SHA1_FOR_LEGACY_USE_ONLY, format!("{}{}", key, WEBSOCKET_GUID).as_bytes()).as_ref());
+    write!(stream, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))
+}
+
+// A single unmasked text frame, as servers send them
+fn write_text(stream: &mut TcpStream, text: &str) -> io::Result<()> {
+    let mut frame = vec![0x81];
+    match text.len() {
+        length @ 0..126 => frame.push(length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(text.as_bytes());
+    stream.write_all(&frame)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let word = chunk.iter().enumerate().fold(0u32, |word, (index, byte)| word | (*byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => encoded.push(BASE64[(word >> (18 - 6 * index) & 0x3F) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,74 @@
+use std::time::{Duration, Instant};
+use tracing::{trace, warn};
+
+// Often enough to notice, not so often that the warnings themselves slow the loop down
+const WARNING_INTERVAL: Duration = Duration::from_secs(5);
+// Quicker phases aren't where the time went
+const NOTABLE_PHASE: Duration = Duration::from_micros(100);
+
+// Times the phases of each pass of a send loop and warns when a pass takes longer than its budget,
+// with how long each phase took, so "inputs feel delayed" can be traced to a slow link or hook
+pub struct Overrun {
+    budget: Duration,
+    start: Option<Instant>,
+    lap: Instant,
+    phases: Vec<(&'static str, Duration)>,
+    overruns: u64,
+    worst: Duration,
+    warned: Option<Instant>,
+}
+
+impl Overrun {
+    pub fn new(budget: Duration) -> Overrun {
+        Overrun { budget, start: None, lap: Instant::now(), phases: Vec::new(), overruns: 0, worst: Duration::ZERO, warned: None }
+    }
+
+    // The pass starts when the loop stops waiting, later calls in the same pass do nothing
+    pub fn begin(&mut self) {
+        if self.start.is_none() {
+            let now = Instant::now();
+            self.start = Some(now);
+            self.lap = now;
+        }
+    }
+
+    pub fn lap(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.phases.push((phase, now - self.lap));
+        self.lap = now;
+    }
+
+    pub fn finish(&mut self) {
+        let Some(start) = self.start.take() else {
+            return;
+        };
+        let took = start.elapsed();
+        if took <= self.budget {
+            self.phases.clear();
+            return;
+        }
+
+        self.overruns += 1;
+        self.worst = self.worst.max(took);
+        let breakdown = self.phases.drain(..)
+            .filter(|(_, spent)| *spent >= NOTABLE_PHASE)
+            .map(|(phase, spent)| format!("{} {:.1}ms", phase, spent.as_secs_f64() * 1000.))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if self.warned.is_some_and(|warned| warned.elapsed() < WARNING_INTERVAL) {
+            trace!("Pass took {:.1}ms ({})", took.as_secs_f64() * 1000., breakdown);
+            return;
+        }
+        warn!(
+            "Pass took {:.1}ms, over its {:.1}ms budget ({}), {} overrun(s) since the last warning, the worst {:.1}ms",
+            took.as_secs_f64() * 1000.,
+            self.budget.as_secs_f64() * 1000.,
+            breakdown,
+            self.overruns,
+            self.worst.as_secs_f64() * 1000.
+        );
+        self.warned = Some(Instant::now());
+        self.overruns = 0;
+        self.worst = Duration::ZERO;
+    }
+}
@@ -0,0 +1,195 @@
+use crate::capture::Capture;
+use crate::command::Command;
+use std::io::{self, BufRead};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use tracing::warn;
+
+const MAX_DATAGRAM: usize = 0x10000;
+
+// Feeds commands to the switches besides the gamepads, polled once per tick
+pub trait InputSource: Send {
+    fn describe(&self) -> String;
+
+    // What arrived since the last poll
+    fn poll(&mut self) -> Vec<Command>;
+}
+
+// Sees every batch sent to a target, next to the target itself
+pub trait OutputSink: Send {
+    fn describe(&self) -> String;
+
+    fn write(&mut self, target: &str, commands: &[Command]);
+}
+
+// Plugins are written <scheme>[:<argument>] in the config, like targets
+pub struct Plugin<T: ?Sized> {
+    pub scheme: &'static str,
+    pub syntax: &'static str,
+    pub create: fn(&str) -> Result<Box<T>, String>,
+}
+
+static SOURCES: [Plugin<dyn InputSource>; 2] = [
+    Plugin {
+        scheme: "stdin",
+        syntax: "stdin",
+        create: |_| Ok(Box::new(Stdin::open())),
+    },
+    Plugin {
+        scheme: "udp",
+        syntax: "udp:<ip>:<port>",
+        create: |address| Ok(Box::new(Udp::bind(address.parse::<SocketAddr>().map_err(|err| err.to_string())?)?)),
+    },
+];
+
+static SINKS: [Plugin<dyn OutputSink>; 2] = [
+    Plugin {
+        scheme: "record",
+        syntax: "record:<file>",
+        create: |path| match path {
+            "" => Err("The record sink needs a file".to_string()),
+            path => Ok(Box::new(Record::create(Path::new(path))?)),
+        },
+    },
+    Plugin {
+        scheme: "log",
+        syntax: "log",
+        create: |_| Ok(Box::new(Log)),
+    },
+];
+
+// Embedders add their own plugins before the config is applied, they win over the built-in ones
+static REGISTERED_SOURCES: Mutex<Vec<Plugin<dyn InputSource>>> = Mutex::new(Vec::new());
+static REGISTERED_SINKS: Mutex<Vec<Plugin<dyn OutputSink>>> = Mutex::new(Vec::new());
+
+pub fn register_source(plugin: Plugin<dyn InputSource>) {
+    REGISTERED_SOURCES.lock().unwrap().push(plugin);
+}
+
+pub fn register_sink(plugin: Plugin<dyn OutputSink>) {
+    REGISTERED_SINKS.lock().unwrap().push(plugin);
+}
+
+pub fn open_source(spec: &str) -> Result<Box<dyn InputSource>, String> {
+    open("source", spec, &REGISTERED_SOURCES.lock().unwrap(), &SOURCES)
+}
+
+pub fn open_sink(spec: &str) -> Result<Box<dyn OutputSink>, String> {
+    open("sink", spec, &REGISTERED_SINKS.lock().unwrap(), &SINKS)
+}
+
+fn open<T: ?Sized>(kind: &str, spec: &str, registered: &[Plugin<T>], built_in: &[Plugin<T>]) -> Result<Box<T>, String> {
+    let spec = spec.trim();
+    let (scheme, argument) = spec.split_once(':').unwrap_or((spec, ""));
+    let plugins = || registered.iter().chain(built_in);
+    match plugins().find(|plugin| plugin.scheme == scheme) {
+        Some(plugin) => (plugin.create)(argument).map_err(|err| format!("Invalid {} {} ({})", kind, spec, err)),
+        None => {
+            let syntaxes: Vec<&str> = plugins().map(|plugin| plugin.syntax).collect();
+            Err(format!("Unknown {} {}, expected one of {}", kind, spec, syntaxes.join(", ")))
+        }
+    }
+}
+
+// sys-botbase commands, one per line or separated by semicolons
+fn parse_commands(text: &str) -> Vec<Command> {
+    text.split(['\n', ';'])
+        .filter(|command| !command.trim().is_empty())
+        .filter_map(|command| command.parse().map_err(|err| warn!("Ignoring {} ({})", command.trim(), err)).ok())
+        .collect()
+}
+
+// Read on their own thread, a line typed halfway doesn't hold the tick
+struct Stdin {
+    lines: Receiver<String>,
+}
+
+impl Stdin {
+    fn open() -> Stdin {
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+        Stdin { lines }
+    }
+}
+
+impl InputSource for Stdin {
+    fn describe(&self) -> String {
+        "standard input".to_string()
+    }
+
+    fn poll(&mut self) -> Vec<Command> {
+        self.lines.try_iter().flat_map(|line| parse_commands(&line)).collect()
+    }
+}
+
+struct Udp {
+    socket: UdpSocket,
+    address: SocketAddr,
+}
+
+impl Udp {
+    fn bind(address: SocketAddr) -> Result<Udp, String> {
+        let socket = UdpSocket::bind(address).map_err(|err| format!("Unable to listen on {} ({})", address, err))?;
+        socket.set_nonblocking(true).map_err(|err| err.to_string())?;
+        Ok(Udp { socket, address })
+    }
+}
+
+impl InputSource for Udp {
+    fn describe(&self) -> String {
+        format!("UDP on {}", self.address)
+    }
+
+    fn poll(&mut self) -> Vec<Command> {
+        let mut buffer = vec![0; MAX_DATAGRAM];
+        let mut commands = Vec::new();
+        while let Ok((length, _)) = self.socket.recv_from(&mut buffer) {
+            commands.extend(parse_commands(&String::from_utf8_lossy(&buffer[..length])));
+        }
+        commands
+    }
+}
+
+struct Record {
+    capture: Capture,
+    path: String,
+}
+
+impl Record {
+    fn create(path: &Path) -> Result<Record, String> {
+        let capture = Capture::create(path).map_err(|err| format!("Unable to create {} ({})", path.display(), err))?;
+        Ok(Record { capture, path: path.display().to_string() })
+    }
+}
+
+impl OutputSink for Record {
+    fn describe(&self) -> String {
+        format!("recording to {}", self.path)
+    }
+
+    fn write(&mut self, target: &str, commands: &[Command]) {
+        self.capture.record(target, commands);
+    }
+}
+
+struct Log;
+
+impl OutputSink for Log {
+    fn describe(&self) -> String {
+        "log".to_string()
+    }
+
+    fn write(&mut self, target: &str, commands: &[Command]) {
+        let commands: Vec<String> = commands.iter().map(Command::to_string).collect();
+        println!("{}: {}", target, commands.join("; "));
+    }
+}
@@ -0,0 +1,57 @@
+use crate::command::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::error::Error;
+use crate::frame::Frame;
+use crate::router::Router;
+use crate::shutdown;
+use minifb::{ScaleMode, Window, WindowOptions};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+// Each pixelPeek stalls the link for a while, so frames are pulled at a modest rate
+const FRAME_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct Preview {
+    window: Window,
+    last_frame: Option<Instant>,
+}
+
+impl Preview {
+    pub fn open() -> Result<Preview, Error> {
+        let options = WindowOptions { resize: true, scale_mode: ScaleMode::AspectRatioStretch, ..WindowOptions::default() };
+        let window = Window::new("Switch preview", SCREEN_WIDTH as usize, SCREEN_HEIGHT as usize, options)
+            .map_err(|err| Error::GUI(err.to_string()))?;
+        Ok(Preview { window, last_frame: None })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    // Called every tick, the window has to be updated even when no new frame is due
+    pub fn refresh(&mut self, router: &mut Router) {
+        if !self.is_open() || self.last_frame.is_some_and(|last| last.elapsed() < FRAME_INTERVAL) {
+            self.window.update();
+            return;
+        }
+        self.last_frame = Some(Instant::now());
+
+        let shown = Frame::grab(router).and_then(|frame| {
+            self.window.update_with_buffer(&frame.pixels, frame.width, frame.height).map_err(|err| err.to_string())
+        });
+        if let Err(err) = shown {
+            warn!("Unable to refresh the preview ({})", err);
+            self.window.update();
+        }
+    }
+}
+
+pub fn run(router: &mut Router) -> Result<(), Error> {
+    let mut preview = Preview::open()?;
+    while preview.is_open() && !shutdown::requested() {
+        preview.refresh(router);
+        router.heartbeat();
+        thread::sleep(Duration::from_millis(16));
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use crate::command::Command;
+use crate::compact;
+use crate::config::{Encoding, Framing, ProtocolSettings, SizeHeader};
+use std::io::Write;
+use std::sync::OnceLock;
+use tracing::error;
+
+static PROTOCOL: OnceLock<ProtocolSettings> = OnceLock::new();
+
+pub fn configure(settings: ProtocolSettings) {
+    if PROTOCOL.set(settings).is_err() {
+        panic!("Protocol settings are already configured!");
+    }
+}
+
+fn settings() -> ProtocolSettings {
+    *PROTOCOL.get_or_init(ProtocolSettings::default)
+}
+
+pub fn encoding() -> Encoding {
+    settings().encoding
+}
+
+// Every transfer of a batch back to back, kept by the transport so each batch reuses the same memory
+#[derive(Default)]
+pub struct Transfers {
+    data: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl Transfers {
+    // Transports pass the framing they natively speak, the config can force another one
+    pub fn encode(&mut self, native: Framing, commands: &[Command]) -> &Transfers {
+        let settings = settings();
+        let framing = match settings.framing {
+            Framing::AUTO => native,
+            framing => framing,
+        };
+        self.clear();
+        for command in commands {
+            self.push(framing, settings.size_header, command);
+        }
+        self
+    }
+
+    pub fn encode_compact(&mut self, commands: &[Command]) -> &Transfers {
+        self.clear();
+        for command in commands {
+            match compact::encode(command, &mut self.data) {
+                Ok(()) => self.ends.push(self.data.len()),
+                Err(err) => error!("Dropped {} ({})", command.name(), err),
+            }
+        }
+        self
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts.zip(&self.ends).map(|(start, end)| &self.data[start..*end])
+    }
+
+    // All the transfers at once, for streams which don't keep their boundaries anyway
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.ends.clear();
+    }
+
+    // usb-botbase reads the size header and the command in two separate transfers
+    fn push(&mut self, framing: Framing, size_header: SizeHeader, command: &Command) {
+        match framing {
+            Framing::AUTO | Framing::LINES => {
+                write!(self.data, "{}\r\n", command).unwrap();
+            }
+            Framing::PREFIXED => {
+                let header = self.data.len();
+                self.data.extend_from_slice(&[0; 4]);
+                self.ends.push(self.data.len());
+                write!(self.data, "{}", command).unwrap();
+                let length = self.data.len() - header - 4;
+                let size = match size_header {
+                    SizeHeader::PADDED => length + 2,
+                    SizeHeader::EXACT => length,
+                    SizeHeader::TERMINATED => {
+                        self.data.extend_from_slice(b"\r\n");
+                        length + 2
+                    }
+                };
+                self.data[header..header + 4].copy_from_slice(&(size as u32).to_le_bytes());
+            }
+        }
+        self.ends.push(self.data.len());
+    }
+}
@@ -0,0 +1,29 @@
+// With real-time scheduling nothing else running on the machine delays a tick, which TAS
+// playback would see as a dropped frame. Needs root, CAP_SYS_NICE or an rtprio limit
+#[cfg(target_os = "linux")]
+const PRIORITY: i32 = 10;
+
+// Raises the calling thread and pins it to the core it runs on, returning that core
+#[cfg(target_os = "linux")]
+pub fn raise() -> Result<usize, String> {
+    use std::io;
+    use std::mem;
+
+    let param = libc::sched_param { sched_priority: PRIORITY };
+    let err = unsafe { libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param) };
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(err).to_string());
+    }
+    let core = usize::try_from(unsafe { libc::sched_getcpu() }).map_err(|_| io::Error::last_os_error().to_string())?;
+    let mut cores: libc::cpu_set_t = unsafe { mem::zeroed() };
+    unsafe { libc::CPU_SET(core, &mut cores) };
+    if unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &cores) } != 0 {
+        return Err(io::Error::last_os_error().to_string());
+    }
+    Ok(core)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn raise() -> Result<usize, String> {
+    Err("only supported on Linux".to_string())
+}
@@ -0,0 +1,377 @@
+use crate::command::Command;
+use crate::connection::{Tcp, UsbDevice};
+use crate::error::Error;
+use crate::health::SendOutcome;
+use crate::held::HeldState;
+use crate::notify;
+use crate::response::Response;
+use crate::transport::Transport;
+use crate::usb::Usb;
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const RESTORE_INTERVAL: Duration = Duration::from_secs(1);
+// How long sys-botbase holds a clicked button by default
+const CLICK_HOLD: Duration = Duration::from_millis(50);
+
+enum Job {
+    // The commands, then the releases of its clicks once the hold is over
+    SEND(Vec<Command>, Vec<Command>, Duration),
+    HEARTBEAT,
+    REQUEST(Command),
+}
+
+// Owns the connection of a link, so both links send at once without a thread per batch
+struct Worker {
+    jobs: Sender<Job>,
+    done: Receiver<(Result<(), String>, Duration)>,
+    answers: Receiver<Result<Response, String>>,
+}
+
+impl Worker {
+    fn spawn(connection: Box<dyn Transport>) -> Worker {
+        let (jobs, receiver) = mpsc::channel();
+        let (done_sender, done) = mpsc::channel();
+        let (answer_sender, answers) = mpsc::channel();
+        thread::spawn(move || work(connection, receiver, done_sender, answer_sender));
+        Worker { jobs, done, answers }
+    }
+
+    fn wait(&self) -> (Result<(), String>, Duration) {
+        self.done.recv().unwrap_or_else(|_| (Err("link worker stopped".to_string()), Duration::ZERO))
+    }
+}
+
+fn buttons(commands: &[Command]) -> impl Iterator<Item = &str> {
+    commands.iter().filter_map(|command| match command {
+        Command::SETSTICK(..) => None,
+        command => command.input(),
+    })
+}
+
+// Sends the releases that are due, or all of them, a failure is reported with the next job
+fn release(connection: &mut dyn Transport, pending: &mut Vec<(Instant, Vec<Command>)>, all: bool, failed: &mut Option<String>) {
+    let now = Instant::now();
+    let (due, waiting): (Vec<_>, Vec<_>) = std::mem::take(pending).into_iter().partition(|(deadline, _)| all || *deadline <= now);
+    *pending = waiting;
+    for (_, releases) in due {
+        if let Err(err) = connection.try_send_commands(&releases) {
+            *failed = Some(err);
+        }
+    }
+}
+
+// Releases wait in the worker rather than holding up the next batch. They go out early when a
+// batch acts on their buttons, the console would see the buttons out of order otherwise
+fn work(mut connection: Box<dyn Transport>, jobs: Receiver<Job>, done: Sender<(Result<(), String>, Duration)>, answers: Sender<Result<Response, String>>) {
+    let mut pending: Vec<(Instant, Vec<Command>)> = Vec::new();
+    let mut failed: Option<String> = None;
+
+    loop {
+        let job = match pending.iter().map(|(deadline, _)| *deadline).min() {
+            Some(deadline) => jobs.recv_timeout(deadline.saturating_duration_since(Instant::now())),
+            None => jobs.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match job {
+            Err(RecvTimeoutError::Timeout) => release(connection.as_mut(), &mut pending, false, &mut failed),
+            // Nothing stays held once the tool stops
+            Err(RecvTimeoutError::Disconnected) => {
+                release(connection.as_mut(), &mut pending, true, &mut failed);
+                return;
+            }
+            Ok(Job::SEND(commands, releases, hold)) => {
+                let start = Instant::now();
+                if pending.iter().any(|(_, pending)| buttons(pending).any(|button| buttons(&commands).any(|other| other == button))) {
+                    release(connection.as_mut(), &mut pending, true, &mut failed);
+                }
+                let result = match failed.take() {
+                    Some(err) => Err(err),
+                    None => connection.try_send_commands(&commands),
+                };
+                if result.is_ok() && !releases.is_empty() {
+                    pending.push((Instant::now() + hold, releases));
+                }
+                let _ = done.send((result, start.elapsed()));
+            }
+            Ok(Job::HEARTBEAT) => {
+                let start = Instant::now();
+                let result = match failed.take() {
+                    Some(err) => Err(err),
+                    None => connection.try_heartbeat(),
+                };
+                let _ = done.send((result, start.elapsed()));
+            }
+            Ok(Job::REQUEST(command)) => {
+                let _ = answers.send(connection.request(&command));
+            }
+        }
+    }
+}
+
+struct Link {
+    name: &'static str,
+    worker: Option<Worker>,
+    latency: Duration,
+    last_restore: Instant,
+}
+
+impl Link {
+    fn new(name: &'static str, connection: Option<Box<dyn Transport>>) -> Link {
+        if connection.is_none() {
+            warn!("{} link unavailable, it will be added once reachable", name);
+        }
+
+        Link {
+            name,
+            worker: connection.map(Worker::spawn),
+            latency: Duration::ZERO,
+            last_restore: Instant::now(),
+        }
+    }
+
+    fn is_up(&self) -> bool {
+        self.worker.is_some()
+    }
+
+    fn restore(&mut self, held: &HeldState, connect: impl FnOnce() -> Option<Box<dyn Transport>>) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        if self.is_up() || self.last_restore.elapsed() < RESTORE_INTERVAL {
+            return outcome;
+        }
+
+        self.last_restore = Instant::now();
+        if let Some(mut connection) = connect() {
+            if connection.try_send_commands(&held.resync_packets()).is_ok() {
+                info!("{} link is back", self.name);
+                notify::send(notify::SWITCH_BACK, &format!("The {} link is back", self.name));
+                self.latency = Duration::ZERO;
+                self.worker = Some(Worker::spawn(connection));
+                outcome.reconnects += 1;
+            }
+        }
+        outcome
+    }
+
+    fn lose(&mut self, err: String, what: &str) -> SendOutcome {
+        warn!("{} link {} ({}), continuing on the other link", self.name, what, err);
+        notify::send(notify::SWITCH_LOST, &format!("The {} link {} ({}), continuing on the other link", self.name, what, err));
+        self.worker = None;
+        self.last_restore = Instant::now();
+        SendOutcome { failures: 1, ..SendOutcome::default() }
+    }
+
+    // Hands the job to the worker, false when the link is down. A worker that stopped shows when
+    // waiting for its result
+    fn post(&self, job: Job) -> bool {
+        match &self.worker {
+            Some(worker) => {
+                let _ = worker.jobs.send(job);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn wait(&mut self, what: &str) -> SendOutcome {
+        let Some(worker) = &self.worker else {
+            return SendOutcome::default();
+        };
+        match worker.wait() {
+            (Ok(()), latency) => {
+                self.latency = (self.latency * 3 + latency) / 4;
+                SendOutcome::default()
+            }
+            (Err(err), _) => self.lose(err, what),
+        }
+    }
+
+    fn request(&mut self, command: &Command) -> Option<Result<Response, String>> {
+        match self.post(Job::REQUEST(command.clone())) {
+            true => self.worker.as_ref()?.answers.recv().ok(),
+            false => None,
+        }
+    }
+}
+
+fn try_connect_usb(device: &UsbDevice) -> Option<Box<dyn Transport>> {
+    Usb::try_connect(device).map(|usb| Box::new(usb) as Box<dyn Transport>)
+}
+
+fn try_connect_tcp(address: SocketAddr) -> Option<Box<dyn Transport>> {
+    Tcp::try_connect(address).map(|tcp| Box::new(tcp) as Box<dyn Transport>)
+}
+
+// Presses, releases and sticks land twice in the same state, anything else would run twice
+fn repeatable(command: &Command) -> bool {
+    matches!(command, Command::PRESS(_) | Command::RELEASE(_) | Command::SETSTICK(..))
+}
+
+// Buttons and sticks go over both links, the first copy to arrive is the one that counts. Other
+// commands only go over the faster link
+pub struct Redundant {
+    usb: Link,
+    tcp: Link,
+    device: UsbDevice,
+    address: SocketAddr,
+    held: HeldState,
+}
+
+impl Redundant {
+    pub fn new(device: UsbDevice, address: SocketAddr) -> Result<Redundant, Error> {
+        let usb = try_connect_usb(&device);
+        let tcp = try_connect_tcp(address);
+        if usb.is_none() && tcp.is_none() {
+            return Err(Error::UNREACHABLE);
+        }
+
+        Ok(Redundant {
+            usb: Link::new("USB", usb),
+            tcp: Link::new("TCP", tcp),
+            device,
+            address,
+            held: HeldState::default(),
+        })
+    }
+
+    // Posted to both workers before waiting on either, so the links send at the same time
+    fn send_both(&mut self, usb: Vec<Command>, tcp: Vec<Command>, releases: &[Command], hold: Duration) -> SendOutcome {
+        let usb = !usb.is_empty() && self.usb.post(Job::SEND(usb, releases.to_vec(), hold));
+        let tcp = !tcp.is_empty() && self.tcp.post(Job::SEND(tcp, releases.to_vec(), hold));
+        let mut outcome = SendOutcome::default();
+        if usb {
+            outcome = outcome.merge(self.usb.wait("lost"));
+        }
+        if tcp {
+            outcome = outcome.merge(self.tcp.wait("lost"));
+        }
+        outcome
+    }
+}
+
+impl Transport for Redundant {
+    fn describe(&self) -> String {
+        format!("USB switch {} and switch at {}", self.device, self.address)
+    }
+
+    fn heartbeat(&mut self) -> SendOutcome {
+        let usb = self.usb.post(Job::HEARTBEAT);
+        let tcp = self.tcp.post(Job::HEARTBEAT);
+        let mut outcome = SendOutcome::default();
+        if usb {
+            outcome = outcome.merge(self.usb.wait("stopped answering"));
+        }
+        if tcp {
+            outcome = outcome.merge(self.tcp.wait("stopped answering"));
+        }
+        outcome
+    }
+
+    // USB answers faster, TCP when the USB link is down or fails
+    fn request(&mut self, command: &Command) -> Result<Response, String> {
+        match self.usb.request(command) {
+            Some(Ok(response)) => Ok(response),
+            usb => self.tcp.request(command).or(usb).unwrap_or_else(|| Err("No link to the switch available".to_string())),
+        }
+    }
+
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        commands.iter().for_each(|command| self.held.observe(command));
+
+        let address = self.address;
+        let device = &self.device;
+        let outcome = self.usb.restore(&self.held, || try_connect_usb(device))
+            .merge(self.tcp.restore(&self.held, || try_connect_tcp(address)));
+        if !self.usb.is_up() && !self.tcp.is_up() {
+            warn!("No link to the switch available, dropping inputs");
+            return outcome.merge(SendOutcome { failures: 1, ..SendOutcome::default() });
+        }
+
+        // A click becomes a press then a release, both sent over both links. The duplicate of each
+        // lands while the button is already in that state, as long as the hold outlasts the gap between
+        // the links, so the console still sees a single click
+        let releases: Vec<Command> = commands.iter()
+            .filter_map(|command| match command {
+                Command::CLICK(button) => Some(Command::RELEASE(button.clone())),
+                _ => None,
+            })
+            .collect();
+        let presses: Vec<Command> = commands.iter()
+            .map(|command| match command {
+                Command::CLICK(button) => Command::PRESS(button.clone()),
+                command => command.clone(),
+            })
+            .collect();
+
+        // The faster link gets the whole batch in order, the other only what is safe to repeat
+        let repeated: Vec<Command> = presses.iter().filter(|command| repeatable(command)).cloned().collect();
+        let usb_first = self.usb.is_up() && (!self.tcp.is_up() || self.usb.latency <= self.tcp.latency);
+        let (usb, tcp) = match usb_first {
+            true => (presses, repeated),
+            false => (repeated, presses),
+        };
+
+        // The worker of each link lets go of the clicks once the hold is over
+        let hold = CLICK_HOLD.max(self.usb.latency.abs_diff(self.tcp.latency) * 2);
+        outcome.merge(self.send_both(usb, tcp, &releases, hold))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dryrun::DryRun;
+    use std::sync::{Arc, Mutex};
+
+    fn link(name: &'static str) -> (Link, Arc<Mutex<Vec<Command>>>) {
+        let dry_run = DryRun::new();
+        let sent = dry_run.sent.clone();
+        (Link::new(name, Some(Box::new(dry_run))), sent)
+    }
+
+    fn redundant() -> (Redundant, Arc<Mutex<Vec<Command>>>, Arc<Mutex<Vec<Command>>>) {
+        let (usb, usb_sent) = link("USB");
+        let (tcp, tcp_sent) = link("TCP");
+        let redundant = Redundant {
+            usb,
+            tcp,
+            device: UsbDevice::INDEX(0),
+            address: "127.0.0.1:6000".parse().unwrap(),
+            held: HeldState::default(),
+        };
+        (redundant, usb_sent, tcp_sent)
+    }
+
+    #[test]
+    fn buttons_go_over_both_links() {
+        let (mut redundant, usb, tcp) = redundant();
+        let commands = vec![Command::PRESS("A".into()), Command::SETSTICK("LEFT".into(), 0, 0x7FFF)];
+        assert_eq!(redundant.send_commands(&commands).failures, 0);
+        assert_eq!(*usb.lock().unwrap(), commands);
+        assert_eq!(*tcp.lock().unwrap(), commands);
+    }
+
+    #[test]
+    fn keys_and_touches_go_over_one_link() {
+        let (mut redundant, usb, tcp) = redundant();
+        let commands = vec![
+            Command::PRESS("A".into()),
+            Command::OTHER("key 4".to_string()),
+            Command::TOUCH(vec![(100, 200)]),
+        ];
+        assert_eq!(redundant.send_commands(&commands).failures, 0);
+        assert_eq!(*usb.lock().unwrap(), commands);
+        assert_eq!(*tcp.lock().unwrap(), vec![Command::PRESS("A".into())]);
+
+        // The slower link only gets the buttons once the other one is faster
+        redundant.usb.latency = Duration::from_millis(20);
+        usb.lock().unwrap().clear();
+        tcp.lock().unwrap().clear();
+        let touch = vec![Command::TOUCH(vec![(100, 200)])];
+        assert_eq!(redundant.send_commands(&touch).failures, 0);
+        assert!(usb.lock().unwrap().is_empty());
+        assert_eq!(*tcp.lock().unwrap(), touch);
+    }
+}
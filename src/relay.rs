@@ -0,0 +1,192 @@
+use crate::auth;
+use crate::command::Command;
+use crate::compact;
+use crate::config::Source;
+use crate::discovery;
+use crate::error::Error;
+use crate::held::HeldState;
+use crate::response::Response;
+use crate::router::Router;
+use crate::shutdown;
+use crate::version;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, info_span, warn};
+
+pub const DEFAULT_LISTEN: &str = "0.0.0.0:6100";
+pub const DEFAULT_PORT: u16 = 6100;
+
+const TICK: Duration = Duration::from_millis(100);
+
+enum RelayEvent {
+    COMMAND(usize, Command),
+    DISCONNECTED(usize),
+}
+
+fn handle_client(client: usize, stream: TcpStream, secret: Option<String>, version: String, sender: Sender<RelayEvent>) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_else(|_| "unknown".to_string());
+    let _span = info_span!("relay", client).entered();
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    // Commands sent right behind the authentication answer stay buffered for the loop below
+    let mut reader = BufReader::new(stream);
+    if let Some(secret) = secret {
+        if let Err(err) = auth::verify_client(&mut reader, &secret) {
+            warn!("Rejected relay client {} ({})", peer, err);
+            return;
+        }
+    }
+
+    info!("Relay client {} connected", peer);
+    let mut compact = false;
+    loop {
+        let (line, parsed) = if compact {
+            match compact::read(&mut reader) {
+                Ok(command) => (command.to_string(), Ok(command)),
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    warn!("Invalid compact command from relay client {} ({})", peer, err);
+                    break;
+                }
+                Err(_) => break,
+            }
+        } else {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = line.trim().to_string();
+                    let parsed = line.parse::<Command>();
+                    (line, parsed)
+                }
+            }
+        };
+
+        // Only inputs and touches are relayed, memory and system commands never reach sys-botbase
+        let reply = match parsed {
+            _ if line.is_empty() => None,
+            Ok(Command::OTHER(command)) if command == "getVersion" => Some(version.as_str()),
+            Ok(Command::OTHER(command)) if command == compact::NEGOTIATE && !compact => {
+                compact = true;
+                Some("1")
+            }
+            Ok(command) if command.is_input() || command.is_touch() => {
+                if sender.send(RelayEvent::COMMAND(client, command)).is_err() {
+                    break;
+                }
+                None
+            }
+            _ => {
+                warn!("Ignoring {} from relay client {}", line, peer);
+                None
+            }
+        };
+
+        if let Some(reply) = reply {
+            if writer.write_all(format!("{}\n", reply).as_bytes()).is_err() {
+                break;
+            }
+        }
+    }
+
+    info!("Relay client {} disconnected", peer);
+    let _ = sender.send(RelayEvent::DISCONNECTED(client));
+}
+
+pub fn resolve(host: &str) -> Result<SocketAddr, Error> {
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, DEFAULT_PORT)
+    };
+
+    let unresolved = |reason: String| Error::RESOLVE { host: host.to_string(), reason };
+    address.to_socket_addrs()
+        .map_err(|err| unresolved(err.to_string()))?
+        .next()
+        .ok_or_else(|| unresolved("no address found".to_string()))
+}
+
+// Answers the discovery broadcast so joining players on the network find the relay without its address
+fn answer_probes(listen: SocketAddr, version: String) {
+    let socket = match UdpSocket::bind(listen) {
+        Ok(socket) => socket,
+        Err(err) => {
+            warn!("Unable to answer discovery probes ({}), joining players will need the address", err);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        let mut buf = [0u8; 64];
+        while let Ok((size, peer)) = socket.recv_from(&mut buf) {
+            if &buf[..size] == discovery::PROBE {
+                let _ = socket.send_to(format!("{} relay", version).as_bytes(), peer);
+            }
+        }
+    });
+}
+
+pub fn serve(listen: SocketAddr, secret: Option<String>, router: &mut Router) -> Result<(), Error> {
+    let listener = TcpListener::bind(listen).map_err(|source| Error::LISTEN { address: listen, source })?;
+    info!("Relaying inputs from clients connecting to {}", listen);
+    // Clients gate commands on the version like with a console, so they get the one of the console
+    let version = match router.request(&Command::OTHER("getVersion".to_string())) {
+        Ok(Response::TEXT(version)) => version,
+        _ => version::newest().to_string(),
+    };
+    answer_probes(listen, version.clone());
+    if secret.is_none() {
+        warn!("No secret configured, anyone reaching this port can control the switch!");
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for (client, stream) in listener.incoming().enumerate() {
+            match stream {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    let secret = secret.clone();
+                    let version = version.clone();
+                    thread::spawn(move || handle_client(client, stream, secret, version, sender));
+                }
+                Err(err) => warn!("Unable to accept relay client ({})", err),
+            }
+        }
+    });
+
+    let mut held: BTreeMap<usize, HeldState> = BTreeMap::new();
+    while !shutdown::requested() {
+        let mut batches: BTreeMap<usize, Vec<Command>> = BTreeMap::new();
+        let mut event = match receiver.recv_timeout(TICK) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        while let Some(current) = event {
+            match current {
+                RelayEvent::COMMAND(client, command) => {
+                    held.entry(client).or_default().observe(&command);
+                    batches.entry(client).or_default().push(command);
+                }
+                // Don't leave the buttons of a client that vanished held down
+                RelayEvent::DISCONNECTED(client) => {
+                    if let Some(state) = held.remove(&client) {
+                        batches.entry(client).or_default().extend(state.release_packets());
+                    }
+                }
+            }
+            event = receiver.try_recv().ok();
+        }
+
+        for (client, commands) in batches {
+            router.send(Source::GAMEPAD, client, commands);
+        }
+        router.heartbeat();
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use crate::command::Command;
+use crate::config::Source;
+use crate::keyboard;
+use crate::response;
+use crate::router::Router;
+use crate::shutdown;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+const TICK: Duration = Duration::from_millis(100);
+
+pub enum Typed {
+    COMMAND(Command),
+    STATS,
+}
+
+fn prompt() {
+    print!("> ");
+    let _ = io::stdout().flush();
+}
+
+// Reads raw sys-botbase commands from stdin, the channel closes on exit or end of input
+pub fn spawn() -> Receiver<Typed> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        prompt();
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            match line.trim() {
+                "" => {}
+                "exit" | "quit" => break,
+                "stats" => {
+                    if sender.send(Typed::STATS).is_err() {
+                        break;
+                    }
+                }
+                line => {
+                    let commands = match line.strip_prefix("type ") {
+                        Some(text) => keyboard::type_commands(text),
+                        None if line == "paste" => keyboard::paste_commands(),
+                        None => line.parse::<Command>().map(|command| vec![command]),
+                    };
+                    match commands {
+                        Ok(commands) => {
+                            if commands.into_iter().any(|command| sender.send(Typed::COMMAND(command)).is_err()) {
+                                break;
+                            }
+                        }
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            prompt();
+        }
+    });
+    receiver
+}
+
+pub fn handle(router: &mut Router, typed: Typed) {
+    match typed {
+        Typed::COMMAND(command) => execute(router, command),
+        Typed::STATS => router.stats().iter().for_each(|line| println!("{}", line)),
+    }
+}
+
+// Commands sys-botbase answers wait for their response, the others are just sent
+pub fn execute(router: &mut Router, command: Command) {
+    if response::reply_kind(&command).is_none() {
+        router.send(Source::MACRO, 0, vec![command]);
+        return;
+    }
+
+    match router.request(&command) {
+        Ok(response) => println!("{}", response),
+        Err(err) => println!("No response to {} ({})", command, err),
+    }
+}
+
+pub fn run(router: &mut Router) {
+    println!("Type sys-botbase commands, type <text> or paste for the keyboard, stats for the session so far, exit to quit");
+    let receiver = spawn();
+    while !shutdown::requested() {
+        match receiver.recv_timeout(TICK) {
+            Ok(typed) => handle(router, typed),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        router.heartbeat();
+    }
+}
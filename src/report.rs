@@ -0,0 +1,55 @@
+use crate::command::Command;
+use crate::config::Source;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+// Cells per side of a stick heatmap, each one about 1/16 of the stick travel
+const HEATMAP_SIZE: usize = 16;
+
+// What the session was used for, written as JSON when it ends. Buttons count presses and clicks,
+// heatmaps count each position sent, rows going from the top (y up) to the bottom
+#[derive(Default, Serialize)]
+pub struct Report {
+    duration_secs: f64,
+    buttons: BTreeMap<String, u64>,
+    sticks: BTreeMap<String, Vec<Vec<u64>>>,
+    commands: BTreeMap<&'static str, u64>,
+    runs: BTreeMap<String, u64>,
+}
+
+fn cell(value: i32) -> usize {
+    let offset = (value.clamp(-0x8000, 0x7FFF) + 0x8000) as usize;
+    offset * HEATMAP_SIZE / 0x10000
+}
+
+impl Report {
+    pub fn observe(&mut self, source: Source, commands: &[Command]) {
+        *self.commands.entry(source.name()).or_default() += commands.len() as u64;
+        for command in commands {
+            match command {
+                Command::PRESS(button) | Command::CLICK(button) => *self.buttons.entry(button.to_string()).or_default() += 1,
+                Command::SETSTICK(stick, x, y) => {
+                    let heatmap = self.sticks.entry(stick.to_string()).or_insert_with(|| vec![vec![0; HEATMAP_SIZE]; HEATMAP_SIZE]);
+                    heatmap[HEATMAP_SIZE - 1 - cell(*y)][cell(*x)] += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // A macro replayed, a mash started, a schedule or watch that went off
+    pub fn run(&mut self, name: &str) {
+        *self.runs.entry(name.to_string()).or_default() += 1;
+    }
+
+    pub fn write(&mut self, path: &Path, duration: Duration) -> io::Result<()> {
+        self.duration_secs = duration.as_secs_f64();
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()
+    }
+}
@@ -0,0 +1,88 @@
+use crate::command::Command;
+use crate::hex;
+use std::fmt::{Display, Formatter};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reply {
+    TEXT,
+    BYTES,
+    NUMBER,
+}
+
+// sys-botbase only answers these, over TCP as a text line (hex for data and numbers),
+// over USB as raw bytes behind a u32 LE size
+const REPLIES: [(&str, Reply); 22] = [
+    ("getVersion", Reply::TEXT),
+    ("freezeCount", Reply::TEXT),
+    ("isProgramRunning", Reply::TEXT),
+    ("charge", Reply::TEXT),
+    ("game", Reply::TEXT),
+    ("getTitleID", Reply::NUMBER),
+    ("getTitleVersion", Reply::NUMBER),
+    ("getMainNsoBase", Reply::NUMBER),
+    ("getHeapBase", Reply::NUMBER),
+    ("getSystemLanguage", Reply::NUMBER),
+    ("pointer", Reply::NUMBER),
+    ("pointerAll", Reply::NUMBER),
+    ("pointerRelative", Reply::NUMBER),
+    ("getBuildID", Reply::BYTES),
+    ("peek", Reply::BYTES),
+    ("peekAbsolute", Reply::BYTES),
+    ("peekMain", Reply::BYTES),
+    ("peekMulti", Reply::BYTES),
+    ("peekAbsoluteMulti", Reply::BYTES),
+    ("peekMainMulti", Reply::BYTES),
+    ("pixelPeek", Reply::BYTES),
+    ("pointerPeek", Reply::BYTES),
+];
+
+pub fn reply_kind(command: &Command) -> Option<Reply> {
+    REPLIES.iter().find(|(reply, _)| *reply == command.name()).map(|(_, kind)| *kind)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Response {
+    TEXT(String),
+    BYTES(Vec<u8>),
+    NUMBER(u64),
+}
+
+impl Response {
+    pub fn from_line(kind: Reply, line: &str) -> Result<Response, String> {
+        let line = line.trim();
+        match kind {
+            Reply::TEXT => Ok(Response::TEXT(line.to_string())),
+            Reply::BYTES => hex::decode(line)
+                .map(Response::BYTES)
+                .ok_or_else(|| format!("Invalid hex response {}", line)),
+            Reply::NUMBER => u64::from_str_radix(line.trim_start_matches("0x"), 16)
+                .map(Response::NUMBER)
+                .map_err(|_| format!("Invalid number response {}", line)),
+        }
+    }
+
+    pub fn from_raw(kind: Reply, bytes: Vec<u8>) -> Result<Response, String> {
+        match kind {
+            Reply::TEXT => String::from_utf8(bytes)
+                .map(|text| Response::TEXT(text.trim().to_string()))
+                .map_err(|err| err.to_string()),
+            Reply::BYTES => Ok(Response::BYTES(bytes)),
+            Reply::NUMBER => {
+                let mut number = [0; 8];
+                let length = bytes.len().min(8);
+                number[..length].copy_from_slice(&bytes[..length]);
+                Ok(Response::NUMBER(u64::from_le_bytes(number)))
+            }
+        }
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Response::TEXT(text) => write!(f, "{}", text),
+            Response::BYTES(bytes) => write!(f, "{}", hex::encode(bytes)),
+            Response::NUMBER(number) => write!(f, "0x{:016X}", number),
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use crate::capture::Capture;
+use crate::command::Command;
+use crate::config::{Route, Source};
+use crate::error::Error;
+use crate::health::{LinkHealth, SendOutcome};
+use crate::held::HeldState;
+use crate::inputlog::InputLog;
+use crate::metrics::{self, Metrics};
+use crate::overlay::Overlay;
+use crate::plugin::{InputSource, OutputSink};
+use crate::report::Report;
+use crate::response::Response;
+use crate::transport::{Target, Transport};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, info_span, trace, warn};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+pub const DETACH: &str = "detachController";
+// Far inside any stick dead zone
+const ANTI_IDLE_WIGGLE: i32 = 0x100;
+
+pub struct Router {
+    targets: Vec<(String, Box<dyn Transport>)>,
+    routes: Vec<Route>,
+    last_activity: Vec<Instant>,
+    health: Vec<LinkHealth>,
+    capture: Option<Capture>,
+    input_log: Option<InputLog>,
+    metrics: Option<Metrics>,
+    overlay: Option<Overlay>,
+    report: Option<Report>,
+    recording: Option<Capture>,
+    attached: Vec<bool>,
+    anti_idle: Option<Duration>,
+    last_input: Option<Instant>,
+    last_recording: Option<PathBuf>,
+    gamepads_paused: bool,
+    held: Vec<HeldState>,
+    running: BTreeMap<&'static str, String>,
+    sources: Vec<Box<dyn InputSource>>,
+    sinks: Vec<Box<dyn OutputSink>>,
+    started: Instant,
+    coalesced: u64,
+    dropped_events: u64,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router {
+            targets: Vec::new(),
+            routes: Vec::new(),
+            last_activity: Vec::new(),
+            health: Vec::new(),
+            capture: None,
+            input_log: None,
+            metrics: None,
+            overlay: None,
+            report: None,
+            recording: None,
+            attached: Vec::new(),
+            anti_idle: None,
+            last_input: None,
+            last_recording: None,
+            gamepads_paused: false,
+            held: Vec::new(),
+            running: BTreeMap::new(),
+            sources: Vec::new(),
+            sinks: Vec::new(),
+            started: Instant::now(),
+            coalesced: 0,
+            dropped_events: 0,
+        }
+    }
+}
+
+impl Router {
+    pub fn single(transport: Box<dyn Transport>) -> Router {
+        Router {
+            targets: vec![("switch".to_string(), transport)],
+            last_activity: vec![Instant::now()],
+            health: vec![LinkHealth::default()],
+            attached: vec![false],
+            held: vec![HeldState::default()],
+            ..Router::default()
+        }
+    }
+
+    pub fn new(targets: &BTreeMap<String, Target>, routes: Vec<Route>) -> Result<Router, Error> {
+        if let Some(route) = routes.iter().find(|route| !targets.contains_key(&route.target)) {
+            return Err(Error::INVALID { what: "route".to_string(), reason: format!("unknown target {}", route.target) });
+        }
+
+        let targets: Vec<_> = targets.iter()
+            .map(|(name, target)| {
+                info!("Connecting to {}...", name);
+                (name.clone(), target.connect_retrying())
+            })
+            .collect();
+        let last_activity = vec![Instant::now(); targets.len()];
+        let health = targets.iter().map(|_| LinkHealth::default()).collect();
+        let attached = vec![false; targets.len()];
+        let held = targets.iter().map(|_| HeldState::default()).collect();
+
+        Ok(Router { targets, routes, last_activity, health, attached, held, ..Router::default() })
+    }
+
+    pub fn describe(&self) -> String {
+        self.targets.iter().map(|(_, transport)| transport.describe()).collect::<Vec<_>>().join(", ")
+    }
+
+    pub fn capture_to(&mut self, capture: Capture) {
+        self.capture = Some(capture);
+    }
+
+    pub fn log_inputs_to(&mut self, input_log: InputLog) {
+        self.input_log = Some(input_log);
+    }
+
+    pub fn serve_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    pub fn broadcast_to(&mut self, overlay: Overlay) {
+        self.overlay = Some(overlay);
+    }
+
+    pub fn keep_report(&mut self) {
+        self.report = Some(Report::default());
+    }
+
+    pub fn count_run(&mut self, name: &str) {
+        if let Some(report) = &mut self.report {
+            report.run(name);
+        }
+    }
+
+    pub fn write_report(&mut self, path: &Path) -> std::io::Result<()> {
+        let uptime = self.uptime();
+        match &mut self.report {
+            Some(report) => report.write(path, uptime),
+            None => Ok(()),
+        }
+    }
+
+    // Where the gamepad loop writes the raw events it reads
+    pub fn input_log(&mut self) -> Option<&mut InputLog> {
+        self.input_log.as_mut()
+    }
+
+    pub fn add_source(&mut self, source: Box<dyn InputSource>) {
+        info!("Taking inputs from {}", source.describe());
+        self.sources.push(source);
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn OutputSink>) {
+        info!("Sending outputs to {}", sink.describe());
+        self.sinks.push(sink);
+    }
+
+    // Called once per tick by whatever drives the session, plugin inputs are routed as their own source
+    pub fn poll_sources(&mut self) {
+        let commands: Vec<Command> = self.sources.iter_mut().flat_map(|source| source.poll()).collect();
+        if !commands.is_empty() {
+            self.send(Source::PLUGIN, 0, commands);
+        }
+    }
+
+    // Without any input for this long the console gets a wiggle too small to move anything,
+    // so it doesn't go to sleep during long unattended sessions
+    pub fn keep_awake(&mut self, after: Duration) {
+        self.anti_idle = Some(after);
+        self.last_input = Some(Instant::now());
+    }
+
+    // A macro only holds what the gamepads sent, in the capture format so play replays it
+    pub fn toggle_recording(&mut self, path: &Path) {
+        if self.recording.take().is_some() {
+            self.set_running("recording", None);
+            info!("Stopped recording the macro");
+            return;
+        }
+        match Capture::create(path) {
+            Ok(recording) => {
+                info!("Recording the gamepad to {}", path.display());
+                self.recording = Some(recording);
+                self.set_running("recording", Some(path.display().to_string()));
+                self.last_recording = Some(path.to_path_buf());
+            }
+            Err(err) => warn!("Unable to record to {} ({})", path.display(), err),
+        }
+    }
+
+    // What keeps going alongside the gamepad, like a replay or a mash, for the dashboard
+    pub fn set_running(&mut self, what: &'static str, detail: Option<String>) {
+        match detail {
+            Some(detail) => self.running.insert(what, detail),
+            None => self.running.remove(what),
+        };
+    }
+
+    pub fn running(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.running.iter().map(|(what, detail)| (*what, detail.as_str()))
+    }
+
+    // Gamepad inputs are dropped until resumed, what they held is released so nothing stays pressed.
+    // Macros and other sources keep going
+    pub fn pause_gamepads(&mut self, paused: bool) {
+        if paused == self.gamepads_paused {
+            return;
+        }
+        self.gamepads_paused = paused;
+        if paused {
+            self.release_all();
+            self.set_running("paused", Some("gamepad forwarding".to_string()));
+            info!("Paused forwarding the gamepad");
+        } else {
+            self.set_running("paused", None);
+            info!("Resumed forwarding the gamepad");
+        }
+    }
+
+    pub fn gamepads_paused(&self) -> bool {
+        self.gamepads_paused
+    }
+
+    pub fn last_recording(&self) -> Option<&Path> {
+        self.last_recording.as_deref()
+    }
+
+    // A sequence can't be split between targets, so routed macros go command by command
+    pub fn runs_sequences(&self) -> bool {
+        self.routes.iter().all(|route| route.source != Source::MACRO)
+            && self.targets.iter().all(|(_, transport)| transport.runs_sequences())
+    }
+
+    pub fn routes_gamepads(&self) -> bool {
+        self.routes.iter().any(|route| route.gamepad.is_some())
+    }
+
+    pub fn send(&mut self, source: Source, gamepad: usize, commands: Vec<Command>) {
+        if self.gamepads_paused && source == Source::GAMEPAD {
+            return;
+        }
+        // What was asked for, however many targets it goes to and whatever coalescing drops
+        if let Some(report) = &mut self.report {
+            report.observe(source, &commands);
+        }
+        let mut per_target: Vec<Vec<Command>> = vec![Vec::new(); self.targets.len()];
+
+        for command in commands {
+            let route = self.routes.iter().find(|route| route.matches(source, gamepad, &command));
+            match route.and_then(|route| self.targets.iter().position(|(name, _)| *name == route.target)) {
+                Some(index) => per_target[index].push(command),
+                None => per_target.iter_mut().for_each(|commands| commands.push(command.clone())),
+            }
+        }
+
+        for (index, commands) in per_target.into_iter().enumerate() {
+            let _span = info_span!("target", name = %self.targets[index].0).entered();
+            let count = commands.len();
+            let commands = self.held[index].coalesce(commands);
+            if commands.len() < count {
+                self.coalesced += (count - commands.len()) as u64;
+                debug!("Dropped {} command(s) that wouldn't change anything", count - commands.len());
+            }
+            if !commands.is_empty() {
+                trace!("Sending {}", commands.iter().map(Command::to_string).collect::<Vec<_>>().join("; "));
+                if let Some(capture) = &mut self.capture {
+                    capture.record(&self.targets[index].0, &commands);
+                }
+                if let Some(input_log) = &mut self.input_log {
+                    input_log.commands(source, gamepad, &self.targets[index].0, &commands);
+                }
+                if let Some(recording) = self.recording.as_mut().filter(|_| source == Source::GAMEPAD) {
+                    recording.record(&self.targets[index].0, &commands);
+                }
+                for sink in &mut self.sinks {
+                    sink.write(&self.targets[index].0, &commands);
+                }
+                let start = Instant::now();
+                let outcome = self.targets[index].1.send_commands(&commands);
+                self.record(index, commands.len(), start.elapsed(), outcome);
+                self.last_activity[index] = Instant::now();
+
+                // sys-botbase attaches its virtual controller on the first input
+                for command in &commands {
+                    self.held[index].observe(command);
+                    if command.is_input() {
+                        self.attached[index] = true;
+                        self.last_input = Some(Instant::now());
+                    } else if command.name() == DETACH {
+                        self.attached[index] = false;
+                    }
+                }
+                if let Some(overlay) = self.overlay.as_ref().filter(|_| commands.iter().any(Command::is_input)) {
+                    overlay.publish(&self.targets[index].0, &self.held[index], &commands);
+                }
+            }
+        }
+    }
+
+    // Nothing stays pressed or tilted on the consoles once the tool stops
+    pub fn release_all(&mut self) {
+        for index in 0..self.targets.len() {
+            let commands = std::mem::take(&mut self.held[index]).release_packets();
+            if !commands.is_empty() {
+                info!("Releasing the inputs held on {}", self.targets[index].0);
+                let _ = self.targets[index].1.send_commands(&commands);
+            }
+        }
+    }
+
+    // Leaves no virtual controller behind on the consoles this session attached one to
+    pub fn detach(&mut self) {
+        for index in 0..self.targets.len() {
+            if std::mem::take(&mut self.attached[index]) {
+                info!("Detaching the controller from {}", self.targets[index].0);
+                let _ = self.targets[index].1.send_commands(&[Command::OTHER(DETACH.to_string())]);
+            }
+        }
+    }
+
+    // Answers come from a single switch, the first target
+    pub fn request(&mut self, command: &Command) -> Result<Response, String> {
+        let result = self.targets[0].1.request(command);
+        self.last_activity[0] = Instant::now();
+        result
+    }
+
+    pub fn heartbeat(&mut self) {
+        if let (Some(after), Some(last_input)) = (self.anti_idle, self.last_input) {
+            if last_input.elapsed() >= after {
+                // Two batches, a batch only keeps the last position of a stick
+                self.send(Source::MACRO, 0, vec![Command::SETSTICK("RIGHT".into(), ANTI_IDLE_WIGGLE, 0)]);
+                self.send(Source::MACRO, 0, vec![Command::SETSTICK("RIGHT".into(), 0, 0)]);
+            }
+        }
+        for index in 0..self.targets.len() {
+            if self.last_activity[index].elapsed() >= HEARTBEAT_INTERVAL {
+                let _span = info_span!("target", name = %self.targets[index].0).entered();
+                let start = Instant::now();
+                let outcome = self.targets[index].1.heartbeat();
+                self.record(index, 0, start.elapsed(), outcome);
+                self.last_activity[index] = Instant::now();
+            }
+        }
+        if let Some(mut metrics) = self.metrics.take() {
+            if metrics.due() {
+                metrics.publish(metrics::render(self));
+            }
+            self.metrics = Some(metrics);
+        }
+    }
+
+    fn record(&mut self, index: usize, commands: usize, latency: Duration, outcome: SendOutcome) {
+        let health = &mut self.health[index];
+        if health.record(commands, latency, outcome) {
+            let name = &self.targets[index].0;
+            if health.is_degraded() {
+                warn!("Link to {} is degraded, inputs may feel laggy ({})", name, health);
+            } else {
+                info!("Link to {} recovered ({})", name, health);
+            }
+        }
+    }
+
+    // The slowest link sets the pace, the others get the same updates
+    pub fn stick_interval(&self) -> Duration {
+        self.health.iter().map(LinkHealth::stick_interval).max().unwrap_or_default()
+    }
+
+    // What each console holds, as far as the commands sent to it go
+    pub fn held(&self) -> impl Iterator<Item = (&str, &HeldState)> {
+        self.targets.iter().map(|(name, _)| name.as_str()).zip(&self.held)
+    }
+
+    pub fn health(&self) -> impl Iterator<Item = (&str, &LinkHealth)> {
+        self.targets.iter().map(|(name, _)| name.as_str()).zip(&self.health)
+    }
+
+    // Input events that never made it to a console, like those of gamepads past the configured ones
+    pub fn drop_event(&mut self) {
+        self.dropped_events += 1;
+    }
+
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events
+    }
+
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // The session so far, one line each
+    pub fn stats(&self) -> Vec<String> {
+        let uptime = self.uptime();
+        let mut lines = vec![format!(
+            "Session of {}m{:02}s, {} dropped event(s), {} command(s) coalesced away",
+            uptime.as_secs() / 60,
+            uptime.as_secs() % 60,
+            self.dropped_events,
+            self.coalesced
+        )];
+        lines.extend(self.health().map(|(name, health)| format!("Link to {}: {}", name, health)));
+        lines
+    }
+}
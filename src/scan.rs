@@ -0,0 +1,141 @@
+use crate::dump::DEFAULT_CHUNK;
+use crate::memory::{self, Region};
+use crate::router::Router;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const DEFAULT_FILE: &str = "scan.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Filter {
+    EQUAL,
+    CHANGED,
+    UNCHANGED,
+    INCREASED,
+    DECREASED,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+struct Candidate {
+    address: u64,
+    value: u64,
+}
+
+// Values are little endian unsigned integers of the same width, aligned on it
+#[derive(Serialize, Deserialize)]
+pub struct Scan {
+    region: Region,
+    width: usize,
+    candidates: Vec<Candidate>,
+}
+
+pub fn parse_value(s: &str) -> Result<u64, String> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| format!("Invalid value {}", s))
+}
+
+pub fn parse_width(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(width @ (1 | 2 | 4 | 8)) => Ok(width),
+        _ => Err(format!("Invalid width {}, expected 1, 2, 4 or 8", s)),
+    }
+}
+
+fn value_at(bytes: &[u8], width: usize) -> u64 {
+    let mut value = [0; 8];
+    value[..width].copy_from_slice(&bytes[..width]);
+    u64::from_le_bytes(value)
+}
+
+fn progress(done: usize, total: usize) {
+    print!("\rRead {}/{} bytes ({}%)", done, total, done * 100 / total.max(1));
+    let _ = io::stdout().flush();
+}
+
+impl Scan {
+    // Reads the whole range chunk by chunk and keeps every value equal to the one searched
+    pub fn first(router: &mut Router, region: Region, offset: u64, size: usize, width: usize, value: u64) -> Result<Scan, String> {
+        let mut candidates = Vec::new();
+        let mut done = 0;
+        while done < size {
+            let length = DEFAULT_CHUNK.min(size - done) / width * width;
+            if length == 0 {
+                break;
+            }
+            let start = offset + done as u64;
+            let bytes = memory::peek(router, region, start, length)?;
+            candidates.extend(bytes.chunks_exact(width)
+                .enumerate()
+                .map(|(index, chunk)| Candidate { address: start + (index * width) as u64, value: value_at(chunk, width) })
+                .filter(|candidate| candidate.value == value));
+            done += length;
+            progress(done, size);
+            router.heartbeat();
+        }
+        println!();
+        Ok(Scan { region, width, candidates })
+    }
+
+    // Candidates close to each other are read together, a chunk at a time
+    pub fn next(&mut self, router: &mut Router, filter: Filter, value: Option<u64>) -> Result<(), String> {
+        if filter == Filter::EQUAL && value.is_none() {
+            return Err("The equal filter needs a value".to_string());
+        }
+
+        let mut kept = Vec::new();
+        let mut index = 0;
+        while index < self.candidates.len() {
+            let start = self.candidates[index].address;
+            let end = self.candidates[index..].iter()
+                .take_while(|candidate| candidate.address + (self.width as u64) - start <= DEFAULT_CHUNK as u64)
+                .last()
+                .map_or(start, |candidate| candidate.address) + self.width as u64;
+            let bytes = memory::peek(router, self.region, start, (end - start) as usize)?;
+
+            while index < self.candidates.len() && self.candidates[index].address < end {
+                let candidate = self.candidates[index];
+                let position = (candidate.address - start) as usize;
+                let current = value_at(&bytes[position..], self.width);
+                let keep = match filter {
+                    Filter::EQUAL => Some(current) == value,
+                    Filter::CHANGED => current != candidate.value,
+                    Filter::UNCHANGED => current == candidate.value,
+                    Filter::INCREASED => current > candidate.value,
+                    Filter::DECREASED => current < candidate.value,
+                };
+                if keep {
+                    kept.push(Candidate { address: candidate.address, value: current });
+                }
+                index += 1;
+            }
+            router.heartbeat();
+        }
+        self.candidates = kept;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Scan, String> {
+        let content = fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+        serde_json::from_str(&content).map_err(|err| format!("Invalid scan {}: {}", path.display(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = serde_json::to_string(self).map_err(|err| err.to_string())?;
+        fs::write(path, content).map_err(|err| format!("Unable to write {}: {}", path.display(), err))
+    }
+
+    pub fn print(&self, limit: usize) {
+        println!("{} candidate(s) in the {} region", self.candidates.len(), self.region.name());
+        for candidate in self.candidates.iter().take(limit) {
+            println!("0x{:X} = {} (0x{:X})", candidate.address, candidate.value, candidate.value);
+        }
+        if self.candidates.len() > limit {
+            println!("...");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use crate::capture::{self, Instruction};
+use crate::config::ScheduleSettings;
+use crate::error::Error;
+use crate::macros;
+use crate::router::Router;
+use chrono::{DateTime, Local};
+use cron::Schedule;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+enum When {
+    CRON(Box<Schedule>),
+    EVERY(Duration),
+}
+
+struct Entry {
+    name: String,
+    instructions: Vec<Instruction>,
+    when: When,
+    next: Option<DateTime<Local>>,
+}
+
+impl Entry {
+    fn plan(&mut self, now: DateTime<Local>) {
+        self.next = match &self.when {
+            When::CRON(schedule) => schedule.after(&now).next(),
+            When::EVERY(every) => chrono::Duration::from_std(*every).ok().map(|every| now + every),
+        };
+        match self.next {
+            Some(next) => info!("Next run of {} at {}", self.name, next.format("%Y-%m-%d %H:%M:%S")),
+            None => info!("{} won't run again", self.name),
+        }
+    }
+}
+
+// Cron expressions are the usual five fields (minute hour day month weekday) in local time,
+// a sixth leading field gives the seconds
+fn parse_cron(expression: &str) -> Result<Schedule, String> {
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_string(),
+    };
+    Schedule::from_str(&expression).map_err(|err| err.to_string())
+}
+
+// Macros run one at a time and block the gamepad while they play, it comes back in between
+pub struct Scheduler {
+    entries: Vec<Entry>,
+}
+
+impl Scheduler {
+    // A broken macro only leaves its entry out, a broken schedule is an error of the config
+    pub fn new(settings: &BTreeMap<String, ScheduleSettings>) -> Result<Scheduler, Error> {
+        let now = Local::now();
+        let mut entries = Vec::new();
+        for (name, settings) in settings {
+            let invalid = |reason: String| Error::INVALID { what: format!("schedule {}", name), reason };
+            let when = match (&settings.cron, &settings.every) {
+                (Some(cron), None) => When::CRON(Box::new(parse_cron(cron).map_err(|err| invalid(format!("cron expression: {}", err)))?)),
+                (None, Some(every)) => When::EVERY(macros::parse_duration(every).map_err(|err| invalid(format!("interval: {}", err)))?),
+                _ => return Err(invalid("needs either a cron expression or an interval".to_string())),
+            };
+            // Loaded once so a broken macro shows now rather than when its run comes up
+            match capture::load_macro(&settings.file) {
+                Ok(instructions) => {
+                    let mut entry = Entry { name: name.clone(), instructions, when, next: None };
+                    entry.plan(now);
+                    entries.push(entry);
+                }
+                Err(err) => warn!("{} won't run ({})", name, err),
+            }
+        }
+        Ok(Scheduler { entries })
+    }
+
+    pub fn poll(&mut self, router: &mut Router) {
+        for entry in &mut self.entries {
+            if entry.next.is_none_or(|next| next > Local::now()) {
+                continue;
+            }
+            info!("Running {}", entry.name);
+            router.count_run(&format!("schedule {}", entry.name));
+            capture::play(&entry.instructions, router);
+            // Runs missed while this one played are skipped rather than queued
+            entry.plan(Local::now());
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use crate::command::Command;
+use crate::response::Response;
+use crate::router::Router;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+const JPEG_MAGIC: [u8; 2] = [0xFF, 0xD8];
+
+pub fn capture(router: &mut Router, dir: &Path) -> Result<PathBuf, String> {
+    let jpeg = match router.request(&Command::OTHER("pixelPeek".to_string()))? {
+        Response::BYTES(bytes) if bytes.starts_with(&JPEG_MAGIC) => bytes,
+        _ => return Err("pixelPeek didn't answer with a JPEG".to_string()),
+    };
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let path = dir.join(format!("screenshot-{}{:03}.jpg", timestamp.as_secs(), timestamp.subsec_millis()));
+    fs::create_dir_all(dir).and_then(|_| fs::write(&path, jpeg)).map_err(|err| err.to_string())?;
+    Ok(path)
+}
+
+pub fn save(router: &mut Router, dir: &Path) {
+    match capture(router, dir) {
+        Ok(path) => info!("Saved screenshot to {}", path.display()),
+        Err(err) => warn!("Unable to take a screenshot ({})", err),
+    }
+}
@@ -0,0 +1,251 @@
+use crate::command::{self, Command, STICKS};
+use crate::config::Source;
+use crate::error::Error;
+use crate::frame::{self, Frame, Template};
+use crate::macros;
+use crate::memory::{self, Location, Region};
+use crate::response::Response;
+use crate::router::Router;
+use crate::shutdown;
+use crate::screenshot;
+use rhai::{Blob, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+// How often on_title_change looks at the running title
+const TITLE_CHECK: Duration = Duration::from_secs(1);
+
+type Shared = Rc<RefCell<Router>>;
+type Outcome<T> = Result<T, Box<EvalAltResult>>;
+
+fn with<T>(router: &Shared, f: impl FnOnce(&mut Router) -> Result<T, String>) -> Outcome<T> {
+    f(&mut router.borrow_mut()).map_err(|err| err.into())
+}
+
+fn send(router: &Shared, command: Result<Command, String>) -> Outcome<()> {
+    router.borrow_mut().send(Source::MACRO, 0, vec![command?]);
+    Ok(())
+}
+
+fn location(s: &str) -> Result<Location, String> {
+    s.parse()
+}
+
+fn position(found: Option<(usize, usize)>) -> Dynamic {
+    match found {
+        Some((x, y)) => vec![Dynamic::from(x as i64), Dynamic::from(y as i64)].into(),
+        None => Dynamic::UNIT,
+    }
+}
+
+fn register(engine: &mut Engine, router: &Shared) {
+    let shared = router.clone();
+    engine.register_fn("press", move |button: &str| -> Outcome<()> {
+        send(&shared, macros::button(button).map(Command::PRESS))
+    });
+    let shared = router.clone();
+    engine.register_fn("release", move |button: &str| -> Outcome<()> {
+        send(&shared, macros::button(button).map(Command::RELEASE))
+    });
+    let shared = router.clone();
+    engine.register_fn("click", move |button: &str| -> Outcome<()> {
+        send(&shared, macros::button(button).map(Command::CLICK))
+    });
+    let shared = router.clone();
+    engine.register_fn("stick", move |stick: &str, x: i64, y: i64| -> Outcome<()> {
+        let stick = stick.to_uppercase();
+        if !STICKS.contains(&stick.as_str()) {
+            return Err(format!("Unknown stick {}", stick).into());
+        }
+        let axis = |value: i64| i32::try_from(value.clamp(-32767, 32767)).unwrap_or_default();
+        send(&shared, Ok(Command::SETSTICK(command::name(&STICKS, &stick), axis(x), axis(y))))
+    });
+    // Waiting keeps the link alive, a long wait would otherwise look like a dead link
+    let shared = router.clone();
+    engine.register_fn("wait", move |ms: i64| {
+        let resume = Instant::now() + Duration::from_millis(ms.max(0) as u64);
+        while let Some(wait) = resume.checked_duration_since(Instant::now()).filter(|_| !shutdown::requested()) {
+            shared.borrow_mut().heartbeat();
+            thread::sleep(wait.min(Duration::from_millis(100)));
+        }
+    });
+    let shared = router.clone();
+    engine.register_fn("send", move |command: &str| -> Outcome<()> {
+        send(&shared, command.parse())
+    });
+    let shared = router.clone();
+    engine.register_fn("query", move |command: &str| -> Outcome<String> {
+        let command: Command = command.parse()?;
+        with(&shared, |router| router.request(&command).map(|response| response.to_string()))
+    });
+    let shared = router.clone();
+    engine.register_fn("peek", move |at: &str, size: i64| -> Outcome<Blob> {
+        let at = location(at)?;
+        with(&shared, |router| memory::peek_at(router, Region::HEAP, &at, size.max(0) as usize))
+    });
+    let shared = router.clone();
+    engine.register_fn("peek_int", move |at: &str, size: i64| -> Outcome<i64> {
+        let at = location(at)?;
+        let bytes = with(&shared, |router| memory::peek_at(router, Region::HEAP, &at, size.clamp(1, 8) as usize))?;
+        let mut value = [0; 8];
+        value[..bytes.len()].copy_from_slice(&bytes);
+        Ok(i64::from_le_bytes(value))
+    });
+    let shared = router.clone();
+    engine.register_fn("poke", move |at: &str, data: Blob| -> Outcome<()> {
+        let at = location(at)?;
+        memory::poke_at(&mut shared.borrow_mut(), Region::HEAP, &at, &data);
+        Ok(())
+    });
+    let shared = router.clone();
+    engine.register_fn("pixel", move |x: i64, y: i64| -> Outcome<i64> {
+        let frame = with(&shared, Frame::grab)?;
+        frame.pixel(x.max(0) as usize, y.max(0) as usize)
+            .map(i64::from)
+            .ok_or_else(|| format!("{},{} is outside the {}x{} screen", x, y, frame.width, frame.height).into())
+    });
+    // Colors are 0xRRGGBB, without a timeout the wait only ends once the pixel matches
+    let shared = router.clone();
+    engine.register_fn("wait_until_pixel", move |x: i64, y: i64, color: i64, tolerance: i64| -> Outcome<()> {
+        let (x, y, color, tolerance) = (x.max(0) as usize, y.max(0) as usize, color as u32, tolerance.clamp(0, 255) as u8);
+        with(&shared, |router| frame::wait_for_pixel(router, x, y, color, tolerance, None)).map(|_| ())
+    });
+    let shared = router.clone();
+    engine.register_fn("wait_until_pixel", move |x: i64, y: i64, color: i64, tolerance: i64, timeout: i64| -> Outcome<bool> {
+        let (x, y, color, tolerance) = (x.max(0) as usize, y.max(0) as usize, color as u32, tolerance.clamp(0, 255) as u8);
+        let timeout = Duration::from_millis(timeout.max(0) as u64);
+        with(&shared, |router| frame::wait_for_pixel(router, x, y, color, tolerance, Some(timeout)))
+    });
+    engine.register_fn("pixel_matches", |color: i64, expected: i64, tolerance: i64| {
+        frame::close_to(color as u32, expected as u32, tolerance.clamp(0, 255) as u8)
+    });
+    // Templates are PNG files, a found one gives the [x, y] of its top left corner and () otherwise
+    let shared = router.clone();
+    engine.register_fn("find_template", move |path: &str, tolerance: i64| -> Outcome<Dynamic> {
+        let template = Template::load(Path::new(path))?;
+        let frame = with(&shared, Frame::grab)?;
+        Ok(position(frame.find(&template, tolerance.clamp(0, 255) as u8, (0, 0, frame.width, frame.height))))
+    });
+    let shared = router.clone();
+    engine.register_fn("find_template", move |path: &str, tolerance: i64, x: i64, y: i64, width: i64, height: i64| -> Outcome<Dynamic> {
+        let template = Template::load(Path::new(path))?;
+        let frame = with(&shared, Frame::grab)?;
+        let area = (x.max(0) as usize, y.max(0) as usize, width.max(0) as usize, height.max(0) as usize);
+        Ok(position(frame.find(&template, tolerance.clamp(0, 255) as u8, area)))
+    });
+    let shared = router.clone();
+    engine.register_fn("wait_until_template", move |path: &str, tolerance: i64, timeout: i64| -> Outcome<Dynamic> {
+        let template = Template::load(Path::new(path))?;
+        let timeout = Duration::from_millis(timeout.max(0) as u64);
+        with(&shared, |router| frame::wait_for_template(router, &template, tolerance.clamp(0, 255) as u8, Some(timeout))).map(position)
+    });
+    let shared = router.clone();
+    engine.register_fn("screenshot", move || -> Outcome<String> {
+        with(&shared, |router| screenshot::capture(router, Path::new(".")).map(|path| path.display().to_string()))
+    });
+}
+
+// The router is lent to the engine for the time the script runs and handed back after
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+    router: Shared,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script, String> {
+        let router = Shared::default();
+        let mut engine = Engine::new();
+        engine.on_progress(|_| shutdown::requested().then_some(Dynamic::UNIT));
+        register(&mut engine, &router);
+        let ast = engine.compile_file(path.to_path_buf()).map_err(|err| err.to_string())?;
+        Ok(Script { engine, ast, scope: Scope::new(), state: Map::new().into(), router })
+    }
+
+    fn lend<T>(&mut self, router: &mut Router, f: impl FnOnce(&mut Script) -> T) -> T {
+        std::mem::swap(router, &mut self.router.borrow_mut());
+        let result = f(self);
+        std::mem::swap(router, &mut self.router.borrow_mut());
+        result
+    }
+
+    pub fn run(&mut self, router: &mut Router) -> Result<(), String> {
+        self.lend(router, |script| {
+            script.engine.run_ast_with_scope(&mut script.scope, &script.ast).map_err(|err| err.to_string())
+        })
+    }
+
+    fn has_hook(&self, name: &str, arity: usize) -> bool {
+        self.ast.iter_functions().any(|function| function.name == name && function.params.len() == arity)
+    }
+
+    // Hooks the script doesn't define are skipped, a failing one is reported and the session goes on
+    fn hook(&mut self, router: &mut Router, name: &str, args: impl FuncArgs) -> Option<Dynamic> {
+        let result = self.lend(router, |script| {
+            let options = CallFnOptions::new().bind_this_ptr(&mut script.state);
+            script.engine.call_fn_with_options::<Dynamic>(options, &mut script.scope, &script.ast, name, args)
+        });
+        result.inspect_err(|err| warn!("Script hook {} failed ({})", name, err)).ok()
+    }
+}
+
+// A script given to the gamepad forwarding runs once, then its hooks are called as things happen:
+// on_connect(), on_tick(), on_title_change(old, new) and on_button(button, pressed), which drops
+// the button when it returns false. Functions can't see the variables of the script, hooks keep
+// their state in the this map instead
+pub struct Hooks {
+    script: Script,
+    title: Option<u64>,
+    title_checked: Option<Instant>,
+}
+
+impl Hooks {
+    pub fn load(path: &Path, router: &mut Router) -> Result<Hooks, Error> {
+        let mut script = Script::load(path).map_err(|reason| Error::LOAD { path: path.to_path_buf(), reason })?;
+        if let Err(err) = script.run(router) {
+            error!("Script {} failed ({})", path.display(), err);
+        }
+        if script.has_hook("on_connect", 0) {
+            script.hook(router, "on_connect", ());
+        }
+        Ok(Hooks { script, title: None, title_checked: None })
+    }
+
+    pub fn tick(&mut self, router: &mut Router) {
+        if self.script.has_hook("on_title_change", 2) && self.title_checked.is_none_or(|checked| checked.elapsed() >= TITLE_CHECK) {
+            self.title_checked = Some(Instant::now());
+            if let Ok(Response::NUMBER(title)) = router.request(&Command::OTHER("getTitleID".to_string())) {
+                let previous = self.title.replace(title);
+                if previous.is_some_and(|previous| previous != title) {
+                    let format = |title: u64| format!("{:016X}", title);
+                    self.script.hook(router, "on_title_change", (previous.map(format).unwrap_or_default(), format(title)));
+                }
+            }
+        }
+        if self.script.has_hook("on_tick", 0) {
+            self.script.hook(router, "on_tick", ());
+        }
+    }
+
+    pub fn button(&mut self, router: &mut Router, button: &str, pressed: bool) -> bool {
+        if !self.script.has_hook("on_button", 2) {
+            return true;
+        }
+        self.script.hook(router, "on_button", (button.to_string(), pressed))
+            .and_then(|keep| keep.as_bool().ok())
+            .unwrap_or(true)
+    }
+}
+
+pub fn run(router: &mut Router, path: &Path) {
+    let result = Script::load(path).and_then(|mut script| script.run(router));
+    if let Err(err) = result {
+        error!("Script {} failed ({})", path.display(), err);
+    }
+}
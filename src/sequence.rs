@@ -0,0 +1,17 @@
+use crate::command::{Command, Name};
+
+pub enum Step {
+    CLICK(Name),
+    WAIT(u64),
+}
+
+// sys-botbase runs a whole clickSeq on its own, one packet instead of one per click
+pub fn click_seq(steps: &[Step]) -> Command {
+    let steps: Vec<String> = steps.iter()
+        .map(|step| match step {
+            Step::CLICK(button) => button.to_string(),
+            Step::WAIT(millis) => format!("W{}", millis),
+        })
+        .collect();
+    Command::OTHER(format!("clickSeq {}", steps.join(",")))
+}
@@ -0,0 +1,111 @@
+use crate::error::Error;
+use crate::command::Command;
+use crate::config::Framing;
+use crate::health::SendOutcome;
+use crate::notify;
+use crate::protocol::Transfers;
+use crate::transport::Transport;
+use serialport::SerialPort;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+pub const DEFAULT_BAUD: u32 = 115200;
+
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug)]
+pub struct SerialDevice {
+    pub path: String,
+    pub baud: u32,
+}
+
+impl FromStr for SerialDevice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('@') {
+            Some((path, baud)) => {
+                let baud = baud.parse().map_err(|_| format!("Invalid baud rate {}", baud))?;
+                Ok(SerialDevice { path: path.to_string(), baud })
+            }
+            None => Ok(SerialDevice { path: s.to_string(), baud: DEFAULT_BAUD }),
+        }
+    }
+}
+
+pub fn list_ports() -> Vec<String> {
+    serialport::available_ports()
+        .map(|ports| ports.into_iter().map(|port| port.port_name).collect())
+        .unwrap_or_default()
+}
+
+fn try_open(device: &SerialDevice) -> serialport::Result<Box<dyn SerialPort>> {
+    serialport::new(&device.path, device.baud)
+        .timeout(WRITE_TIMEOUT)
+        .open()
+}
+
+fn open(device: &SerialDevice) -> Box<dyn SerialPort> {
+    loop {
+        match try_open(device) {
+            Ok(port) => return port,
+            Err(err) => {
+                warn!("Unable to open {} ({}), retrying...", device.path, err);
+                thread::sleep(REOPEN_INTERVAL);
+            }
+        }
+    }
+}
+
+// The microcontroller firmware parses the same text commands sys-botbase gets over TCP
+fn write_commands(port: &mut Box<dyn SerialPort>, transfers: &mut Transfers, commands: &[Command]) -> io::Result<()> {
+    transfers.encode(Framing::LINES, commands).iter().try_for_each(|transfer| port.write_all(transfer))?;
+    port.flush()
+}
+
+pub struct Serial {
+    port: Box<dyn SerialPort>,
+    device: SerialDevice,
+    transfers: Transfers,
+}
+
+impl Serial {
+    pub fn connect(device: SerialDevice) -> Result<Serial, Error> {
+        let port = try_open(&device).map_err(|source| Error::SERIAL { path: device.path.clone(), source })?;
+        Ok(Serial { port, device, transfers: Transfers::default() })
+    }
+}
+
+impl Transport for Serial {
+    fn describe(&self) -> String {
+        format!("microcontroller on {}", self.device.path)
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.port = try_open(&self.device).map_err(|err| err.to_string())?;
+        Ok(())
+    }
+
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        while let Err(err) = write_commands(&mut self.port, &mut self.transfers, commands) {
+            warn!("Lost serial connection to {} ({}), reopening...", self.device.path, err);
+            notify::send(notify::SWITCH_LOST, &format!("Lost the serial connection to {} ({}), reopening", self.device.path, err));
+            self.port = open(&self.device);
+            info!("Reopened {}!", self.device.path);
+            notify::send(notify::SWITCH_BACK, &format!("Reopened {}", self.device.path));
+            outcome.failures += 1;
+            outcome.reconnects += 1;
+        }
+        outcome.bytes = self.transfers.bytes().len() as u64;
+        outcome
+    }
+
+    fn try_send_commands(&mut self, commands: &[Command]) -> Result<(), String> {
+        write_commands(&mut self.port, &mut self.transfers, commands).map_err(|err| err.to_string())
+    }
+}
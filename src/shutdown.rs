@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+const STEP: Duration = Duration::from_millis(50);
+
+// A first Ctrl+C or SIGTERM lets the running command stop and leave the console clean,
+// a second one quits right away
+pub fn install() {
+    let handler = ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        info!("Stopping, press Ctrl+C again to quit right away");
+    });
+    if let Err(err) = handler {
+        warn!("Unable to catch Ctrl+C ({}), inputs may stay held when stopping", err);
+    }
+}
+
+// Stops like a first Ctrl+C, for what quits from elsewhere than the terminal
+pub fn request() {
+    if !REQUESTED.swap(true, Ordering::SeqCst) {
+        info!("Stopping");
+    }
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+// Sleeps in short steps so a stop doesn't wait for the end of a long delay
+pub fn sleep(duration: Duration) {
+    let end = Instant::now() + duration;
+    while let Some(left) = end.checked_duration_since(Instant::now()).filter(|_| !requested()) {
+        thread::sleep(left.min(STEP));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::capture::Instruction;
+use crate::error::Error;
+use crate::macros;
+use std::f64::consts::TAU;
+use std::fmt::Write;
+
+// Built-in recipes, written in the macro language from the few numbers they ask for
+pub struct Template {
+    pub name: &'static str,
+    pub description: &'static str,
+    parameters: &'static [(&'static str, u32)],
+    write: fn(&[u32]) -> String,
+}
+
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "egg-hatch",
+        description: "Run in circles then mash A to go through the hatching, over and over",
+        parameters: &[("Seconds to run in circles", 60), ("Times to press A afterwards", 30), ("Cycles", 5)],
+        write: egg_hatch,
+    },
+    Template {
+        name: "mash",
+        description: "Press A a number of times with a pause between presses",
+        parameters: &[("Presses", 100), ("Milliseconds between presses", 100)],
+        write: mash,
+    },
+    Template {
+        name: "soft-reset",
+        description: "Close the game from HOME and start it again, mashing A through the title screen",
+        parameters: &[("Seconds the game takes to start", 20), ("Times to press A on the title screen", 10)],
+        write: soft_reset,
+    },
+    Template {
+        name: "walk",
+        description: "Walk left and right in place to trigger random encounters",
+        parameters: &[("Milliseconds each way", 800), ("Round trips", 50)],
+        write: walk,
+    },
+];
+
+const CIRCLE_STEPS: u32 = 8;
+const CIRCLE_STEP_MS: u32 = 125;
+
+fn egg_hatch(values: &[u32]) -> String {
+    let mut text = String::new();
+    for _ in 0..values[2] {
+        for step in 0..values[0] * 1000 / CIRCLE_STEP_MS {
+            let angle = TAU * (step % CIRCLE_STEPS) as f64 / CIRCLE_STEPS as f64;
+            let (x, y) = ((angle.cos() * 32767.0) as i32, (angle.sin() * 32767.0) as i32);
+            let _ = writeln!(text, "stick LEFT {} {}; wait {}ms", x, y, CIRCLE_STEP_MS);
+        }
+        let _ = writeln!(text, "stick LEFT 0 0");
+        for _ in 0..values[1] {
+            let _ = writeln!(text, "click A; wait 500ms");
+        }
+    }
+    text
+}
+
+fn mash(values: &[u32]) -> String {
+    (0..values[0]).map(|_| format!("click A; wait {}ms\n", values[1])).collect()
+}
+
+fn soft_reset(values: &[u32]) -> String {
+    let mut text = String::from("click HOME; wait 1s\nclick X; wait 1s\nclick A; wait 3s\nclick A; wait 1s\nclick A\n");
+    let _ = writeln!(text, "wait {}s", values[0]);
+    for _ in 0..values[1] {
+        let _ = writeln!(text, "click A; wait 1s");
+    }
+    text
+}
+
+fn walk(values: &[u32]) -> String {
+    (0..values[1])
+        .map(|_| format!("stick LEFT -32767 0 for {0}ms\nstick LEFT 32767 0 for {0}ms\n", values[0]))
+        .collect()
+}
+
+pub fn find(name: &str) -> Result<&'static Template, Error> {
+    TEMPLATES.iter()
+        .find(|template| template.name == name)
+        .ok_or_else(|| Error::TEMPLATE(format!("Unknown template {}, expected one of {}", name, names().join(", "))))
+}
+
+pub fn names() -> Vec<&'static str> {
+    TEMPLATES.iter().map(|template| template.name).collect()
+}
+
+pub fn select() -> Result<&'static Template, Error> {
+    let options: Vec<String> = TEMPLATES.iter().map(|template| format!("{} - {}", template.name, template.description)).collect();
+    let index = inquire::Select::new("Which automation do you want to run?", options)
+        .raw_prompt()
+        .map_err(|err| Error::TEMPLATE(format!("No template selected ({})", err)))?
+        .index;
+    Ok(&TEMPLATES[index])
+}
+
+impl Template {
+    // Every parameter is asked for, its default being what the recipe usually needs
+    pub fn prompt(&self) -> Result<String, Error> {
+        let values: Vec<u32> = self.parameters.iter()
+            .map(|(question, default)| {
+                inquire::CustomType::<u32>::new(question)
+                    .with_default(*default)
+                    .with_error_message("Please type a whole number")
+                    .prompt()
+                    .map_err(|err| Error::TEMPLATE(format!("No value given ({})", err)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((self.write)(&values))
+    }
+}
+
+pub fn instructions(text: &str) -> Result<Vec<Instruction>, Error> {
+    macros::parse(text).map_err(|err| Error::TEMPLATE(format!("Invalid template ({})", err)))
+}
@@ -0,0 +1,38 @@
+use crate::shutdown;
+use std::time::{Duration, Instant};
+
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+// Ticks on a fixed schedule, the time spent on a tick doesn't push the next ones back.
+// Ticks missed while the thread was busy are skipped rather than run in a burst
+pub struct Ticker {
+    interval: Duration,
+    next: Instant,
+}
+
+impl Ticker {
+    pub fn new(interval: Duration) -> Ticker {
+        let interval = interval.max(MIN_INTERVAL);
+        Ticker { interval, next: Instant::now() + interval }
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.next
+    }
+
+    // Moves on to the next tick when this one is due
+    pub fn due(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        let missed = (now - self.next).as_nanos() / self.interval.as_nanos();
+        self.next += self.interval * (missed as u32 + 1);
+        true
+    }
+
+    pub fn wait(&mut self) {
+        shutdown::sleep(self.next.saturating_duration_since(Instant::now()));
+        self.due();
+    }
+}
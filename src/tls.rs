@@ -0,0 +1,90 @@
+use crate::config::TlsSettings;
+use crate::error::Error;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, OnceLock};
+
+struct Tls {
+    config: Arc<ClientConfig>,
+    server_name: ServerName<'static>,
+}
+
+static TLS: OnceLock<Tls> = OnceLock::new();
+
+pub enum Stream {
+    PLAIN(TcpStream),
+    TLS(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::PLAIN(socket) => socket,
+            Stream::TLS(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::PLAIN(socket) => socket.read(buf),
+            Stream::TLS(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::PLAIN(socket) => socket.write(buf),
+            Stream::TLS(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::PLAIN(socket) => socket.flush(),
+            Stream::TLS(stream) => stream.flush(),
+        }
+    }
+}
+
+pub fn configure(settings: &TlsSettings) -> Result<(), Error> {
+    let mut roots = RootCertStore::empty();
+    match &settings.ca_file {
+        Some(ca_file) => {
+            let invalid = |reason: String| Error::LOAD { path: ca_file.clone(), reason };
+            let certificates = CertificateDer::pem_file_iter(ca_file).map_err(|err| invalid(err.to_string()))?;
+            for certificate in certificates {
+                let certificate = certificate.map_err(|err| invalid(format!("invalid certificate: {}", err)))?;
+                roots.add(certificate).map_err(|err| invalid(format!("unsupported certificate: {}", err)))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| Error::TLS(err.to_string()))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(settings.server_name.clone())
+        .map_err(|err| Error::TLS(format!("invalid server name {}: {}", settings.server_name, err)))?;
+
+    TLS.set(Tls { config: Arc::new(config), server_name }).map_err(|_| Error::TLS("already configured".to_string()))
+}
+
+// The handshake happens on the first read or write, so errors surface like any other I/O failure
+pub fn wrap(socket: TcpStream) -> io::Result<Stream> {
+    let Some(tls) = TLS.get() else {
+        return Ok(Stream::PLAIN(socket));
+    };
+
+    let connection = ClientConnection::new(tls.config.clone(), tls.server_name.clone())
+        .map_err(io::Error::other)?;
+    Ok(Stream::TLS(Box::new(StreamOwned::new(connection, socket))))
+}
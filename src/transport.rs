@@ -0,0 +1,203 @@
+use crate::bluetooth::{self, Bluetooth};
+use crate::command::Command;
+use crate::connection::Tcp;
+#[cfg(feature = "usb")]
+use crate::connection::UsbDevice;
+use crate::dryrun::DryRun;
+use crate::error::Error;
+use crate::gadget::Gadget;
+use crate::health::SendOutcome;
+use crate::hidplus::{self, HidPlus};
+use crate::response::Response;
+use crate::serial::{Serial, SerialDevice};
+#[cfg(feature = "usb")]
+use crate::usb::Usb;
+use std::fmt::{Debug, Display, Formatter};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+const RETRY_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+pub trait Transport: Send {
+    // What the link goes to, for messages
+    fn describe(&self) -> String;
+
+    // Delivers the commands, recovering from failures however the link allows
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome;
+
+    // A single attempt, so failover and redundant links notice a dead link themselves
+    fn try_send_commands(&mut self, commands: &[Command]) -> Result<(), String> {
+        failed(&self.describe(), self.send_commands(commands))
+    }
+
+    fn heartbeat(&mut self) -> SendOutcome {
+        SendOutcome::default()
+    }
+
+    fn try_heartbeat(&mut self) -> Result<(), String> {
+        failed(&self.describe(), self.heartbeat())
+    }
+
+    // Whether the link ends at a sysmodule running clickSeq itself, other links would drop it
+    fn runs_sequences(&self) -> bool {
+        false
+    }
+
+    // Sends a command sys-botbase answers and waits for its response
+    fn request(&mut self, command: &Command) -> Result<Response, String> {
+        Err(format!("This link can't read the response to {}", command))
+    }
+
+    // Opens the link again, links without a connection to lose have nothing to do
+    fn reconnect(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+// Links without a single attempt of their own still report a send that failed along the way
+fn failed(link: &str, outcome: SendOutcome) -> Result<(), String> {
+    match outcome.failures {
+        0 => Ok(()),
+        failures => Err(format!("{} failure(s) sending to {}", failures, link)),
+    }
+}
+
+// Keeps trying with a growing delay, so a switch rebooting or out of reach doesn't end a long run
+pub fn retry<T>(mut connect: impl FnMut() -> Result<T, Error>) -> T {
+    let mut backoff = RETRY_BACKOFF;
+    loop {
+        match connect() {
+            Ok(connected) => return connected,
+            Err(err) => {
+                warn!("{}, retrying in {}s...", err, backoff.as_secs());
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_RETRY_BACKOFF);
+            }
+        }
+    }
+}
+
+// Targets are validated when parsed, this only fires for an address that stopped parsing since
+fn invalid(address: &str, err: impl Display) -> Error {
+    Error::TARGET(format!("{} ({})", address, err))
+}
+
+pub struct Backend {
+    pub scheme: &'static str,
+    pub syntax: &'static str,
+    validate: fn(&str) -> Result<(), String>,
+    connect: fn(&str) -> Result<Box<dyn Transport>, Error>,
+}
+
+// Targets are written <scheme>:<address>, a new backend only needs an entry here
+pub static BACKENDS: &[Backend] = &[
+    #[cfg(feature = "usb")]
+    Backend {
+        scheme: "usb",
+        syntax: "usb[:<index or serial>]",
+        validate: |address| address.parse::<UsbDevice>().map(|_| ()),
+        connect: |address| Ok(Box::new(Usb::connect(address.parse().map_err(|err| invalid(address, err))?)?)),
+    },
+    Backend {
+        scheme: "serial",
+        syntax: "serial:<port>[@baud]",
+        validate: |address| address.parse::<SerialDevice>().map(|_| ()),
+        connect: |address| Ok(Box::new(Serial::connect(address.parse().map_err(|err| invalid(address, err))?)?)),
+    },
+    Backend {
+        scheme: "hidplus",
+        syntax: "hidplus:<ip>[:port]",
+        validate: |address| hidplus::parse_address(address).map(|_| ()),
+        connect: |address| Ok(Box::new(HidPlus::connect(hidplus::parse_address(address).map_err(|err| invalid(address, err))?).map_err(Error::HIDPLUS)?)),
+    },
+    Backend {
+        scheme: "bluetooth",
+        syntax: "bluetooth[:<switch address>]",
+        validate: |address| bluetooth::parse_switch(address).map(|_| ()),
+        connect: |address| {
+            let bluetooth = match bluetooth::parse_switch(address).map_err(|err| invalid(address, err))? {
+                Some(switch) => Bluetooth::reconnect(switch),
+                None => Bluetooth::pair(),
+            };
+            Ok(Box::new(bluetooth.map_err(Error::BLUETOOTH)?))
+        },
+    },
+    Backend {
+        scheme: "gadget",
+        syntax: "gadget",
+        validate: |address| match address {
+            "" => Ok(()),
+            _ => Err("The gadget target takes no address".to_string()),
+        },
+        connect: |_| Ok(Box::new(Gadget::create().map_err(Error::GADGET)?)),
+    },
+    Backend {
+        scheme: "dry-run",
+        syntax: "dry-run",
+        validate: |address| match address {
+            "" => Ok(()),
+            _ => Err("The dry-run target takes no address".to_string()),
+        },
+        connect: |_| Ok(Box::new(DryRun::new())),
+    },
+    Backend {
+        scheme: "tcp",
+        syntax: "[tcp:]ip:port",
+        validate: |address| address.parse::<SocketAddr>().map(|_| ()).map_err(|err| err.to_string()),
+        connect: |address| Ok(Box::new(Tcp::connect(address.parse().map_err(|err| invalid(address, err))?)?)),
+    },
+];
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Target {
+    backend: &'static Backend,
+    address: String,
+}
+
+impl Target {
+    pub fn connect(&self) -> Result<Box<dyn Transport>, Error> {
+        (self.backend.connect)(&self.address)
+    }
+
+    pub fn connect_retrying(&self) -> Box<dyn Transport> {
+        retry(|| self.connect())
+    }
+}
+
+impl Debug for Target {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.backend.scheme, self.address)
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (scheme, address) = s.split_once(':').unwrap_or((s, ""));
+        let (backend, address) = match BACKENDS.iter().find(|backend| backend.scheme == scheme) {
+            Some(backend) => (backend, address),
+            None => (BACKENDS.iter().find(|backend| backend.scheme == "tcp").unwrap(), s),
+        };
+
+        (backend.validate)(address).map_err(|err| {
+            let syntaxes: Vec<&str> = BACKENDS.iter().map(|backend| backend.syntax).collect();
+            format!("Invalid target {} ({}), expected one of {}", s, err, syntaxes.join(", "))
+        })?;
+        Ok(Target { backend, address: address.to_string() })
+    }
+}
+
+impl TryFrom<String> for Target {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
@@ -0,0 +1,263 @@
+use crate::config::Config;
+use crate::notify;
+use crate::router::Router;
+use crate::shutdown;
+use ksni::blocking::{Handle, TrayMethods};
+use ksni::menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem, SubMenu};
+use ksni::{MenuItem, Status, ToolTip};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use tracing::info;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Link {
+    CONNECTED,
+    DEGRADED,
+    LOST,
+}
+
+// What the menu asks of the forwarding loop, which owns the router
+enum Choice {
+    PAUSE(bool),
+    PROFILE(PathBuf),
+}
+
+// Lives on the tray's own thread, the menu callbacks only pass choices on
+struct Menu {
+    profiles: Vec<PathBuf>,
+    active: Option<usize>,
+    link: Link,
+    paused: bool,
+    choices: Sender<Choice>,
+}
+
+impl Menu {
+    fn profile(&self) -> String {
+        self.active.map(|active| name(&self.profiles[active])).unwrap_or_else(|| "no config file".to_string())
+    }
+
+    fn describe(&self) -> &'static str {
+        match (self.link, self.paused) {
+            (Link::LOST, _) => "Switch disconnected, reconnecting",
+            (_, true) => "Connected, forwarding paused",
+            (Link::DEGRADED, false) => "Connected, link degraded",
+            (Link::CONNECTED, false) => "Connected",
+        }
+    }
+}
+
+impl ksni::Tray for Menu {
+    fn id(&self) -> String {
+        "switch-usb-controld".to_string()
+    }
+
+    fn title(&self) -> String {
+        format!("Switch controller ({})", self.profile())
+    }
+
+    fn status(&self) -> Status {
+        match self.link {
+            Link::LOST => Status::NeedsAttention,
+            Link::CONNECTED | Link::DEGRADED => Status::Active,
+        }
+    }
+
+    // Freedesktop icon names, every theme has them
+    fn icon_name(&self) -> String {
+        match (self.link, self.paused) {
+            (Link::LOST, _) => "network-offline",
+            (_, true) => "media-playback-pause",
+            (Link::DEGRADED, false) => "network-error",
+            (Link::CONNECTED, false) => "input-gaming",
+        }
+        .to_string()
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        ToolTip {
+            title: self.title(),
+            description: self.describe().to_string(),
+            ..ToolTip::default()
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let profiles = self.profiles.iter()
+            .map(|profile| RadioItem { label: name(profile), ..RadioItem::default() })
+            .collect();
+        let mut items: Vec<MenuItem<Self>> = vec![
+            StandardItem { label: self.describe().to_string(), enabled: false, ..StandardItem::default() }.into(),
+            StandardItem { label: format!("Profile: {}", self.profile()), enabled: false, ..StandardItem::default() }.into(),
+            MenuItem::Separator,
+            CheckmarkItem {
+                label: "Pause forwarding".to_string(),
+                checked: self.paused,
+                activate: Box::new(|menu: &mut Menu| {
+                    menu.paused = !menu.paused;
+                    let _ = menu.choices.send(Choice::PAUSE(menu.paused));
+                }),
+                ..CheckmarkItem::default()
+            }
+            .into(),
+        ];
+        if !self.profiles.is_empty() {
+            items.push(SubMenu {
+                label: "Switch profile".to_string(),
+                enabled: self.profiles.len() > 1,
+                submenu: vec![RadioGroup {
+                    selected: self.active.unwrap_or(usize::MAX),
+                    select: Box::new(|menu: &mut Menu, index| {
+                        if menu.active != Some(index) {
+                            menu.active = Some(index);
+                            let _ = menu.choices.send(Choice::PROFILE(menu.profiles[index].clone()));
+                        }
+                    }),
+                    options: profiles,
+                }
+                .into()],
+                ..SubMenu::default()
+            }
+            .into());
+        }
+        items.push(MenuItem::Separator);
+        items.push(
+            StandardItem {
+                label: "Quit".to_string(),
+                icon_name: "application-exit".to_string(),
+                activate: Box::new(|_| shutdown::request()),
+                ..StandardItem::default()
+            }
+            .into(),
+        );
+        items
+    }
+}
+
+fn name(profile: &Path) -> String {
+    profile.file_stem().unwrap_or(profile.as_os_str()).to_string_lossy().to_string()
+}
+
+// The config files next to the one in use are the profiles to switch to, without one there is
+// nothing to switch from. Other TOML files lying around are left out
+fn profiles(config: Option<&Path>) -> Vec<PathBuf> {
+    let Some(config) = config else {
+        return Vec::new();
+    };
+    let dir = config.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut profiles: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries.flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "toml") && Config::load(Some(path.as_path())).is_ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    profiles.sort();
+    profiles
+}
+
+// A StatusNotifierItem icon showing the link and profile while forwarding, with pause, profile and quit in its menu.
+// Shown by KDE, most Linux panels, and GNOME with the AppIndicator extension
+pub struct Tray {
+    handle: Handle<Menu>,
+    choices: Receiver<Choice>,
+    shown: (bool, bool),
+    switch_to: Option<PathBuf>,
+}
+
+impl Tray {
+    pub fn open(config: Option<&Path>) -> Result<Tray, String> {
+        let profiles = profiles(config);
+        let active = config.and_then(|config| profiles.iter().position(|profile| profile.file_name() == config.file_name()));
+        let (sender, choices) = mpsc::channel();
+        let menu = Menu { profiles, active, link: Link::CONNECTED, paused: false, choices: sender };
+        let handle = menu.spawn().map_err(|err| err.to_string())?;
+
+        // Links are lost and found inside a send, while the forwarding loop waits on it
+        let events = notify::listen();
+        let updates = handle.clone();
+        thread::spawn(move || {
+            for (summary, _) in events {
+                let link = match summary.as_str() {
+                    notify::SWITCH_LOST => Link::LOST,
+                    notify::SWITCH_BACK => Link::CONNECTED,
+                    _ => continue,
+                };
+                if updates.update(|menu| menu.link = link).is_none() {
+                    return;
+                }
+            }
+        });
+        Ok(Tray { handle, choices, shown: (false, false), switch_to: None })
+    }
+
+    // Called every tick, the icon is only updated when what it shows changed
+    pub fn refresh(&mut self, router: &mut Router) {
+        for choice in self.choices.try_iter() {
+            match choice {
+                Choice::PAUSE(paused) => router.pause_gamepads(paused),
+                Choice::PROFILE(profile) => {
+                    info!("Switching to the {} profile", name(&profile));
+                    self.switch_to = Some(profile);
+                    shutdown::request();
+                }
+            }
+        }
+        let shown = (router.health().any(|(_, health)| health.is_degraded()), router.gamepads_paused());
+        if shown != self.shown {
+            self.shown = shown;
+            self.handle.update(|menu| {
+                if menu.link != Link::LOST {
+                    menu.link = if shown.0 { Link::DEGRADED } else { Link::CONNECTED };
+                }
+                menu.paused = shown.1;
+            });
+        }
+    }
+
+    // The config file picked from the menu, the tool starts again with it once stopped
+    pub fn switched_to(&self) -> Option<&Path> {
+        self.switch_to.as_deref()
+    }
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        // Gone before the tool starts again with another profile
+        self.handle.shutdown().wait();
+    }
+}
+
+// Runs the tool again with another config file and the rest of the command line,
+// only returns when it couldn't
+pub fn restart(config: &Path) -> io::Error {
+    let mut args: Vec<OsString> = env::args_os().skip(1).collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--config") {
+        args.drain(index..(index + 2).min(args.len()));
+    }
+    args.retain(|arg| !arg.to_string_lossy().starts_with("--config="));
+    match env::current_exe() {
+        Ok(exe) => replace(Command::new(exe).arg("--config").arg(config).args(args)),
+        Err(err) => err,
+    }
+}
+
+#[cfg(unix)]
+fn replace(command: &mut Command) -> io::Error {
+    use std::os::unix::process::CommandExt;
+    command.exec()
+}
+
+#[cfg(not(unix))]
+fn replace(command: &mut Command) -> io::Error {
+    match command.spawn() {
+        Ok(_) => std::process::exit(0),
+        Err(err) => err,
+    }
+}
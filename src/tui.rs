@@ -0,0 +1,127 @@
+use crate::command::{BUTTONS, STICKS};
+use crate::error::Error;
+use crate::logging;
+use crate::router::Router;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::Terminal;
+use std::collections::VecDeque;
+use std::io::{self, Stdout};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+// Fast enough to follow the sticks, slow enough to leave the link alone
+const FRAME_INTERVAL: Duration = Duration::from_millis(100);
+const LOG_LINES: usize = 200;
+
+// Draws what is sent in place of the log while forwarding, the log shows at the bottom.
+// The terminal stays out of raw mode so Ctrl-C still stops the tool
+pub struct Dashboard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    profile: String,
+    log: Receiver<String>,
+    lines: VecDeque<String>,
+    drawn: Option<Instant>,
+}
+
+impl Dashboard {
+    pub fn open(profile: String) -> Result<Dashboard, Error> {
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen).map_err(Error::DASHBOARD)?;
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout)).map_err(Error::DASHBOARD)?;
+        terminal.hide_cursor().map_err(Error::DASHBOARD)?;
+        let (sender, log) = mpsc::channel();
+        logging::divert(Some(sender));
+        Ok(Dashboard { terminal, profile, log, lines: VecDeque::new(), drawn: None })
+    }
+
+    // Called every tick, draws at most at the frame rate
+    pub fn refresh(&mut self, router: &Router) {
+        self.lines.extend(self.log.try_iter());
+        while self.lines.len() > LOG_LINES {
+            self.lines.pop_front();
+        }
+        if self.drawn.is_some_and(|drawn| drawn.elapsed() < FRAME_INTERVAL) {
+            return;
+        }
+        self.drawn = Some(Instant::now());
+        let _ = self.draw(router);
+    }
+
+    fn draw(&mut self, router: &Router) -> io::Result<()> {
+        let uptime = router.uptime().as_secs();
+        let header = Line::from(format!("Profile: {}    up {}m{:02}s", self.profile, uptime / 60, uptime % 60));
+
+        let links: Vec<Line> = router.health()
+            .flat_map(|(name, health)| {
+                let status = if health.is_degraded() { "DEGRADED" } else { "ok" };
+                [
+                    Line::from(format!("{:<12} {:<8} {}", name, status, health)),
+                    Line::from(format!("{:<12} {:<8} {}", "", "now", health.rates)),
+                ]
+            })
+            .collect();
+
+        let mut inputs: Vec<Line> = Vec::new();
+        for (name, held) in router.held() {
+            inputs.push(Line::styled(name.to_string(), Style::new().add_modifier(Modifier::BOLD)));
+            let buttons: Vec<Span> = BUTTONS.iter()
+                .flat_map(|button| {
+                    let style = match held.buttons().any(|held| held == *button) {
+                        true => Style::new().add_modifier(Modifier::REVERSED),
+                        false => Style::new(),
+                    };
+                    [Span::styled(*button, style), Span::raw(" ")]
+                })
+                .collect();
+            inputs.push(Line::from(buttons));
+            let sticks: Vec<String> = STICKS.iter()
+                .map(|stick| {
+                    let (x, y) = held.stick(stick);
+                    format!("{:<5} x {:>6} y {:>6}", stick, x, y)
+                })
+                .collect();
+            inputs.push(Line::from(sticks.join("    ")));
+        }
+
+        let mut running: Vec<Line> = router.running().map(|(what, detail)| Line::from(format!("{} {}", what, detail))).collect();
+        if running.is_empty() {
+            running.push(Line::from("nothing"));
+        }
+
+        let lines = &self.lines;
+        self.terminal.draw(|frame| {
+            let [header_area, links_area, inputs_area, running_area, log_area] = Layout::vertical([
+                Constraint::Length(1),
+                Constraint::Length(links.len() as u16 + 2),
+                Constraint::Length(inputs.len() as u16 + 2),
+                Constraint::Length(running.len() as u16 + 2),
+                Constraint::Min(0),
+            ])
+            .areas(frame.area());
+
+            let visible = log_area.height.saturating_sub(2) as usize;
+            let log: Vec<Line> = lines.iter().skip(lines.len().saturating_sub(visible)).map(|line| Line::from(line.as_str())).collect();
+
+            frame.render_widget(Paragraph::new(header), header_area);
+            frame.render_widget(Paragraph::new(links).block(Block::bordered().title("Links")), links_area);
+            frame.render_widget(Paragraph::new(inputs).block(Block::bordered().title("Inputs")), inputs_area);
+            frame.render_widget(Paragraph::new(running).block(Block::bordered().title("Running")), running_area);
+            frame.render_widget(Paragraph::new(log).block(Block::bordered().title("Log")), log_area);
+        })?;
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        logging::divert(None);
+        let _ = self.terminal.show_cursor();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+    }
+}
@@ -0,0 +1,320 @@
+use crate::command::Command;
+use crate::config::{Framing, UsbSettings};
+use crate::connection::{expected_reply, UsbDevice, INITIAL_BACKOFF, MAX_BACKOFF, MAX_READ_CHUNK};
+use crate::error::Error;
+use crate::health::SendOutcome;
+use crate::notify;
+use crate::protocol::Transfers;
+use crate::response::Response;
+use crate::shutdown;
+use crate::transport::Transport;
+use futures_lite::future::block_on;
+use futures_lite::StreamExt;
+use nusb::hotplug::HotplugEvent;
+use nusb::transfer::{Queue, RequestBuffer, TransferError};
+use nusb::{Device, DeviceInfo, Interface};
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// An endpoint still stalling after this many clears won't take the batch, the next one tries again
+const MAX_CLEAR_HALTS: u32 = 3;
+// Over half a minute with the backoff, longer than a switch takes to come back
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+// How often waiting for a switch to be plugged in checks for a stop
+const PLUG_POLL: Duration = Duration::from_millis(200);
+
+static USB_SETTINGS: OnceLock<UsbSettings> = OnceLock::new();
+
+pub fn configure_usb(settings: UsbSettings) {
+    if USB_SETTINGS.set(settings).is_err() {
+        panic!("USB settings are already configured!");
+    }
+}
+
+fn usb_settings() -> UsbSettings {
+    *USB_SETTINGS.get_or_init(UsbSettings::default)
+}
+
+pub struct Usb {
+    interface: Interface,
+    // Kept from one batch to the next, the transfers of a batch can still be in flight when the next one comes
+    queue: Queue<Vec<u8>>,
+    device: UsbDevice,
+    transfers: Transfers,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Usb {
+    fn new(interface: Interface, device: UsbDevice) -> Usb {
+        let queue = interface.bulk_out_queue(usb_settings().endpoint);
+        Usb { interface, queue, device, transfers: Transfers::default(), buffers: Vec::new() }
+    }
+
+    pub fn connect(device: UsbDevice) -> Result<Usb, Error> {
+        Ok(Usb::new(open_switch_interface(&device)?, device))
+    }
+
+    pub fn try_connect(device: &UsbDevice) -> Option<Usb> {
+        try_open_switch_interface(device).map(|interface| Usb::new(interface, device.clone()))
+    }
+}
+
+// A dropped queue cancels its transfers, the last batch, usually a detach, has to land first
+impl Drop for Usb {
+    fn drop(&mut self) {
+        let _ = flush(&mut self.queue, &mut self.buffers);
+    }
+}
+
+impl Transport for Usb {
+    fn describe(&self) -> String {
+        format!("USB switch {}", self.device)
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        let interface = try_open_switch_interface(&self.device).ok_or_else(|| format!("{} is not plugged in", self.describe()))?;
+        // What was in flight on the previous interface is dropped with its queue
+        self.queue = interface.bulk_out_queue(usb_settings().endpoint);
+        self.interface = interface;
+        Ok(())
+    }
+
+    fn send_commands(&mut self, commands: &[Command]) -> SendOutcome {
+        let mut outcome = SendOutcome::default();
+        let transfers = self.transfers.encode(Framing::PREFIXED, commands);
+        outcome.bytes = transfers.bytes().len() as u64;
+        let mut clears = 0;
+        while let Err((submitted, err)) = write_packet(&mut self.queue, &mut self.buffers, transfers) {
+            outcome.failures += 1;
+            match err {
+                TransferError::Stall if clears >= MAX_CLEAR_HALTS => {
+                    warn!("USB endpoint keeps stalling, dropping the batch");
+                    break;
+                }
+                TransferError::Stall => {
+                    clears += 1;
+                    let _ = self.interface.clear_halt(usb_settings().endpoint);
+                    // What was submitted may have landed, sending it again would repeat presses and clicks
+                    if submitted > 0 {
+                        warn!("USB endpoint stalled partway through the batch, dropping the rest of it");
+                        break;
+                    }
+                }
+                err => {
+                    warn!("Lost USB connection to switch ({}), waiting for it to come back...", err);
+                    notify::send(notify::SWITCH_LOST, &format!("Lost the USB connection ({}), waiting for it to come back", err));
+                    match reopen_switch_interface(&self.device) {
+                        Ok(interface) => {
+                            self.queue = interface.bulk_out_queue(usb_settings().endpoint);
+                            self.interface = interface;
+                            info!("Reattached to switch!");
+                            notify::send(notify::SWITCH_BACK, "Reattached over USB");
+                            outcome.reconnects += 1;
+                        }
+                        Err(err) => {
+                            warn!("Unable to reattach to the switch ({}), giving up on this send", err);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        outcome
+    }
+
+    fn try_send_commands(&mut self, commands: &[Command]) -> Result<(), String> {
+        // Waits for the transfers, a failure has to show on this call
+        let transfers = self.transfers.encode(Framing::PREFIXED, commands);
+        write_packet(&mut self.queue, &mut self.buffers, transfers)
+            .map_err(|(_, err)| err)
+            .and_then(|_| flush(&mut self.queue, &mut self.buffers))
+            .map_err(|err| err.to_string())
+    }
+
+    fn runs_sequences(&self) -> bool {
+        true
+    }
+
+    fn request(&mut self, command: &Command) -> Result<Response, String> {
+        let kind = expected_reply(command)?;
+        self.try_send_commands(std::slice::from_ref(command))?;
+        let bytes = read_response(&self.interface).map_err(|err| err.to_string())?;
+        Response::from_raw(kind, bytes)
+    }
+}
+
+fn is_switch_device(device_info: &DeviceInfo) -> bool {
+    let settings = usb_settings();
+    device_info.vendor_id() == settings.vendor_id && device_info.product_id() == settings.product_id
+}
+
+pub fn list_switch_devices() -> Vec<DeviceInfo> {
+    nusb::list_devices()
+        .map(|devices| devices.filter(is_switch_device).collect())
+        .unwrap_or_default()
+}
+
+pub fn describe_device(device_info: &DeviceInfo) -> String {
+    format!(
+        "Bus {:03} Device {:03} (serial {})",
+        device_info.bus_number(),
+        device_info.device_address(),
+        device_info.serial_number().unwrap_or("unknown")
+    )
+}
+
+fn find_switch_device(device: &UsbDevice) -> Option<DeviceInfo> {
+    let mut devices = list_switch_devices().into_iter();
+    match device {
+        UsbDevice::INDEX(index) => devices.nth(*index),
+        UsbDevice::SERIAL(serial) => devices.find(|d| d.serial_number() == Some(serial.as_str())),
+    }
+}
+
+// The watch can only be waited on without a timeout, a thread does it and passes on the switches
+// plugged in. It ends with the next event once nobody is waiting anymore
+fn watch_switches() -> Result<Receiver<()>, Error> {
+    let mut watch = nusb::watch_devices().map_err(Error::WATCH)?;
+    let (sender, plugged) = mpsc::channel();
+    thread::spawn(move || {
+        while let Some(event) = block_on(watch.next()) {
+            if matches!(&event, HotplugEvent::Connected(device_info) if is_switch_device(device_info)) && sender.send(()).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(plugged)
+}
+
+fn get_switch_device_info(device: &UsbDevice) -> Result<DeviceInfo, Error> {
+    // Start watching before listing so a device plugged in between the two isn't missed
+    let plugged = watch_switches()?;
+
+    loop {
+        if let Some(device_info) = find_switch_device(device) {
+            return Ok(device_info);
+        }
+
+        info!("Waiting for a switch to be plugged in...");
+        loop {
+            if shutdown::requested() {
+                return Err(Error::USB(io::Error::new(io::ErrorKind::Interrupted, "stopped waiting for the switch")));
+            }
+            match plugged.recv_timeout(PLUG_POLL) {
+                Ok(()) => break,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Err(Error::WATCH(io::Error::other("the device watch ended"))),
+            }
+        }
+    }
+}
+
+fn get_device(device_info: DeviceInfo) -> Result<Device, Error> {
+    let device = device_info.open().map_err(Error::USB)?;
+    info!("Opened switch device!");
+    Ok(device)
+}
+
+fn try_open_switch_interface(device: &UsbDevice) -> Option<Interface> {
+    let device_info = find_switch_device(device)?;
+    let device = device_info.open().ok()?;
+    device.reset().ok()?;
+    device.claim_interface(usb_settings().interface).ok()
+}
+
+fn claim_switch_interface(device_info: DeviceInfo) -> Result<Interface, Error> {
+    let device = get_device(device_info)?;
+    device.reset().map_err(Error::USB)?;
+    device.claim_interface(usb_settings().interface).map_err(Error::USB)
+}
+
+fn open_switch_interface(device: &UsbDevice) -> Result<Interface, Error> {
+    claim_switch_interface(get_switch_device_info(device)?)
+}
+
+// A switch coming back re-enumerates, opening it can fail a few times before it settles. One
+// unplugged for good is given up on, the next send tries again
+fn reopen_switch_interface(device: &UsbDevice) -> Result<Interface, Error> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        shutdown::sleep(backoff);
+        if shutdown::requested() {
+            return Err(Error::USB(io::Error::new(io::ErrorKind::Interrupted, "stopping")));
+        }
+        let opened = find_switch_device(device)
+            .ok_or_else(|| Error::USB(io::Error::new(io::ErrorKind::NotFound, "the switch is not plugged in")))
+            .and_then(claim_switch_interface);
+        match opened {
+            Ok(interface) => return Ok(interface),
+            Err(err) if attempts >= MAX_RECONNECT_ATTEMPTS => return Err(err),
+            Err(err) => {
+                warn!("{}, retrying in {:?}...", err, backoff);
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+fn read_in(interface: &Interface, length: usize) -> Result<Vec<u8>, TransferError> {
+    let completion = block_on(interface.bulk_in(usb_settings().in_endpoint, RequestBuffer::new(length)));
+    completion.status.map(|_| completion.data)
+}
+
+// usb-botbase answers with a u32 LE size, then the payload which can take many transfers
+fn read_response(interface: &Interface) -> Result<Vec<u8>, TransferError> {
+    let header = read_in(interface, 4)?;
+    let size = u32::from_le_bytes(header.get(..4).and_then(|bytes| bytes.try_into().ok()).unwrap_or_default()) as usize;
+    let mut payload = Vec::with_capacity(size);
+    while payload.len() < size {
+        let chunk = read_in(interface, (size - payload.len()).min(MAX_READ_CHUNK))?;
+        if chunk.is_empty() {
+            break;
+        }
+        payload.extend(chunk);
+    }
+    Ok(payload)
+}
+
+// Waits for the oldest transfer in flight, nusb gives its buffer back for a later batch
+fn complete(queue: &mut Queue<Vec<u8>>, buffers: &mut Vec<Vec<u8>>) -> Result<(), TransferError> {
+    let completion = block_on(queue.next_complete());
+    buffers.push(completion.data.reuse());
+    completion.status
+}
+
+// Waits for everything in flight, the transfers after a failed one are cancelled
+fn flush(queue: &mut Queue<Vec<u8>>, buffers: &mut Vec<Vec<u8>>) -> Result<(), TransferError> {
+    let mut result = Ok(());
+    for _ in 0..queue.pending() {
+        if let Err(err) = complete(queue, buffers) {
+            queue.cancel_all();
+            result = result.and(Err(err));
+        }
+    }
+    result
+}
+
+// Only waits once max_in_flight transfers are pending, so the batches of a macro burst stream out
+// back to back. A failure can then show on a later batch, which is sent again after recovering.
+// A failure comes with how many transfers of this batch were already submitted
+fn write_packet(queue: &mut Queue<Vec<u8>>, buffers: &mut Vec<Vec<u8>>, transfers: &Transfers) -> Result<(), (usize, TransferError)> {
+    let max_in_flight = usb_settings().max_in_flight.max(1);
+    for (submitted, transfer) in transfers.iter().enumerate() {
+        while queue.pending() >= max_in_flight {
+            if let Err(err) = complete(queue, buffers) {
+                let _ = flush(queue, buffers);
+                return Err((submitted, err));
+            }
+        }
+        let mut buffer = buffers.pop().unwrap_or_default();
+        buffer.extend_from_slice(transfer);
+        queue.submit(buffer);
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use crate::command::Command;
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Before 2.0 sys-botbase parsed a single command per read, so bursts need a little spacing
+const BATCHED_READS: Version = Version(2, 0, 0);
+const LEGACY_COMMAND_SPACING: Duration = Duration::from_millis(2);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version(u32, u32, u32);
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().trim_start_matches('v').split('.').map(|part| part.parse::<u32>());
+        let mut next = || parts.next().transpose().map_err(|_| format!("Invalid version {}", s));
+        let major = next()?.ok_or_else(|| format!("Invalid version {}", s))?;
+        Ok(Version(major, next()?.unwrap_or(0), next()?.unwrap_or(0)))
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.0, self.1, self.2)
+    }
+}
+
+// Oldest sys-botbase release understanding each command that isn't available everywhere
+const FEATURES: [(&str, Version); 9] = [
+    ("clickSeq", Version(2, 0, 0)),
+    ("touch", Version(2, 0, 0)),
+    ("touchHold", Version(2, 0, 0)),
+    ("touchDraw", Version(2, 0, 0)),
+    ("key", Version(2, 0, 0)),
+    ("keyMulti", Version(2, 0, 0)),
+    ("pixelPeek", Version(1, 7, 0)),
+    ("screenOff", Version(2, 1, 0)),
+    ("screenOn", Version(2, 1, 0)),
+];
+
+// What a go-between answers getVersion with when the console behind it can't be asked
+pub fn newest() -> Version {
+    FEATURES.iter().map(|(_, since)| *since).max().unwrap_or(BATCHED_READS)
+}
+
+pub struct Negotiated {
+    version: Option<Version>,
+    warned: BTreeSet<&'static str>,
+}
+
+impl Negotiated {
+    pub fn new(reply: Option<&str>) -> Negotiated {
+        let version = reply.and_then(|reply| reply.parse().ok());
+        match version {
+            Some(version) => info!("Switch runs sys-botbase {}", version),
+            None => warn!("Unable to tell the sys-botbase version, assuming every command is supported"),
+        }
+        Negotiated { version, warned: BTreeSet::new() }
+    }
+
+    pub fn command_spacing(&self) -> Duration {
+        match self.version {
+            Some(version) if version < BATCHED_READS => LEGACY_COMMAND_SPACING,
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn understands(&self, name: &str) -> bool {
+        let since = FEATURES.iter().find(|(feature, _)| *feature == name).map(|(_, since)| *since);
+        match (self.version, since) {
+            (Some(version), Some(since)) => version >= since,
+            _ => true,
+        }
+    }
+
+    // Warns once per command the sysmodule is too old for
+    pub fn supports(&mut self, command: &Command) -> bool {
+        let Some(version) = self.version else {
+            return true;
+        };
+        match FEATURES.iter().find(|(feature, _)| *feature == command.name()) {
+            Some((feature, since)) if version < *since => {
+                if self.warned.insert(feature) {
+                    warn!("sys-botbase {} doesn't support {}, update it to {} or newer", version, feature, since);
+                }
+                false
+            }
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::capture::{self, Instruction};
+use crate::config::WatchSettings;
+use crate::memory::{self, Location, Region};
+use crate::router::Router;
+use crate::shutdown;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const IDLE: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    EQ,
+    NE,
+    LT,
+    LE,
+    GT,
+    GE,
+}
+
+// Compares the watched bytes read as a little endian unsigned integer, e.g. "<= 0x10"
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Condition {
+    operator: Operator,
+    value: u64,
+}
+
+impl Condition {
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        let mut value = [0; 8];
+        let length = bytes.len().min(8);
+        value[..length].copy_from_slice(&bytes[..length]);
+        let value = u64::from_le_bytes(value);
+        match self.operator {
+            Operator::EQ => value == self.value,
+            Operator::NE => value != self.value,
+            Operator::LT => value < self.value,
+            Operator::LE => value <= self.value,
+            Operator::GT => value > self.value,
+            Operator::GE => value >= self.value,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (operator, value) = [("==", Operator::EQ), ("!=", Operator::NE), ("<=", Operator::LE), (">=", Operator::GE), ("<", Operator::LT), (">", Operator::GT)]
+            .into_iter()
+            .find_map(|(symbol, operator)| s.strip_prefix(symbol).map(|value| (operator, value.trim())))
+            .ok_or_else(|| format!("Invalid condition {}, expected an operator like == or <= then a value", s))?;
+        let value = match value.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse(),
+        }
+        .map_err(|_| format!("Invalid value {} in condition", value))?;
+        Ok(Condition { operator, value })
+    }
+}
+
+impl TryFrom<String> for Condition {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let operator = match self.operator {
+            Operator::EQ => "==",
+            Operator::NE => "!=",
+            Operator::LT => "<",
+            Operator::LE => "<=",
+            Operator::GT => ">",
+            Operator::GE => ">=",
+        };
+        write!(f, "{} {}", operator, self.value)
+    }
+}
+
+struct Watch {
+    name: String,
+    settings: WatchSettings,
+    instructions: Vec<Instruction>,
+    last: Option<Vec<u8>>,
+    matched: bool,
+    due: Instant,
+}
+
+pub struct Watches {
+    watches: Vec<Watch>,
+    start: Instant,
+}
+
+impl Watches {
+    pub fn new(settings: BTreeMap<String, WatchSettings>) -> Watches {
+        let now = Instant::now();
+        let watches = settings.into_iter()
+            .map(|(name, settings)| {
+                // Loaded once so a broken macro shows now rather than when the condition matches
+                let instructions = match settings.play.as_deref().map(capture::load_macro) {
+                    Some(Ok(instructions)) => instructions,
+                    Some(Err(err)) => {
+                        warn!("{} won't play anything ({})", name, err);
+                        Vec::new()
+                    }
+                    None => Vec::new(),
+                };
+                Watch { name, settings, instructions, last: None, matched: false, due: now }
+            })
+            .collect();
+        Watches { watches, start: now }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    // Reads the watches that are due, a macro plays when its condition starts matching
+    pub fn poll(&mut self, router: &mut Router) {
+        let mut matching = Vec::new();
+        for (index, watch) in self.watches.iter_mut().enumerate() {
+            if watch.due > Instant::now() {
+                continue;
+            }
+            let settings = &watch.settings;
+            watch.due = Instant::now() + Duration::from_millis(settings.interval_ms);
+
+            let bytes = match memory::peek_at(router, Region::HEAP, &settings.location, settings.size) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    warn!("[{:>9.3}s] Unable to read {} ({})", self.start.elapsed().as_secs_f64(), watch.name, err);
+                    continue;
+                }
+            };
+            if watch.last.as_ref() != Some(&bytes) {
+                println!("[{:>9.3}s] {} = {}", self.start.elapsed().as_secs_f64(), watch.name, memory::describe(&bytes));
+            }
+
+            let matched = settings.when.is_some_and(|condition| condition.matches(&bytes));
+            if matched && !watch.matched {
+                if let Some(condition) = settings.when {
+                    println!("[{:>9.3}s] {} {}", self.start.elapsed().as_secs_f64(), watch.name, condition);
+                }
+                matching.push(index);
+            }
+            watch.matched = matched;
+            watch.last = Some(bytes);
+        }
+
+        for watch in matching.into_iter().map(|index| &self.watches[index]) {
+            if let Some(path) = watch.settings.play.as_ref().filter(|_| !watch.instructions.is_empty()) {
+                router.count_run(&format!("watch {}", path.display()));
+                capture::play(&watch.instructions, router);
+            }
+        }
+    }
+}
+
+pub fn run(router: &mut Router, watches: &mut Watches) {
+    if watches.is_empty() {
+        println!("Nothing to watch, add watches to the config or give a location");
+        return;
+    }
+    while !shutdown::requested() {
+        watches.poll(router);
+        router.heartbeat();
+        thread::sleep(IDLE);
+    }
+}
+
+// A watch given on the command line, with the same options as the ones of the config
+pub fn adhoc(location: Location, size: usize, interval_ms: u64, when: Option<Condition>, play: Option<PathBuf>) -> Watches {
+    let name = location.to_string();
+    Watches::new(BTreeMap::from([(name, WatchSettings { location, size, interval_ms, when, play })]))
+}
@@ -0,0 +1,246 @@
+use crate::discovery;
+use crate::error::Error;
+use crate::hex;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt::Display;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use str0m::change::{SdpAnswer, SdpOffer};
+use str0m::channel::ChannelId;
+use str0m::net::{Protocol, Receive};
+use str0m::{Candidate, Event, IceConnectionState, Input, Output, Rtc};
+use tracing::{info, warn};
+
+const STUN_SERVER: &str = "stun.l.google.com:19302";
+const STUN_MAGIC_COOKIE: u32 = 0x2112A442;
+const STUN_TIMEOUT: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+enum BridgeEvent {
+    CONNECTED(TcpStream),
+    DATA(Vec<u8>),
+    CLOSED,
+}
+
+// Plain RFC 5389 binding request, the XOR-MAPPED-ADDRESS answer is how the peer can reach us through NAT
+fn server_reflexive_address(socket: &UdpSocket) -> Option<SocketAddr> {
+    let server = STUN_SERVER.to_socket_addrs().ok()?.find(|address| address.is_ipv4())?;
+    let mut request = vec![0x00, 0x01, 0x00, 0x00];
+    request.extend_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+    let mut transaction = [0u8; 12];
+    SystemRandom::new().fill(&mut transaction).ok()?;
+    request.extend_from_slice(&transaction);
+    socket.send_to(&request, server).ok()?;
+
+    socket.set_read_timeout(Some(STUN_TIMEOUT)).ok()?;
+    let mut response = [0u8; 512];
+    let (size, _) = socket.recv_from(&mut response).ok()?;
+    let response = &response[..size];
+    if response.len() < 20 || response[8..20] != request[8..20] {
+        return None;
+    }
+
+    let mut attributes = &response[20..];
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let length = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let value = attributes.get(4..4 + length)?;
+        if kind == 0x0020 && length >= 8 && value[1] == 0x01 {
+            let port = u16::from_be_bytes([value[2], value[3]]) ^ (STUN_MAGIC_COOKIE >> 16) as u16;
+            let ip = u32::from_be_bytes([value[4], value[5], value[6], value[7]]) ^ STUN_MAGIC_COOKIE;
+            return Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(ip)), port));
+        }
+        attributes = attributes.get(4 + length.next_multiple_of(4)..).unwrap_or_default();
+    }
+    None
+}
+
+fn failed(what: &str, err: impl Display) -> Error {
+    Error::WEBRTC(format!("{}: {}", what, err))
+}
+
+fn new_peer() -> Result<(Rtc, UdpSocket), Error> {
+    let local_ip = discovery::get_local_ipv4().ok_or_else(|| Error::WEBRTC("no local IPv4 address".to_string()))?;
+    let socket = UdpSocket::bind((local_ip, 0)).map_err(|err| failed("unable to open a UDP socket", err))?;
+    let local_address = socket.local_addr().map_err(|err| failed("unable to open a UDP socket", err))?;
+
+    let mut rtc = Rtc::new();
+    rtc.add_local_candidate(Candidate::host(local_address, "udp").map_err(|err| failed("invalid host candidate", err))?);
+    match server_reflexive_address(&socket) {
+        Some(public_address) => match Candidate::server_reflexive(public_address, local_address, "udp") {
+            Ok(candidate) => {
+                info!("Reachable at {} through NAT", public_address);
+                rtc.add_local_candidate(candidate);
+            }
+            Err(err) => warn!("Invalid public candidate {} ({}), only peers on this network will connect", public_address, err),
+        },
+        None => warn!("Unable to reach the STUN server, only peers on this network will connect"),
+    }
+    Ok((rtc, socket))
+}
+
+// Session descriptions are multi-line, hex keeps them in one copy-pasteable line
+fn input_session_description(message: &str) -> Result<String, Error> {
+    let text = inquire::Text::new(message).prompt().map_err(|err| failed("no session description given", err))?;
+    hex::decode(text.trim())
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| Error::WEBRTC("invalid session description".to_string()))
+}
+
+fn pump(mut stream: TcpStream, sender: Sender<BridgeEvent>) {
+    let mut buf = [0u8; 1024];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(size) => {
+                if sender.send(BridgeEvent::DATA(buf[..size].to_vec())).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    let _ = sender.send(BridgeEvent::CLOSED);
+}
+
+fn attach(stream: TcpStream, sender: &Sender<BridgeEvent>) -> Option<TcpStream> {
+    let writer = stream.try_clone().ok()?;
+    let sender = sender.clone();
+    thread::spawn(move || pump(stream, sender));
+    Some(writer)
+}
+
+// Shuttles bytes between the data channel and a local TCP connection, so the relay
+// protocol (and its authentication) runs unchanged on top of the peer-to-peer link
+fn bridge(mut rtc: Rtc, socket: UdpSocket, connect_to: Option<SocketAddr>, sender: Sender<BridgeEvent>, events: Receiver<BridgeEvent>) -> Result<(), Error> {
+    let local_address = socket.local_addr().map_err(|err| failed("lost the UDP socket", err))?;
+    let mut channel: Option<ChannelId> = None;
+    let mut writer: Option<TcpStream> = None;
+    let mut pending: Vec<u8> = Vec::new();
+    let mut buf = vec![0u8; 2000];
+
+    loop {
+        let timeout = loop {
+            match rtc.poll_output() {
+                Ok(Output::Timeout(timeout)) => break timeout,
+                Ok(Output::Transmit(transmit)) => {
+                    let _ = socket.send_to(&transmit.contents, transmit.destination);
+                }
+                Ok(Output::Event(Event::ChannelOpen(id, _))) => {
+                    info!("Peer-to-peer link established");
+                    channel = Some(id);
+                    if let Some(address) = connect_to {
+                        match TcpStream::connect(address) {
+                            Ok(stream) => writer = attach(stream, &sender),
+                            Err(err) => warn!("Unable to reach the local relay ({})", err),
+                        }
+                    }
+                }
+                Ok(Output::Event(Event::ChannelData(data))) => {
+                    if let Some(stream) = &mut writer {
+                        if stream.write_all(&data.data).is_err() {
+                            writer = None;
+                        }
+                    }
+                }
+                Ok(Output::Event(Event::ChannelClose(_))) => {
+                    info!("Peer closed the WebRTC link");
+                    return Ok(());
+                }
+                Ok(Output::Event(Event::IceConnectionStateChange(IceConnectionState::Disconnected))) => {
+                    warn!("Lost the peer-to-peer link");
+                    return Ok(());
+                }
+                Ok(Output::Event(_)) => {}
+                Err(err) => return Err(failed("the connection failed", err)),
+            }
+        };
+
+        while let Ok(event) = events.try_recv() {
+            match event {
+                BridgeEvent::CONNECTED(stream) => writer = attach(stream, &sender),
+                BridgeEvent::DATA(data) => pending.extend(data),
+                BridgeEvent::CLOSED => writer = None,
+            }
+        }
+        if let Some(id) = channel {
+            if !pending.is_empty() {
+                if let Some(mut data_channel) = rtc.channel(id) {
+                    if data_channel.write(true, &pending).is_ok() {
+                        pending.clear();
+                    }
+                }
+            }
+        }
+
+        let wait = timeout.saturating_duration_since(Instant::now()).clamp(Duration::from_millis(1), POLL_INTERVAL);
+        socket.set_read_timeout(Some(wait)).map_err(|err| failed("lost the UDP socket", err))?;
+        let input = match socket.recv_from(&mut buf) {
+            Ok((size, source)) => match Receive::new(Protocol::Udp, source, local_address, &buf[..size]) {
+                Ok(receive) => Input::Receive(Instant::now(), receive),
+                Err(_) => continue,
+            },
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => Input::Timeout(Instant::now()),
+            Err(err) => return Err(failed("lost the UDP socket", err)),
+        };
+        rtc.handle_input(input).map_err(|err| failed("the connection failed", err))?;
+        if !rtc.is_alive() {
+            warn!("Lost the peer-to-peer link");
+            return Ok(());
+        }
+    }
+}
+
+// Returns a local address that behaves like the relay server on the other end
+pub fn join() -> Result<SocketAddr, Error> {
+    let (mut rtc, socket) = new_peer()?;
+    let mut changes = rtc.sdp_api();
+    changes.add_channel("relay".to_string());
+    let (offer, pending) = changes.apply().ok_or_else(|| Error::WEBRTC("unable to create an offer".to_string()))?;
+
+    println!("Send this offer to the relay host:\n{}", hex::encode(offer.to_sdp_string().as_bytes()));
+    let answer = SdpAnswer::from_sdp_string(&input_session_description("Paste the answer from the relay host:")?)
+        .map_err(|err| failed("invalid answer", err))?;
+    rtc.sdp_api().accept_answer(pending, answer).map_err(|err| failed("answer rejected", err))?;
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).map_err(|err| failed("unable to open the bridge", err))?;
+    let address = listener.local_addr().map_err(|err| failed("unable to open the bridge", err))?;
+    let (sender, events) = mpsc::channel();
+    let accepted = sender.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if accepted.send(BridgeEvent::CONNECTED(stream)).is_err() {
+                break;
+            }
+        }
+    });
+    thread::spawn(move || {
+        if let Err(err) = bridge(rtc, socket, None, sender, events) {
+            warn!("{}, closing the peer-to-peer link", err);
+        }
+    });
+    Ok(address)
+}
+
+pub fn serve(listen: SocketAddr) -> Result<(), Error> {
+    let (mut rtc, socket) = new_peer()?;
+    let offer = SdpOffer::from_sdp_string(&input_session_description("Paste the offer from the joining player:")?)
+        .map_err(|err| failed("invalid offer", err))?;
+    let answer = rtc.sdp_api().accept_offer(offer).map_err(|err| failed("offer rejected", err))?;
+    println!("Send this answer back to the joining player:\n{}", hex::encode(answer.to_sdp_string().as_bytes()));
+
+    let relay = if listen.ip().is_unspecified() {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), listen.port())
+    } else {
+        listen
+    };
+    let (sender, events) = mpsc::channel();
+    thread::spawn(move || {
+        if let Err(err) = bridge(rtc, socket, Some(relay), sender, events) {
+            warn!("{}, closing the peer-to-peer link", err);
+        }
+    });
+    Ok(())
+}